@@ -0,0 +1 @@
+alter table magnets drop column if exists metainfo;
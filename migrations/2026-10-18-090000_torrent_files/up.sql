@@ -0,0 +1 @@
+alter table magnets add column if not exists metainfo text;
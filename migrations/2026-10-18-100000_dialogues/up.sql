@@ -0,0 +1,8 @@
+create table if not exists dialogues
+(
+    chat_id    bigint not null
+        constraint dialogues_pkey
+            primary key,
+    state      text   not null,
+    updated_at timestamp with time zone not null default CURRENT_TIMESTAMP
+);
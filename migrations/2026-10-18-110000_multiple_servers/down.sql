@@ -0,0 +1,5 @@
+alter table magnets drop column server_id;
+alter table dirs drop column server_id;
+alter table servers drop column is_default;
+alter table servers drop column short_id;
+alter table servers drop column alias;
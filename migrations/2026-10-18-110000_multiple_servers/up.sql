@@ -0,0 +1,23 @@
+alter table servers add column if not exists alias varchar(100);
+update servers set alias = url where alias is null;
+alter table servers alter column alias set not null;
+
+-- short identifier to reference a server in callback data, which is limited to 64 bytes
+alter table servers add column if not exists short_id serial
+    constraint servers_short_id_key
+        unique;
+
+-- before this migration only one server per user was allowed
+alter table servers add column if not exists is_default boolean not null default false;
+update servers set is_default = true;
+
+alter table dirs add column if not exists server_id uuid
+    constraint dirs_server_id_fkey
+        references servers
+        on update restrict on delete cascade;
+
+-- the server chosen for a magnet before choosing a directory
+alter table magnets add column if not exists server_id uuid
+    constraint magnets_server_id_fkey
+        references servers
+        on update restrict on delete set null;
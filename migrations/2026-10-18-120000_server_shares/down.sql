@@ -0,0 +1 @@
+DROP table server_shares;
@@ -0,0 +1,13 @@
+create table if not exists server_shares
+(
+    id             uuid   not null default gen_random_uuid() primary key,
+    server_id      uuid   not null references servers
+        on update restrict
+        on delete cascade,
+    friend_user_id bigint not null references users
+        on update restrict
+        on delete restrict,
+    created_at     timestamp with time zone not null default CURRENT_TIMESTAMP,
+    constraint server_shares_server_friend_key
+        unique (server_id, friend_user_id)
+);
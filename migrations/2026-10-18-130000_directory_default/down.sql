@@ -0,0 +1,3 @@
+alter table users drop column last_dir_ordinal;
+alter table dirs drop column position;
+alter table dirs drop column is_default;
@@ -0,0 +1,5 @@
+alter table dirs add column if not exists is_default boolean not null default false;
+alter table dirs add column if not exists position int4 not null default 0;
+update dirs set position = ordinal;
+alter table users add column if not exists last_dir_ordinal int4 not null default 0;
+update users set last_dir_ordinal = coalesce((select max(ordinal) from dirs where dirs.user_id = users.id), 0);
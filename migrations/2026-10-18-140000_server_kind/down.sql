@@ -0,0 +1 @@
+alter table servers drop column kind;
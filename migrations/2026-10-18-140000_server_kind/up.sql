@@ -0,0 +1 @@
+alter table servers add column if not exists kind varchar not null default 'transmission';
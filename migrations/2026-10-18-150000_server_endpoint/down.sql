@@ -0,0 +1,3 @@
+alter table servers drop column ca_cert;
+alter table servers drop column tls;
+alter table servers drop column endpoint;
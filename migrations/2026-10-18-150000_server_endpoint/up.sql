@@ -0,0 +1,3 @@
+alter table servers add column if not exists endpoint varchar(255);
+alter table servers add column if not exists tls varchar not null default 'verify';
+alter table servers add column if not exists ca_cert text;
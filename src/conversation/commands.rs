@@ -1,6 +1,8 @@
+use std::sync::Arc;
+
 use crate::db::{
     models::user::NewUser,
-    repository::{get_user, save_user, Pool},
+    repo::Repository,
 };
 use crate::errors::BotError;
 use crate::core::crypto::random_salt;
@@ -8,7 +10,8 @@ use crate::core::crypto::random_salt;
 use crate::conversation::{
     friends::list_friends,
     directories::directories_commands,
-    servers::servers_commands,
+    servers::{list_servers, report_unreadable_credentials, servers_commands},
+    tasks::list_tasks,
 };
 use log::*;
 use teloxide::Bot;
@@ -39,6 +42,8 @@ pub enum Command {
     ListServers,
     #[command(description = "manage server sharing")]
     ServerSharing,
+    #[command(description = "list my downloads")]
+    Tasks,
 }
 
 pub mod settings_commands {
@@ -81,11 +86,11 @@ pub async fn help_command(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-pub async fn start_command(bot: Bot, pool: Pool, message: Message) -> HandlerResult {
+pub async fn start_command(bot: Bot, repo: Arc<dyn Repository>, message: Message) -> HandlerResult {
     debug!("Handle /start command");
     let m_clone = message.clone();
     debug!("Checking if user already exist");
-    match get_user(&pool, &(m_clone.from().unwrap().id.0 as i64)).await {
+    match repo.get_user(&(m_clone.from().unwrap().id.0 as i64)).await {
         Ok(result) => match result {
             Some( user ) => {
                 debug!("User was already registered");
@@ -105,7 +110,7 @@ pub async fn start_command(bot: Bot, pool: Pool, message: Message) -> HandlerRes
                             username: chat_user.username.clone(),
                             salt: random_salt(),
                         };
-                        match save_user(&pool, user).await {
+                        match repo.save_user(user).await {
                             Ok(_) => {
                                 bot.send_message(message.chat.id, format!("Welcome, {}", chat_user.first_name))
                                     .await?;
@@ -141,14 +146,26 @@ pub async fn add_friend_command(bot: Bot, dialogue: BotDialogue, msg: Message) -
     Ok(())
 }
 
-pub async fn list_friends_command(bot: Bot, pool: Pool, msg: Message) -> HandlerResult {
+pub async fn list_friends_command(bot: Bot, repo: Arc<dyn Repository>, msg: Message) -> HandlerResult {
     let user = msg.from().unwrap();
-    list_friends(&bot, &pool, &user.id.0, &msg.chat.id).await?;
+    list_friends(&bot, &*repo, &user.id.0, &msg.chat.id).await?;
     Ok(())
 }
 
-pub async fn share_server_command(bot: Bot, pool: Pool, msg: Message) -> HandlerResult {
+pub async fn list_servers_command(bot: Bot, repo: Arc<dyn Repository>, msg: Message) -> HandlerResult {
+    let user = msg.from().unwrap();
+    let result = list_servers(&bot, &*repo, &user.id.0, &msg.chat.id).await.map_err(Into::into);
+    report_unreadable_credentials(&bot, &*repo, &user.id.0, &msg.chat.id, result).await
+}
+
+pub async fn share_server_command(bot: Bot, repo: Arc<dyn Repository>, msg: Message) -> HandlerResult {
     let user = msg.from().unwrap();
-    share_server_management(&bot, &pool, &user.id.0, &msg.chat.id).await?;
+    share_server_management(&bot, &*repo, &user.id.0, &msg.chat.id).await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub async fn list_tasks_command(bot: Bot, repo: Arc<dyn Repository>, msg: Message) -> HandlerResult {
+    let user = msg.from().unwrap();
+    let result = list_tasks(&bot, &*repo, &user.id.0, &msg.chat.id).await.map_err(Into::into);
+    report_unreadable_credentials(&bot, &*repo, &user.id.0, &msg.chat.id, result).await
+}
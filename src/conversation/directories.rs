@@ -1,29 +1,50 @@
+use std::sync::Arc;
+
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use teloxide::Bot;
 use teloxide::prelude::*;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Message, ParseMode};
-use crate::conversation::commands::settings_commands::BACK_TO_SETTINGS;
+use crate::conversation::commands::settings_commands::{BACK_TO_SETTINGS, HIDE_MESSAGE};
 
+use crate::core::units::format_bytes;
 use crate::db::models::directories::DownloadDirectory;
-use crate::db::repository::{add_directory, delete_directories, get_directories, get_user, Pool};
+use crate::db::models::user::User;
+use crate::db::repo::Repository;
 use crate::errors::BotError;
-use crate::router::{HandlerResult, BotDialogue};
+use crate::router::{HandlerResult, BotDialogue, State};
 
 pub mod directories_commands {
     pub const LIST_DIRECTORIES: &str = "List Directories 📂";
     pub const ADD_DIRECTORY: &str = "Add Directory 📂+";
     pub const RESET_DIRECTORIES: &str = "Reset Directories 📂❌";
+    pub const RENAME_DIRECTORY: &str = "Rename ✏️";
+    pub const CHANGE_DIRECTORY_PATH: &str = "Change path 🛤";
+    pub const MOVE_DIRECTORY_UP: &str = "Move up ⬆️";
+    pub const MOVE_DIRECTORY_DOWN: &str = "Move down ⬇️";
+    pub const MAKE_DIRECTORY_DEFAULT: &str = "Make default ⭐";
+    pub const DELETE_DIRECTORY: &str = "Delete ❌";
 }
 
 pub async fn list_directories(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user_id: &u64,
     chat_id: &ChatId,
 ) -> Result<(), BotError> {
-    let user = &get_user(pool, &(*user_id as i64)).await?.unwrap();
-    let dirs: Vec<DownloadDirectory> = get_directories(pool, user).await?;
-    let keyboard = InlineKeyboardMarkup::new(
-        vec![
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let dirs: Vec<DownloadDirectory> = repo.get_directories(user).await?;
+    let mut keys = dirs.chunks(2)
+        .map(|chunk| chunk.iter()
+            .map(|dir| InlineKeyboardButton::callback(
+                format!("{}{}", if dir.is_default { "⭐ " } else { "" }, dir.alias),
+                format!("dir:{}", dir.ordinal),
+            ))
+            .collect::<Vec<InlineKeyboardButton>>())
+        .collect::<Vec<Vec<InlineKeyboardButton>>>();
+    keys.append(
+        &mut vec![
             vec![InlineKeyboardButton::callback(
                 directories_commands::ADD_DIRECTORY,
                 directories_commands::ADD_DIRECTORY,
@@ -35,6 +56,7 @@ pub async fn list_directories(
             vec![InlineKeyboardButton::callback(BACK_TO_SETTINGS, BACK_TO_SETTINGS)],
         ]
     );
+    let keyboard = InlineKeyboardMarkup::new(keys);
     match dirs.len() {
         0 => {
             bot.send_message(*chat_id, "There are no registered directories yet")
@@ -42,11 +64,24 @@ pub async fn list_directories(
                 .await?
         }
         _ => {
-            let text: String = dirs
-                .iter()
-                .map(|dir| format!("<b>{}</b>: {}", dir.alias, dir.path))
-                .collect::<Vec<String>>()
-                .join("\n");
+            let servers = repo.get_servers_by_user_id(user).await?;
+            let mut lines = vec![];
+            for dir in &dirs {
+                let dir_server = dir.server_id
+                    .and_then(|id| servers.iter().find(|server| server.id == id));
+                let server_label = dir_server
+                    .map(|server| format!(" [{}]", server.alias))
+                    .unwrap_or_default();
+                // directories of any server are measured on the default one
+                let free_space = match dir_server.or(servers.iter().find(|server| server.is_default)) {
+                    Some(server) => server.free_space(&dir.path).await
+                        .map(|size| format!(" — {} free", format_bytes(size)))
+                        .unwrap_or_default(),
+                    None => String::default(),
+                };
+                lines.push(format!("<b>{}</b>: {}{}{}", dir.alias, dir.path, server_label, free_space));
+            }
+            let text = lines.join("\n");
             bot.send_message(*chat_id, text)
                 .reply_markup(keyboard)
                 .parse_mode(ParseMode::Html)
@@ -59,19 +94,19 @@ pub async fn list_directories(
 pub async fn add_directory_prepare(bot: &Bot, chat_id: &ChatId) -> Result<(), BotError> {
     bot.send_message(
         *chat_id,
-        "<b>Adding directory</b>\nDirectory format is\n\nfirst line: <i>Directory alias</i>\nsecond line: <i>Directory path</i>",
+        "<b>Adding directory</b>\nDirectory format is\n\nfirst line: <i>Directory alias</i>\nsecond line: <i>Directory path</i>\nOptional third line: <i>Server alias, if the path exists only on that server</i>",
     ).parse_mode(ParseMode::Html).await?;
     Ok(())
 }
 
 pub async fn add_directory_dialogue(
     bot: Bot,
-    pool: Pool,
+    repo: Arc<dyn Repository>,
     dialogue: BotDialogue,
     message: Message,
 ) -> HandlerResult {
     let user_id = message.from().unwrap().id.0;
-    let user = get_user(&pool, &(user_id as i64)).await.unwrap().unwrap();
+    let user = repo.get_user(&(user_id as i64)).await?.unwrap();
     let keyboard = InlineKeyboardMarkup::new(
         vec![
             vec![InlineKeyboardButton::callback(
@@ -88,10 +123,26 @@ pub async fn add_directory_dialogue(
     let lines = text.lines().collect::<Vec<&str>>();
     let lines_count = lines.len();
     match lines_count {
-        2 => {
+        2 | 3 => {
             let alias = lines[0].to_string();
             let path = lines[1].to_owned();
-            add_directory(&pool, &user, &alias, &path).await.unwrap();
+            let server_id = match lines.get(2) {
+                Some(server_alias) => {
+                    let servers = repo.get_available_servers(&user).await?;
+                    match servers.iter()
+                        .filter(|server| server.user_id == user.id)
+                        .find(|server| server.alias.eq_ignore_ascii_case(server_alias.trim())) {
+                        Some(server) => Some(server.id),
+                        None => {
+                            bot.send_message(message.chat.id, format!("There is no server {}", server_alias))
+                                .await?;
+                            return Ok(());
+                        }
+                    }
+                }
+                None => None,
+            };
+            repo.add_directory(&user, &alias, &path, server_id).await?;
             bot.send_message(message.chat.id, "Done!")
                 .reply_markup(keyboard)
                 .await?;
@@ -111,12 +162,280 @@ pub async fn add_directory_dialogue(
 
 pub async fn reset_directories(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user_id: &u64,
     chat_id: &ChatId,
 ) -> Result<(), BotError> {
-    let user = get_user(pool, &(*user_id as i64)).await?.unwrap();
-    delete_directories(pool, user).await?;
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    repo.delete_directories(&user).await?;
     bot.send_message(*chat_id, "Done!").await?;
     Ok(())
 }
+
+/// Ordinal of the directory from `prefix:ordinal` callback data
+fn parse_ordinal(data: &str) -> Option<i32> {
+    data.split(':').nth(1).and_then(|ordinal| ordinal.parse::<i32>().ok())
+}
+
+async fn find_directory(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user: &User,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<Option<DownloadDirectory>, BotError> {
+    let dir = match parse_ordinal(data) {
+        Some(ordinal) => repo.get_directory(user, ordinal).await?,
+        None => None,
+    };
+    if dir.is_none() {
+        bot.send_message(*chat_id, "Directory was not found").await?;
+    }
+    Ok(dir)
+}
+
+/// `dir:ordinal` shows what can be done with the directory
+pub async fn directory_menu(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let dir = match find_directory(bot, repo, user, chat_id, data).await? {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let ordinal = dir.ordinal;
+    let mut keys = vec![
+        vec![
+            InlineKeyboardButton::callback(directories_commands::RENAME_DIRECTORY, format!("dir_alias:{}", ordinal)),
+            InlineKeyboardButton::callback(directories_commands::CHANGE_DIRECTORY_PATH, format!("dir_path:{}", ordinal)),
+        ],
+        vec![
+            InlineKeyboardButton::callback(directories_commands::MOVE_DIRECTORY_UP, format!("dir_up:{}", ordinal)),
+            InlineKeyboardButton::callback(directories_commands::MOVE_DIRECTORY_DOWN, format!("dir_down:{}", ordinal)),
+        ],
+    ];
+    if !dir.is_default {
+        keys.push(vec![InlineKeyboardButton::callback(
+            directories_commands::MAKE_DIRECTORY_DEFAULT,
+            format!("dir_default:{}", ordinal),
+        )]);
+    }
+    keys.push(vec![InlineKeyboardButton::callback(directories_commands::DELETE_DIRECTORY, format!("dir_del:{}", ordinal))]);
+    keys.push(vec![InlineKeyboardButton::callback(
+        directories_commands::LIST_DIRECTORIES,
+        directories_commands::LIST_DIRECTORIES,
+    )]);
+    bot.send_message(*chat_id, format!("{}<b>{}</b>: {}", if dir.is_default { "⭐ " } else { "" }, dir.alias, dir.path))
+        .reply_markup(InlineKeyboardMarkup::new(keys))
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// `dir_alias:ordinal` and `dir_path:ordinal` ask for the new value
+pub async fn edit_directory_prepare(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+    dialogue: &BotDialogue,
+) -> HandlerResult {
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let dir = match find_directory(bot, repo, user, chat_id, data).await? {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    if data.starts_with("dir_alias:") {
+        bot.send_message(*chat_id, format!("Send me a new alias for <b>{}</b>", dir.alias))
+            .parse_mode(ParseMode::Html)
+            .await?;
+        dialogue.update(State::RenameDirectory { ordinal: dir.ordinal }).await?;
+    } else {
+        bot.send_message(*chat_id, format!("Send me a new path for <b>{}</b>\nThe current one is {}", dir.alias, dir.path))
+            .parse_mode(ParseMode::Html)
+            .await?;
+        dialogue.update(State::ChangeDirectoryPath { ordinal: dir.ordinal }).await?;
+    }
+    Ok(())
+}
+
+pub async fn rename_directory_dialogue(
+    bot: Bot,
+    repo: Arc<dyn Repository>,
+    dialogue: BotDialogue,
+    ordinal: i32,
+    message: Message,
+) -> HandlerResult {
+    let user = repo.get_user(&(message.from().unwrap().id.0 as i64)).await?.unwrap();
+    match message.text().map(str::trim) {
+        Some(alias) if !alias.is_empty() && !alias.contains('\n') => {
+            repo.rename_directory(&user, ordinal, alias).await?;
+            bot.send_message(message.chat.id, "Done!")
+                .reply_markup(back_to_directories_button())
+                .await?;
+            dialogue.exit().await?;
+        }
+        _ => {
+            bot.send_message(message.chat.id, "Please send the alias as a single line").await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn change_directory_path_dialogue(
+    bot: Bot,
+    repo: Arc<dyn Repository>,
+    dialogue: BotDialogue,
+    ordinal: i32,
+    message: Message,
+) -> HandlerResult {
+    let user = repo.get_user(&(message.from().unwrap().id.0 as i64)).await?.unwrap();
+    match message.text().map(str::trim) {
+        Some(path) if !path.is_empty() && !path.contains('\n') => {
+            repo.set_directory_path(&user, ordinal, path).await?;
+            bot.send_message(message.chat.id, "Done!")
+                .reply_markup(back_to_directories_button())
+                .await?;
+            dialogue.exit().await?;
+        }
+        _ => {
+            bot.send_message(message.chat.id, "Please send the path as a single line").await?;
+        }
+    }
+    Ok(())
+}
+
+/// `dir_up:ordinal` and `dir_down:ordinal` swap the directory with its neighbour
+pub async fn move_directory(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let ordinal = match parse_ordinal(data) {
+        Some(ordinal) => ordinal,
+        None => return Ok(()),
+    };
+    let dirs = repo.get_directories(user).await?;
+    let position = match dirs.iter().position(|dir| dir.ordinal == ordinal) {
+        Some(position) => position,
+        None => {
+            bot.send_message(*chat_id, "Directory was not found").await?;
+            return Ok(());
+        }
+    };
+    let neighbour = if data.starts_with("dir_up:") {
+        position.checked_sub(1).and_then(|position| dirs.get(position))
+    } else {
+        dirs.get(position + 1)
+    };
+    if let Some(neighbour) = neighbour {
+        repo.swap_directories(user, ordinal, neighbour.ordinal).await?;
+    }
+    list_directories(bot, repo, user_id, chat_id).await
+}
+
+/// `dir_default:ordinal`
+pub async fn make_directory_default(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    if let Some(dir) = find_directory(bot, repo, user, chat_id, data).await? {
+        repo.set_default_directory(user, dir.ordinal).await?;
+        list_directories(bot, repo, user_id, chat_id).await?;
+    }
+    Ok(())
+}
+
+/// `dir_del:ordinal` asks for a confirmation
+pub async fn delete_directory_callback(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let dir = match find_directory(bot, repo, user, chat_id, data).await? {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let mut rng = StdRng::from_entropy();
+    let mut buttons = vec![
+        vec![InlineKeyboardButton::callback("Yes", format!("confirm_{}", data))],
+        vec![InlineKeyboardButton::callback("No", "-")],
+        vec![InlineKeyboardButton::callback("Also No", "-")]
+    ];
+    buttons.shuffle(&mut rng);
+    bot.send_message(*chat_id, format!("Are you sure you want to delete {}", dir.alias))
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+/// `confirm_dir_del:ordinal`
+pub async fn confirm_delete_directory_callback(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let dir = match find_directory(bot, repo, &user, chat_id, data).await? {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    repo.delete_directory(&user, dir.ordinal).await?;
+    bot.send_message(*chat_id, format!("{} was deleted", dir.alias))
+        .reply_markup(back_to_directories_button())
+        .await?;
+    Ok(())
+}
+
+fn back_to_directories_button() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![InlineKeyboardButton::callback(
+            directories_commands::LIST_DIRECTORIES,
+            directories_commands::LIST_DIRECTORIES,
+        )],
+        vec![InlineKeyboardButton::callback(HIDE_MESSAGE, HIDE_MESSAGE)],
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::conversation::test_bot::{fake_bot, user};
+    use crate::db::memory::InMemoryRepository;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_move_directory() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        repo.add_directory(&user, "Music", "/music", None).await.unwrap();
+
+        move_directory(&bot, &repo, &1, &ChatId(1), "dir_up:2").await.unwrap();
+
+        assert_eq!(&requests.last_buttons()[..2], ["dir:2", "dir:1"]);
+        // the choices which were already sent still point to the same directories
+        assert_eq!(repo.get_directory(&user, 1).await.unwrap().unwrap().alias, "Movies");
+
+        move_directory(&bot, &repo, &1, &ChatId(1), "dir_up:2").await.unwrap();
+        assert_eq!(&requests.last_buttons()[..2], ["dir:2", "dir:1"]);
+    }
+}
@@ -7,16 +7,16 @@ use teloxide::prelude::*;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 use crate::conversation::commands::settings_commands::HIDE_MESSAGE;
 
-use crate::db::repository::{delete_friend, get_friends, get_user, Pool};
+use crate::db::repo::Repository;
 use crate::errors::BotError;
 
 pub async fn list_friends(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user_id: &u64,
     chat_id: &ChatId,
 ) -> Result<(), BotError> {
-    let friends = get_friends(pool, user_id).await.unwrap();
+    let friends = repo.get_friends(user_id).await?;
     if friends.is_empty() {
         bot.send_message(*chat_id, "You don't have any friends now 😢. Try adding one with /add_friend command").await?;
     } else {
@@ -36,9 +36,9 @@ pub async fn list_friends(
     Ok(())
 }
 
-pub async fn manage_friend_callback(bot: &Bot, pool: &Pool, data: &str, message: &Message) -> Result<(), BotError> {
+pub async fn manage_friend_callback(bot: &Bot, repo: &dyn Repository, data: &str, message: &Message) -> Result<(), BotError> {
     let friend_id = data.split(":").last().unwrap().parse::<i64>().unwrap();
-    let user = get_user(pool, &friend_id).await?;
+    let user = repo.get_user(&friend_id).await?;
     match user {
         Some(u) => {
             let kb = InlineKeyboardMarkup::new(vec![
@@ -56,9 +56,9 @@ pub async fn manage_friend_callback(bot: &Bot, pool: &Pool, data: &str, message:
     Ok(())
 }
 
-pub async fn unfriend_callback(bot: &Bot, pool: &Pool, data: &str, message: &Message) -> Result<(), BotError> {
+pub async fn unfriend_callback(bot: &Bot, repo: &dyn Repository, data: &str, message: &Message) -> Result<(), BotError> {
     let friend_id = data.split(":").last().unwrap().parse::<i64>().unwrap();
-    let friend =  get_user(pool, &friend_id).await?;
+    let friend =  repo.get_user(&friend_id).await?;
     if friend.is_none() {
         bot.send_message(message.chat.id, "I don't know this person anymore").await?;
     }
@@ -77,22 +77,23 @@ pub async fn unfriend_callback(bot: &Bot, pool: &Pool, data: &str, message: &Mes
     Ok(())
 }
 
-pub async fn confirm_unfriend_callback(bot: &Bot, pool: &Pool, user_id: &u64, data: &str, message: &Message) -> Result<(), BotError> {
+pub async fn confirm_unfriend_callback(bot: &Bot, repo: &dyn Repository, user_id: &u64, data: &str, message: &Message) -> Result<(), BotError> {
     let friend_id = data.split(":").last().unwrap().parse::<i64>().unwrap();
     let i_user_id = *user_id as i64;
-    let user = match get_user(pool, &i_user_id).await? {
+    let user = match repo.get_user(&i_user_id).await? {
         Some(it) => it,
         None => return Ok(()),
     };
-    let friend = match get_user(pool, &friend_id).await? {
+    let friend = match repo.get_user(&friend_id).await? {
         Some(it) => it,
         None => {
             bot.send_message(message.chat.id, "I don't know this person anymore").await?;
             return Ok(());
         }
     };
-    delete_friend(pool, &i_user_id, &friend_id).await?;
-    delete_friend(pool, &friend_id, &i_user_id).await?;
+    repo.delete_friend(&i_user_id, &friend_id).await?;
+    repo.delete_friend(&friend_id, &i_user_id).await?;
+    repo.delete_shares_between(&i_user_id, &friend_id).await?;
     bot.send_message(message.chat.id, format!("You and {} are no longer friends", friend.username.clone().unwrap())).await?;
     bot.send_message(friend, format!("You and {} are no longer friends", user.username.unwrap())).await?;
     Ok(())
@@ -1,31 +1,47 @@
 use std::env;
+use std::sync::Arc;
 use log::{debug, info, warn};
 use teloxide::net::Download;
 use teloxide::prelude::*;
 use teloxide::types::{ForwardedFrom, Me, True};
-use crate::conversation::tasks::process_magnet;
+use crate::conversation::servers::report_unreadable_credentials;
+use crate::conversation::tasks::{process_magnet, process_torrent_file};
 use crate::core::{
     flaresolver::Flaresolver,
-    rutracker::find_magnet
+    trackers::{find_scraper, get_page_html, get_torrent_file, TrackerLink, TrackerScraper, MAX_TORRENT_FILE_SIZE},
 };
-use crate::db::repository::{add_friend, find_friend, get_user, Pool};
+use crate::db::repo::Repository;
+use crate::metrics;
 use crate::router::HandlerResult;
-use futures::stream::StreamExt; // for .next()
 
 pub async fn process_message(
     bot: Bot,
-    pool: Pool,
+    repo: Arc<dyn Repository>,
     message: Message,
 ) -> HandlerResult {
+    let _timer = metrics::handler_timer("message");
+    let result = route_message(&bot, &*repo, &message).await;
+    match message.from() {
+        Some(user) => report_unreadable_credentials(&bot, &*repo, &user.id.0, &message.chat.id, result).await,
+        None => result,
+    }
+}
+
+async fn route_message(bot: &Bot, repo: &dyn Repository, message: &Message) -> HandlerResult {
     let document = message.document().map(ToOwned::to_owned);
     if let Some(document) = document {
         match document.file_name {
             Some(s) if s.ends_with(".torrent") => {
-                bot.send_message(message.chat.id, format!("You've sent {} file and I will support it soon", s)).await?;
+                if document.file.size as usize > MAX_TORRENT_FILE_SIZE {
+                    warn!("Torrent file {} of {} bytes is too big", s, document.file.size);
+                    bot.send_message(message.chat.id, "This torrent file is too big, I can't handle it :(").await?;
+                    return Ok(());
+                }
                 let file = bot.get_file(document.file.id).await?;
-                let data = bot.download_file_stream(&file.path).next().await.unwrap()?;
-                // let content = String::from_utf8(data.to_vec())?;
-                bot.send_message(message.chat.id, format!("File  of {} bytes received", data.len())).await?;
+                let mut data: Vec<u8> = Vec::with_capacity(file.size as usize);
+                bot.download_file(&file.path, &mut data).await?;
+                debug!("Torrent file {} of {} bytes received", s, data.len());
+                try_to_process_torrent_file(bot, repo, message, &data).await?;
             }
             Some(s) => {
                 bot.send_message(message.chat.id, format!("You've sent {} file, but I don't support it", s)).await?;
@@ -35,49 +51,62 @@ pub async fn process_message(
         return Ok(());
     };
 
-    match message.text().map(ToOwned::to_owned) {
-        Some(s) if s.contains("magnet:") => try_to_process_magnet(&bot, &pool, &message, &s).await?,
-        Some(s) if s.starts_with("https://rutracker.org/forum/viewtopic.php?t=") => {
-            try_to_process_rutracker_link(&bot, &pool, &message, &s).await?
-        }
-        _ => {
-            bot.send_message(message.chat.id, "I don't know what you mean").await?;
-        }
-    };
+    let text = message.text().unwrap_or_default().to_owned();
+    if text.contains("magnet:") {
+        try_to_process_magnet(bot, repo, message, &text).await?
+    } else if let Some(scraper) = find_scraper(text.trim()) {
+        try_to_process_tracker_link(bot, repo, message, text.trim(), scraper).await?
+    } else {
+        bot.send_message(message.chat.id, "I don't know what you mean").await?;
+    }
 
     Ok(())
 }
 
-async fn try_to_process_rutracker_link(
+async fn try_to_process_tracker_link(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     message: &Message,
-    data: &String,
+    url: &str,
+    scraper: Box<dyn TrackerScraper>,
 ) -> HandlerResult {
-    let url = data.to_lowercase();
-    let solver_url = match env::var("FLARESOLVER_URL") {
-        Ok(v) => v,
-        Err(_) => {
-            bot.send_message(
-                message.chat.id,
-                "FLARESOLVER_URL is not set, can't parse rutracker links",
-            ).await?;
-            return Ok(());
+    let solver = if scraper.needs_flaresolver() {
+        match env::var("FLARESOLVER_URL") {
+            Ok(v) => Some(Flaresolver::new(v)),
+            Err(_) => {
+                bot.send_message(
+                    message.chat.id,
+                    format!("FLARESOLVER_URL is not set, can't parse {} links", scraper.name()),
+                ).await?;
+                return Ok(());
+            }
         }
+    } else {
+        None
     };
-    let solver = Flaresolver::new(solver_url.to_string());
-    info!("Fetching '{}'", &url);
-    match solver.get_page_html(url)
+    info!("Fetching '{}'", url);
+    match get_page_html(url, solver.as_ref())
         .await
         .map(|html|
-            html.and_then(|text| find_magnet(text))
+            html.and_then(|text| scraper.extract(url, &text))
         ) {
-        Ok(optional_magnet) => {
+        Ok(optional_link) => {
             info!("Fetched successfully");
-            match optional_magnet {
-                Some(magnet_link) => {
-                    try_to_process_magnet(&bot, pool, message, &magnet_link).await
+            match optional_link {
+                Some(TrackerLink::Magnet(magnet_link)) => {
+                    try_to_process_magnet(bot, repo, message, &magnet_link).await
                 }
+                Some(TrackerLink::TorrentFile(file_url)) => match get_torrent_file(&file_url).await {
+                    Ok(data) => try_to_process_torrent_file(bot, repo, message, &data).await,
+                    Err(err) => {
+                        warn!("Failed to download {}: {}", &file_url, err);
+                        bot.send_message(
+                            message.chat.id,
+                            "Couldn't download the torrent file. Try to send it manually",
+                        ).await?;
+                        Ok(())
+                    }
+                },
                 _ => {
                     bot.send_message(
                         message.chat.id,
@@ -100,12 +129,12 @@ async fn try_to_process_rutracker_link(
 
 async fn try_to_process_magnet(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     message: &Message,
     link: &String,
 ) -> HandlerResult {
     debug!("Processing a magnet link: {}", link);
-    match process_magnet(bot, pool, message, link).await {
+    match process_magnet(bot, repo, message, link).await {
         Ok(_) => {
             debug!("Processing of a magnet link passed. Deleting the original message");
             bot.delete_message(message.chat.id, message.id).await?
@@ -121,13 +150,32 @@ async fn try_to_process_magnet(
     Ok(())
 }
 
+async fn try_to_process_torrent_file(
+    bot: &Bot,
+    repo: &dyn Repository,
+    message: &Message,
+    data: &[u8],
+) -> HandlerResult {
+    match process_torrent_file(bot, repo, message, data).await {
+        Ok(_) => {
+            debug!("Processing of a torrent file passed. Deleting the original message");
+            bot.delete_message(message.chat.id, message.id).await?
+        }
+        Err(err) => {
+            warn!("Processing of a torrent file failed! {}", err);
+            True
+        }
+    };
+    Ok(())
+}
+
 pub async fn invalid_state(bot: Bot, msg: Message) -> HandlerResult {
     bot.send_message(msg.chat.id, "I don't know what you mean")
         .await?;
     Ok(())
 }
 
-pub async fn add_friend_dialogue(bot: Bot, pool: Pool, msg: Message, me: Me) -> HandlerResult {
+pub async fn add_friend_dialogue(bot: Bot, repo: Arc<dyn Repository>, msg: Message, me: Me) -> HandlerResult {
     let forwarded_from = match msg.forward_from() {
         None => {
             bot.send_message(msg.chat.id, "Please forward me a friends message to you from any other chat with your friend").await?;
@@ -159,7 +207,7 @@ pub async fn add_friend_dialogue(bot: Bot, pool: Pool, msg: Message, me: Me) ->
     }
 
     let friend_user_id = forwarded_user.id.0 as i64;
-    let db_user = get_user(&pool, &friend_user_id).await?;
+    let db_user = repo.get_user(&friend_user_id).await?;
     if db_user.is_none() {
         bot.send_message(msg.chat.id, "I don't know this user. Please ask your friend to start a conversation with me first").await?;
         return Ok(());
@@ -167,17 +215,60 @@ pub async fn add_friend_dialogue(bot: Bot, pool: Pool, msg: Message, me: Me) ->
 
     let forwarded_user_username = forwarded_user.username.clone().unwrap();
     let requester_user_id = from.id.0 as i64;
-    let friend_user = find_friend(&pool, &requester_user_id, &friend_user_id).await?;
+    let friend_user = repo.find_friend(&requester_user_id, &friend_user_id).await?;
     if friend_user.is_some() {
         bot.send_message(msg.chat.id, format!("You are already friends with {}", forwarded_user_username)).await?;
         return Ok(());
     }
 
-    add_friend(&pool, &requester_user_id, &friend_user_id).await?;
-    add_friend(&pool, &friend_user_id, &requester_user_id).await?;
+    repo.add_friend(&requester_user_id, &friend_user_id).await?;
+    repo.add_friend(&friend_user_id, &requester_user_id).await?;
     bot.send_message(msg.chat.id, format!("You are now friends with {} 🎉", forwarded_user_username)).await?;
     let befriended_user = db_user.unwrap();
     bot.send_message(befriended_user, format!("{} added you as a friend! 🎉\n\nNow potentially you can use their shared servers.\nYou can find those with the command /listservers", from_user_username)).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use crate::conversation::test_bot::{document, fake_bot, user};
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::server::NewServer;
+
+    use super::*;
+
+    const TORRENT: &[u8] = b"d4:infod6:lengthi1048576e4:name8:test.iso12:piece lengthi262144e\
+6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+
+    #[tokio::test]
+    async fn test_torrent_file_upload() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = NewServer::new(1, "http://127.0.0.1:1".to_owned(), "Home".to_owned(), None);
+        repo.add_server(&user, &server).await.unwrap();
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        requests.add_file("torrent", TORRENT);
+
+        route_message(&bot, &repo, &document(1, "test.torrent", "torrent", TORRENT.len())).await.unwrap();
+
+        let magnets = repo.magnets();
+        assert_eq!(magnets.len(), 1);
+        assert!(magnets[0].metainfo.is_some());
+        assert!(requests.texts()[0].ends_with("Choose directory to download"));
+    }
+
+    #[tokio::test]
+    async fn test_too_big_torrent_file() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        user(&repo, 1).await;
+
+        let message = document(1, "big.torrent", "big", MAX_TORRENT_FILE_SIZE + 1);
+        route_message(&bot, &repo, &message).await.unwrap();
+
+        assert!(repo.magnets().is_empty());
+        assert_eq!(requests.methods(), vec!["SendMessage"]);
+        assert_eq!(requests.texts(), vec!["This torrent file is too big, I can't handle it :("]);
+    }
+}
@@ -2,6 +2,9 @@ pub(crate) mod directories;
 pub(crate) mod commands;
 pub(crate) mod servers;
 pub(crate) mod tasks;
+pub(crate) mod task_files;
 pub(crate) mod messages;
 pub(crate) mod friends;
-mod shared_server;
+pub(crate) mod shared_server;
+#[cfg(test)]
+pub(crate) mod test_bot;
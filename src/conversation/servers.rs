@@ -1,22 +1,29 @@
+use std::sync::Arc;
+
+use log::*;
 use teloxide::Bot;
 use teloxide::prelude::*;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Message, ParseMode};
-use transmission_rpc::{TransClient, types::BasicAuth};
-use crate::conversation::commands::settings_commands::BACK_TO_SETTINGS;
+use transmission_rpc::types::SessionSetArgs;
+use crate::conversation::commands::settings_commands::{BACK_TO_SETTINGS, HIDE_MESSAGE};
 
+use crate::core::backend::{self, TorrentBackend};
 use crate::core::trans_url::TransUrl;
+use crate::core::units::format_bytes;
 use crate::db::{
     models::{
-        server::{Authentication, NewServer, Server},
+        server::{Authentication, NewServer, Server, ServerEndpoint, TlsOptions},
         user::User,
     },
-    repository::{
-        add_server, add_server_auth, delete_servers, get_servers_by_user_id, get_user,
-        Pool, tasks_count_by_server_id,
-    },
+    repo::Repository,
 };
-use crate::errors::BotError;
-use crate::router::{BotDialogue, HandlerResult};
+use crate::errors::{BotError, DbError};
+use crate::router::{BotDialogue, HandlerResult, State};
+
+const SELF_SIGNED: &str = "self-signed";
+const TLS_HINT: &str = "<i>Optional for https: self-signed to accept any certificate, or the PEM of the certificate authority to trust</i>";
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
 
 pub mod servers_commands {
     pub const SERVER_STATS: &str = "Server stats 👀";
@@ -26,209 +33,754 @@ pub mod servers_commands {
 
 pub async fn show_stats(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user_id: &u64,
     chat_id: &ChatId,
 ) -> Result<(), BotError> {
-    let keyboard = InlineKeyboardMarkup::new(
-        vec![
-            vec![InlineKeyboardButton::callback(
-                servers_commands::REGISTER_SERVER,
-                servers_commands::REGISTER_SERVER,
-            )],
-            vec![InlineKeyboardButton::callback(
-                servers_commands::RESET_SERVERS,
-                servers_commands::RESET_SERVERS,
-            )],
-            vec![InlineKeyboardButton::callback(
-                BACK_TO_SETTINGS,
-                BACK_TO_SETTINGS,
-            )],
-        ]
-    );
-    let user = get_user(pool, &(*user_id as i64)).await?.unwrap();
-    let servers: Vec<Server> = get_servers_by_user_id(pool, &user).await?;
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let servers: Vec<Server> = repo.get_servers_by_user_id(&user).await?;
     let mut stat_lines = vec!["Downloads for server:".to_string()];
-    // for now it is just one
-    match servers.get(0) {
-        Some(server) => {
-            let tasks = tasks_count_by_server_id(&pool, &server.id).await?;
-            stat_lines.push(format!(
-                "<b>{}</b>: <i>{}</i>",
-                server.url().get_base_url(),
-                tasks
+    let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![];
+    for server in &servers {
+        let tasks = repo.tasks_count_by_server_id(&server.id).await?;
+        let status = match server.to_backend().version().await {
+            Ok(_) => "👍",
+            Err(_) => "👎",
+        };
+        stat_lines.push(format!(
+            "{}<b>{}</b> {}: <i>{}</i> {}",
+            if server.is_default { "⭐ " } else { "" },
+            server.alias,
+            server.url().get_base_url(),
+            tasks,
+            status
+        ));
+        let mut row = vec![
+            InlineKeyboardButton::callback(
+                format!("{} 📊", server.alias),
+                format!("srv_dash:{}", server.short_id),
+            ),
+            InlineKeyboardButton::callback(
+                format!("Edit {} ✏️", server.alias),
+                format!("srv_edit:{}", server.short_id),
+            ),
+        ];
+        if !server.is_default {
+            row.push(InlineKeyboardButton::callback(
+                format!("Make {} default ⭐", server.alias),
+                format!("srv_default:{}", server.short_id),
             ));
-            let client = &mut server.to_client();
-            let status = match client.session_get().await {
-                Ok(_) => "👍",
-                Err(_) => "👎",
-            };
-            stat_lines.push(format!("Server status: {}", status))
         }
-        _ => stat_lines.push("Nothing yet :(".to_string()),
+        keys.push(row);
+    }
+    if servers.is_empty() {
+        stat_lines.push("Nothing yet :(".to_string());
     }
+    keys.push(vec![InlineKeyboardButton::callback(
+        servers_commands::REGISTER_SERVER,
+        servers_commands::REGISTER_SERVER,
+    )]);
+    keys.push(vec![InlineKeyboardButton::callback(
+        servers_commands::RESET_SERVERS,
+        servers_commands::RESET_SERVERS,
+    )]);
+    keys.push(vec![InlineKeyboardButton::callback(
+        BACK_TO_SETTINGS,
+        BACK_TO_SETTINGS,
+    )]);
     let text = stat_lines.join("\n");
     bot.send_message(*chat_id, text)
-        .reply_markup(keyboard)
+        .reply_markup(InlineKeyboardMarkup::new(keys))
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// Own and shared servers with their availability and transmission version
+pub async fn list_servers(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let own_servers = repo.get_servers_by_user_id(&user).await?;
+    let shared_servers = repo.get_shared_servers(&user).await?;
+    if own_servers.is_empty() && shared_servers.is_empty() {
+        bot.send_message(*chat_id, "You don't have any servers yet. Register one in /settings")
+            .await?;
+        return Ok(());
+    }
+    let mut lines = vec![];
+    if !own_servers.is_empty() {
+        lines.push("<b>My servers:</b>".to_string());
+        for server in &own_servers {
+            lines.push(server_line(repo, server, None).await?);
+        }
+    }
+    if !shared_servers.is_empty() {
+        lines.push("<b>Shared with me:</b>".to_string());
+        for server in &shared_servers {
+            let owner = repo.get_user(&server.user_id).await?
+                .map(|owner| owner.display_name())
+                .unwrap_or_default();
+            lines.push(server_line(repo, server, Some(owner)).await?);
+        }
+    }
+    bot.send_message(*chat_id, lines.join("\n"))
         .parse_mode(ParseMode::Html)
         .await?;
     Ok(())
 }
 
+async fn server_line(repo: &dyn Repository, server: &Server, owner: Option<String>) -> Result<String, BotError> {
+    let tasks = repo.tasks_count_by_server_id(&server.id).await?;
+    let status = match server.to_backend().version().await {
+        Ok(version) => format!("👍 v{}", version),
+        Err(_) => "👎".to_string(),
+    };
+    let owner = owner.map(|owner| format!(" by {}", owner)).unwrap_or_default();
+    Ok(format!(
+        "{}{}{}: <i>{}</i> tasks {}",
+        if server.is_default && owner.is_empty() { "⭐ " } else { "" },
+        server.alias,
+        owner,
+        tasks,
+        status
+    ))
+}
+
+/// Speed limit presets in KB/s, 0 removes the limit
+const DOWNLOAD_LIMITS: [i32; 4] = [512, 2048, 10240, 0];
+const UPLOAD_LIMITS: [i32; 4] = [128, 1024, 5120, 0];
+
+/// `srv_dash:server_short_id` sends the dashboard of the server
+pub async fn show_dashboard(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let server = match find_own_server(repo, &user, data).await? {
+        Some(server) => server,
+        None => {
+            bot.send_message(*chat_id, "Server not found").await?;
+            return Ok(());
+        }
+    };
+    let text = dashboard_text(&server).await;
+    bot.send_message(*chat_id, text)
+        .reply_markup(dashboard_buttons(&server))
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// `srv_turtle:server_short_id:1|0` and `srv_limit:server_short_id:d|u:kbps` change the session
+/// and refresh the dashboard
+pub async fn change_session(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let server = match find_own_server(repo, &user, data).await? {
+        Some(server) => server,
+        None => {
+            bot.send_message(message.chat.id, "Server not found").await?;
+            return Ok(());
+        }
+    };
+    let args = match session_args(data) {
+        Some(args) => args,
+        None => {
+            error!("Broken session callback received: {}", &data);
+            return Ok(());
+        }
+    };
+    let notice = match server.to_backend().session_set(args).await {
+        Ok(_) => "✅ Applied",
+        Err(err) => {
+            warn!("Unable to change the session of {}: {}", server.alias, err);
+            "❗️ The server didn't accept the change"
+        }
+    };
+    let text = format!("{}\n\n{}", dashboard_text(&server).await, notice);
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .reply_markup(dashboard_buttons(&server))
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// The session change of a `srv_turtle` or `srv_limit` callback, broken or unknown values are rejected
+fn session_args(data: &str) -> Option<SessionSetArgs> {
+    let parts: Vec<&str> = data.split(':').collect();
+    let limit = |limit: &str| limit.parse::<i32>().ok().filter(|limit| *limit >= 0);
+    match parts[..] {
+        ["srv_turtle", _, enabled @ ("0" | "1")] => Some(SessionSetArgs {
+            alt_speed_enabled: Some(enabled == "1"),
+            ..SessionSetArgs::default()
+        }),
+        ["srv_limit", _, "d", value] => limit(value).map(|limit| SessionSetArgs {
+            speed_limit_down_enabled: Some(limit > 0),
+            speed_limit_down: Some(limit).filter(|limit| *limit > 0),
+            ..SessionSetArgs::default()
+        }),
+        ["srv_limit", _, "u", value] => limit(value).map(|limit| SessionSetArgs {
+            speed_limit_up_enabled: Some(limit > 0),
+            speed_limit_up: Some(limit).filter(|limit| *limit > 0),
+            ..SessionSetArgs::default()
+        }),
+        _ => None,
+    }
+}
+
+async fn find_own_server(repo: &dyn Repository, user: &User, data: &str) -> Result<Option<Server>, BotError> {
+    match data.split(':').nth(1).and_then(|id| id.parse::<i32>().ok()) {
+        Some(short_id) => Ok(repo.get_server_by_short_id(user, short_id).await?),
+        None => Ok(None),
+    }
+}
+
+async fn dashboard_text(server: &Server) -> String {
+    let mut backend = server.to_backend();
+    let version = match backend.version().await {
+        Ok(version) => version,
+        Err(_) => return format!("<b>{}</b> is not reachable 👎", server.alias),
+    };
+    let mut lines = vec![format!("<b>{}</b> 📊\n{} {}", server.alias, server.kind().name(), version)];
+    if let Ok(stats) = backend.session_stats().await {
+        lines.push(format!(
+            "⬇️ {}/s ⬆️ {}/s",
+            format_bytes(stats.download_speed),
+            format_bytes(stats.upload_speed)
+        ));
+        lines.push(format!(
+            "Torrents: {}, active: {}, paused: {}",
+            stats.torrent_count, stats.active_torrent_count, stats.paused_torrent_count
+        ));
+        lines.push(format!(
+            "Total downloaded: {}, uploaded: {}",
+            format_bytes(stats.cumulative_stats.downloaded_bytes),
+            format_bytes(stats.cumulative_stats.uploaded_bytes)
+        ));
+    }
+    lines.join("\n")
+}
+
+fn dashboard_buttons(server: &Server) -> InlineKeyboardMarkup {
+    let limit_label = |limit: i32| match limit {
+        0 => "∞".to_string(),
+        limit => format_bytes(limit as i64 * 1024),
+    };
+    let limits = |direction: &str, icon: &str, presets: [i32; 4]| presets.iter()
+        .map(|limit| InlineKeyboardButton::callback(
+            format!("{} {}", icon, limit_label(*limit)),
+            format!("srv_limit:{}:{}:{}", server.short_id, direction, limit),
+        ))
+        .collect::<Vec<InlineKeyboardButton>>();
+    InlineKeyboardMarkup::new(vec![
+        vec![
+            InlineKeyboardButton::callback("Turtle mode 🐢", format!("srv_turtle:{}:1", server.short_id)),
+            InlineKeyboardButton::callback("Full speed 🐇", format!("srv_turtle:{}:0", server.short_id)),
+        ],
+        limits("d", "⬇️", DOWNLOAD_LIMITS),
+        limits("u", "⬆️", UPLOAD_LIMITS),
+        vec![InlineKeyboardButton::callback("Refresh 🔄", format!("srv_dash:{}", server.short_id))],
+        vec![InlineKeyboardButton::callback(HIDE_MESSAGE, HIDE_MESSAGE)],
+    ])
+}
+
+pub async fn make_server_default(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let short_id = match data.split(':').nth(1).and_then(|id| id.parse::<i32>().ok()) {
+        Some(id) => id,
+        None => {
+            error!("Broken default server callback received: {}", &data);
+            return Ok(());
+        }
+    };
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    match repo.get_server_by_short_id(&user, short_id).await? {
+        Some(server) => {
+            repo.set_default_server(&user, &server.id).await?;
+            show_stats(bot, repo, user_id, chat_id).await
+        }
+        None => {
+            bot.send_message(*chat_id, "Server not found").await?;
+            Ok(())
+        }
+    }
+}
+
 impl Server {
-    pub fn to_client(&self) -> TransClient {
-        match &self.auth() {
-            Some(auth) => TransClient::with_auth(
-                self.url().to_rpc_url(),
-                BasicAuth {
-                    user: auth.username.clone(),
-                    password: auth.password.clone(),
-                },
-            ),
-            None => TransClient::new(self.url().to_rpc_url()),
+    pub fn to_backend(&self) -> Box<dyn TorrentBackend> {
+        backend::connect(&self.endpoint(), self.auth(), Some(self.id))
+    }
+
+    /// Free space in bytes of the server disk where the path is, if the server can tell
+    pub async fn free_space(&self, path: &str) -> Option<i64> {
+        match self.to_backend().free_space(path).await {
+            Ok(size) => Some(size),
+            Err(err) => {
+                debug!("Unable to get free space of {} on {}: {}", path, self.alias, err);
+                None
+            }
         }
     }
 }
 
 impl NewServer {
-    pub fn to_client(&self) -> TransClient {
-        match &self.auth() {
-            Some(auth) => TransClient::with_auth(
-                self.url().to_rpc_url(),
-                BasicAuth {
-                    user: auth.username.clone(),
-                    password: auth.password.clone(),
-                },
-            ),
-            None => TransClient::new(self.url().to_rpc_url()),
-        }
+    pub fn to_backend(&self) -> Box<dyn TorrentBackend> {
+        backend::connect(&self.endpoint(), self.auth(), None)
     }
 }
 
 pub async fn reset_servers(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user_id: &u64,
     chat_id: &ChatId,
 ) -> Result<(), BotError> {
-    let user = get_user(pool, &(*user_id as i64)).await?.unwrap();
-    delete_servers(pool, &user).await?;
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    repo.delete_servers(&user).await?;
     bot.send_message(*chat_id, "Done!").await?;
     Ok(())
 }
 
 pub async fn register_server_prepare(
     bot: &Bot,
-    pool: &Pool,
-    user_id: &u64,
     chat_id: &ChatId,
-) -> Result<bool, BotError> {
-    let user = get_user(pool, &(*user_id as i64)).await?.unwrap();
-    let servers: Vec<Server> = get_servers_by_user_id(pool, &user).await?;
-    // for now is only 1 allowed
-    if servers.len() != 0 {
-        bot.send_message(*chat_id, "There is already a server registered!")
-            .await?;
-        Ok(false)
-    } else {
-        bot.send_message(
-            *chat_id,
-            "Enter server details in the format:\n<i>A link to you webui: E.g. http://localhost:9091/transmission/web</i>\n<i>Optional: user</i>\n<i>Optional: password</i>",
-        ).parse_mode(ParseMode::Html).await?;
-        Ok(true)
-    }
+) -> Result<(), BotError> {
+    bot.send_message(
+        *chat_id,
+        format!(
+            "Enter server details in the format:\n<i>A link to your webui or rpc: E.g. http://localhost:9091/transmission/web, http://localhost:8080 for qBittorrent or http://localhost:8112 for Deluge</i>\n<i>Optional: user, any one for Deluge</i>\n<i>Optional: password</i>\n<i>Optional: server alias</i>\n{}",
+            TLS_HINT
+        ),
+    ).parse_mode(ParseMode::Html).await?;
+    Ok(())
 }
 
 pub async fn register_server_dialogue(
     bot: Bot,
-    pool: Pool,
+    repo: Arc<dyn Repository>,
     dialogue: BotDialogue,
     message: Message,
 ) -> HandlerResult {
     let user_id = message.from().unwrap().id.0;
-    let user = get_user(&pool, &(user_id as i64)).await.unwrap().unwrap();
-    let text = message.text().unwrap();
-    let lines = text.lines().collect::<Vec<&str>>();
+    let user = repo.get_user(&(user_id as i64)).await?.unwrap();
+    let (url, auth, alias, tls) = match parse_server_details(&bot, &message).await? {
+        Some(details) => details,
+        None => return Ok(()),
+    };
+    let alias = alias.unwrap_or_else(|| url.host());
+    let server = NewServer::new(user_id, url.get_base_url(), alias, auth).with_tls(tls);
+    if try_to_add_server(&bot, &*repo, &user, &server, &message).await? {
+        dialogue.exit().await?
+    }
+    Ok(())
+}
+
+/// Url, credentials, alias and certificate checks from the lines of the message,
+/// or nothing if the format is wrong
+async fn parse_server_details(
+    bot: &Bot,
+    message: &Message,
+) -> Result<Option<(TransUrl, Option<Authentication>, Option<String>, TlsOptions)>, BotError> {
+    let text = message.text().unwrap_or_default();
+    let (mut lines, tls) = split_tls_options(text);
     let lines_count = lines.len();
-    match lines_count {
-        1 => {
-            let url = TransUrl::from_web_url(&lines.get(0).unwrap().to_string());
-            if url.is_none() {
-                return Ok(());
-            }
-            let server = NewServer::new(user_id.clone(), url.unwrap().get_base_url(), None);
-            if try_to_add_server(&bot, &pool, &user, &server, &message).await? {
-                dialogue.exit().await?
-            }
-        }
-        3 => {
-            let url = TransUrl::from_web_url(&lines.get(0).unwrap().to_string());
-            if url.is_none() {
-                return Ok(());
-            }
-            let server = NewServer::new(
-                user_id.clone(),
-                url.unwrap().get_base_url(),
-                Some(Authentication {
-                    username: lines.get(1).unwrap().to_string(),
-                    password: lines.get(2).unwrap().to_string(),
-                }),
-            );
-            if try_to_add_server(&bot, &pool, &user, &server, &message).await? {
-                dialogue.exit().await?
-            }
-        }
+    let (auth, alias) = match lines_count {
+        1 => (None, None),
+        2 => (None, Some(lines[1])),
+        3 | 4 => (
+            Some(Authentication {
+                username: lines[1].to_string(),
+                password: lines[2].to_string(),
+            }),
+            lines.get(3).copied(),
+        ),
         _ => {
             bot.send_message(message.chat.id, format!("Incorrect format. Found {} lines", lines_count))
                 .parse_mode(ParseMode::Html)
                 .await?;
-            if register_server_prepare(&bot, &pool, &user_id, &message.chat.id).await? {
-                dialogue.exit().await?
-            }
+            register_server_prepare(bot, &message.chat.id).await?;
+            return Ok(None);
+        }
+    };
+    if let Err(err) = backend::check_tls(&tls) {
+        bot.send_message(message.chat.id, format!("The certificate is not usable: {}", err)).await?;
+        return Ok(None);
+    }
+    let url = match TransUrl::from_web_url(&lines.remove(0).to_string()) {
+        Some(url) => url,
+        None => {
+            bot.send_message(message.chat.id, "The link should start with http:// or https://").await?;
+            return Ok(None);
         }
     };
+    Ok(Some((url, auth, alias.map(str::to_owned), tls)))
+}
+
+/// Takes out a pasted certificate or the self-signed line, which are the last ones
+fn split_tls_options(text: &str) -> (Vec<&str>, TlsOptions) {
+    let mut lines = text.lines().map(str::trim).collect::<Vec<&str>>();
+    if let (Some(begin), Some(end)) = (
+        lines.iter().position(|line| *line == PEM_BEGIN),
+        lines.iter().rposition(|line| *line == PEM_END),
+    ) {
+        if begin < end {
+            let pem = lines.drain(begin..=end).collect::<Vec<_>>().join("\n");
+            return (lines, TlsOptions::PinnedCa(pem));
+        }
+    }
+    if lines.len() > 1 && lines.last().is_some_and(|line| line.eq_ignore_ascii_case(SELF_SIGNED)) {
+        lines.pop();
+        return (lines, TlsOptions::AcceptInvalid);
+    }
+    (lines, TlsOptions::Verify)
+}
+
+/// Replaces a failure to decrypt a stored password with a hint to re-enter it,
+/// any other result is returned as is
+pub async fn report_unreadable_credentials(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    result: HandlerResult,
+) -> HandlerResult {
+    let error = match result {
+        Err(error) if error.downcast_ref::<BotError>().is_some_and(BotError::is_unreadable_credentials)
+            || error.downcast_ref::<DbError>().is_some_and(DbError::is_unreadable_credentials) => error,
+        result => return result,
+    };
+    warn!("User {} has unreadable credentials: {}", user_id, error);
+    let servers = match repo.get_user(&(*user_id as i64)).await? {
+        Some(user) => repo.get_unreadable_servers(&user).await?,
+        None => vec![],
+    };
+    if servers.is_empty() {
+        bot.send_message(
+            *chat_id,
+            "Stored credentials of a shared server are unreadable, please ask its owner to re-enter them",
+        ).await?;
+        return Ok(());
+    }
+    let buttons = servers
+        .iter()
+        .map(|server| vec![InlineKeyboardButton::callback(
+            format!("Edit {} ✏️", server.alias),
+            format!("srv_edit:{}", server.short_id),
+        )])
+        .collect::<Vec<_>>();
+    bot.send_message(*chat_id, "Stored credentials are unreadable, please re-enter them")
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
     Ok(())
 }
 
+/// `srv_edit:server_short_id` asks for the new server details
+pub async fn edit_server_prepare(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+    dialogue: &BotDialogue,
+) -> HandlerResult {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let short_id = data.split(':').nth(1).and_then(|id| id.parse::<i32>().ok());
+    let server = match short_id {
+        Some(short_id) => repo.get_server_details_by_short_id(&user, short_id).await?,
+        None => None,
+    };
+    let server = match server {
+        Some(server) => server,
+        None => {
+            bot.send_message(*chat_id, "Server not found").await?;
+            return Ok(());
+        }
+    };
+    bot.send_message(
+        *chat_id,
+        format!(
+            "Editing <b>{}</b>, the current link is {}\n\
+            Enter new server details in the format:\n<i>A link to your webui or rpc</i>\n<i>Optional: user</i>\n<i>Optional: password, the current credentials are kept if omitted</i>\n<i>Optional: server alias, the current one is kept if omitted</i>\n{}",
+            server.alias,
+            server.url().get_base_url(),
+            TLS_HINT
+        ),
+    ).parse_mode(ParseMode::Html).await?;
+    dialogue.update(State::EditServer { short_id: server.short_id }).await?;
+    Ok(())
+}
+
+pub async fn edit_server_dialogue(
+    bot: Bot,
+    repo: Arc<dyn Repository>,
+    dialogue: BotDialogue,
+    short_id: i32,
+    message: Message,
+) -> HandlerResult {
+    let user_id = message.from().unwrap().id.0;
+    let user = repo.get_user(&(user_id as i64)).await?.unwrap();
+    let server = match repo.get_server_details_by_short_id(&user, short_id).await? {
+        Some(server) => server,
+        None => {
+            bot.send_message(message.chat.id, "Server not found").await?;
+            dialogue.exit().await?;
+            return Ok(());
+        }
+    };
+    let (url, auth, alias, tls) = match parse_server_details(&bot, &message).await? {
+        Some(details) => details,
+        None => return Ok(()),
+    };
+    let alias = alias.unwrap_or(server.alias.clone());
+    // Without new credentials the stored ones are checked, an unreadable password asks to re-enter them
+    let stored_auth = match auth {
+        Some(_) => None,
+        None => repo.get_server_by_short_id(&user, short_id).await.ok().flatten().and_then(|server| server.auth()),
+    };
+    let candidate = NewServer::new(user_id, url.get_base_url(), alias, auth.clone().or(stored_auth)).with_tls(tls);
+    match resolve_endpoint(&candidate).await {
+        Some(endpoint) => {
+            repo.update_server(&user, &server.id, &url.get_base_url(), &candidate.alias(), &endpoint, auth).await?;
+            bot.send_message(message.chat.id, "Done!").await?;
+            dialogue.exit().await?;
+        }
+        None => {
+            bot.send_message(message.chat.id, "Unable to connect to server! Check details").await?;
+        }
+    }
+    Ok(())
+}
+
+/// The first endpoint a client answers at with the server credentials
+async fn resolve_endpoint(server: &NewServer) -> Option<ServerEndpoint> {
+    for endpoint in server.candidate_endpoints() {
+        if backend::connect(&endpoint, server.auth(), None).version().await.is_ok() {
+            return Some(endpoint);
+        }
+    }
+    None
+}
+
 async fn try_to_add_server(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user: &User,
     server: &NewServer,
     message: &Message,
 ) -> Result<bool, BotError> {
-    let mut client = server.to_client();
-    match client.session_get().await {
-        Ok(_) => {
-            add_a_server(pool, user, &server).await?;
+    match resolve_endpoint(server).await {
+        Some(endpoint) => {
+            repo.add_server(user, &server.clone().with_endpoint(&endpoint)).await?;
             bot.send_message(message.chat.id, "Done!").await?;
 
             Ok(true)
         }
-        Err(_) => {
+        None => {
             bot.send_message(
                 message.chat.id,
                 "Unable to connect to server! Check details",
             ).await?;
-            register_server_prepare(bot, pool, &(user.id as u64), &message.chat.id).await?;
+            register_server_prepare(bot, &message.chat.id).await?;
             Ok(false)
         }
     }
 }
 
-async fn add_a_server(pool: &Pool, user: &User, server: &NewServer) -> Result<Server, BotError> {
-    match &server.auth() {
-        Some(auth) => {
-            add_server_auth(
-                pool,
-                user,
-                &server.url().get_base_url(),
-                &auth.username,
-                &auth.password,
-            )
-                .await
+#[cfg(test)]
+mod test {
+    use crate::conversation::test_bot::{fake_bot, message, user};
+    use crate::core::backend::fake_transmission::{FakeTransmission, VERSION};
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::server::ServerKind;
+    use teloxide::dispatching::dialogue::{InMemStorage, Storage};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_register_server() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(Some(("admin", "secret"))).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let message = message(1, &transmission.base_url());
+        let auth = |password: &str| Some(Authentication {
+            username: "admin".to_owned(),
+            password: password.to_owned(),
+        });
+
+        let wrong = NewServer::new(1, transmission.base_url(), "Home".to_owned(), auth("wrong"));
+        assert!(!try_to_add_server(&bot, &repo, &user, &wrong, &message).await.unwrap());
+        assert!(repo.get_available_servers(&user).await.unwrap().is_empty());
+        assert_eq!(requests.texts()[0], "Unable to connect to server! Check details");
+
+        let right = NewServer::new(1, transmission.base_url(), "Home".to_owned(), auth("secret"));
+        assert!(try_to_add_server(&bot, &repo, &user, &right, &message).await.unwrap());
+        let servers = repo.get_available_servers(&user).await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert!(servers[0].is_default);
+        assert_eq!(requests.texts().pop().unwrap(), "Done!");
+    }
+
+    #[tokio::test]
+    async fn test_register_by_rpc_url() {
+        let (bot, _) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let rpc_url = format!("{}/transmission/rpc", transmission.base_url());
+        let message = message(1, &rpc_url);
+
+        let url = TransUrl::from_web_url(&rpc_url).unwrap();
+        let server = NewServer::new(1, url.get_base_url(), "Home".to_owned(), None);
+        assert!(try_to_add_server(&bot, &repo, &user, &server, &message).await.unwrap());
+        let servers = repo.get_available_servers(&user).await.unwrap();
+        assert_eq!(servers[0].endpoint().url.as_str(), rpc_url);
+        assert_eq!(servers[0].kind(), ServerKind::Transmission);
+        // the stored endpoint is used as is
+        assert!(servers[0].to_backend().version().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_edit_server_keeps_credentials() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(Some(("admin", "secret"))).await;
+        let repo = Arc::new(InMemoryRepository::default());
+        let user = user(&*repo, 1).await;
+        let auth = Some(Authentication { username: "admin".to_owned(), password: "secret".to_owned() });
+        let server = NewServer::new(1, transmission.base_url(), "Home".to_owned(), auth);
+        let server = repo.add_server(&user, &server).await.unwrap();
+        let dialogue = BotDialogue::new(InMemStorage::<State>::new().erase(), ChatId(1));
+        dialogue.update(State::EditServer { short_id: server.short_id }).await.unwrap();
+
+        let text = format!("{}\nOffice", transmission.base_url());
+        edit_server_dialogue(bot, repo.clone(), dialogue, server.short_id, message(1, &text)).await.unwrap();
+
+        assert_eq!(requests.texts(), vec!["Done!"]);
+        let server = repo.get_server_by_short_id(&user, server.short_id).await.unwrap().unwrap();
+        assert_eq!(server.alias, "Office");
+        assert_eq!(server.username, Some("admin".to_owned()));
+        assert_eq!(server.password, Some("secret".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_servers_with_unreachable_one() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let owner = user(&repo, 1).await;
+        let friend = user(&repo, 2).await;
+        let home = NewServer::new(1, transmission.base_url(), "Home".to_owned(), None);
+        repo.add_server(&owner, &home).await.unwrap();
+        let office = NewServer::new(1, "http://127.0.0.1:1".to_owned(), "Office".to_owned(), None);
+        let office = repo.add_server(&owner, &office).await.unwrap();
+        let shared = NewServer::new(2, "http://127.0.0.1:2".to_owned(), "Cottage".to_owned(), None);
+        let shared = repo.add_server(&friend, &shared).await.unwrap();
+        repo.share_server(&shared.id, &owner.id).await.unwrap();
+
+        list_servers(&bot, &repo, &1, &ChatId(1)).await.unwrap();
+        assert_eq!(requests.texts(), vec![format!(
+            "<b>My servers:</b>\n⭐ Home: <i>0</i> tasks 👍 v{}\nOffice: <i>0</i> tasks 👎\n\
+            <b>Shared with me:</b>\nCottage by user2: <i>0</i> tasks 👎",
+            VERSION
+        )]);
+
+        show_stats(&bot, &repo, &1, &ChatId(1)).await.unwrap();
+        let text = requests.texts().pop().unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("⭐ <b>Home</b> ") && lines[1].ends_with("<i>0</i> 👍"));
+        assert!(lines[2].starts_with("<b>Office</b> ") && lines[2].ends_with("<i>0</i> 👎"));
+        assert!(requests.last_buttons().contains(&format!("srv_default:{}", office.short_id)));
+    }
+
+    #[test]
+    fn test_session_args() {
+        let turtle = session_args("srv_turtle:1:1").unwrap();
+        assert_eq!(turtle.alt_speed_enabled, Some(true));
+        assert_eq!(session_args("srv_turtle:1:0").unwrap().alt_speed_enabled, Some(false));
+
+        let down = session_args("srv_limit:1:d:2048").unwrap();
+        assert_eq!((down.speed_limit_down_enabled, down.speed_limit_down), (Some(true), Some(2048)));
+        assert_eq!(down.speed_limit_up_enabled, None);
+        let unlimited = session_args("srv_limit:1:u:0").unwrap();
+        assert_eq!((unlimited.speed_limit_up_enabled, unlimited.speed_limit_up), (Some(false), None));
+
+        for data in [
+            "srv_limit:1:d:fast",
+            "srv_limit:1:u:-5",
+            "srv_limit:1:d:99999999999",
+            "srv_limit:1:x:512",
+            "srv_turtle:1:yes",
+            "srv_turtle:1",
+            "srv_speed:1:1",
+        ] {
+            assert!(session_args(data).is_none(), "{} should be rejected", data);
         }
-        None => add_server(pool, &user, &server.url().get_base_url()).await,
     }
-        .map_err(BotError::from)
+
+    #[tokio::test]
+    async fn test_change_session() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = NewServer::new(1, transmission.base_url(), "Home".to_owned(), None);
+        let server = repo.add_server(&user, &server).await.unwrap();
+        let dashboard = message(1, "Home");
+
+        change_session(&bot, &repo, &1, &format!("srv_limit:{}:d:2048", server.short_id), &dashboard).await.unwrap();
+        change_session(&bot, &repo, &1, &format!("srv_turtle:{}:1", server.short_id), &dashboard).await.unwrap();
+        assert_eq!(transmission.session(), serde_json::json!({
+            "speed-limit-down-enabled": true,
+            "speed-limit-down": 2048,
+            "alt-speed-enabled": true,
+        }));
+        assert!(requests.texts().pop().unwrap().ends_with("✅ Applied"));
+
+        change_session(&bot, &repo, &1, &format!("srv_limit:{}:u:0", server.short_id), &dashboard).await.unwrap();
+        assert_eq!(transmission.session()["speed-limit-up-enabled"], false);
+        assert!(transmission.session().get("speed-limit-up").is_none());
+
+        // broken input changes nothing
+        let before = transmission.session();
+        let edits = requests.methods().len();
+        change_session(&bot, &repo, &1, &format!("srv_limit:{}:d:fast", server.short_id), &dashboard).await.unwrap();
+        change_session(&bot, &repo, &1, &format!("srv_speed:{}:1", server.short_id), &dashboard).await.unwrap();
+        assert_eq!(transmission.session(), before);
+        assert_eq!(requests.methods().len(), edits);
+    }
+
+    #[test]
+    fn test_tls_options() {
+        let (lines, tls) = split_tls_options("https://example.com\nadmin\nsecret\nSelf-Signed");
+        assert_eq!(lines, vec!["https://example.com", "admin", "secret"]);
+        assert_eq!(tls, TlsOptions::AcceptInvalid);
+
+        let text = format!("https://example.com\nHome\n{}\nMIIB\n{}", PEM_BEGIN, PEM_END);
+        let (lines, tls) = split_tls_options(&text);
+        assert_eq!(lines, vec!["https://example.com", "Home"]);
+        assert_eq!(tls, TlsOptions::PinnedCa(format!("{}\nMIIB\n{}", PEM_BEGIN, PEM_END)));
+        assert!(backend::check_tls(&tls).is_err());
+
+        let (lines, tls) = split_tls_options("https://example.com\nself-signed.example.com");
+        assert_eq!(lines.len(), 2);
+        assert_eq!(tls, TlsOptions::Verify);
+    }
 }
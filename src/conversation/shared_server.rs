@@ -5,7 +5,8 @@ use crate::conversation::commands::settings_commands::HIDE_MESSAGE;
 use crate::conversation::shared_server::shared_servers_commands::{RESET, SHARE, UN_SHARE};
 
 use crate::db::{
-    repository::Pool,
+    models::{server::Server, user::User},
+    repo::Repository,
 };
 use crate::errors::BotError;
 
@@ -15,12 +16,18 @@ pub mod shared_servers_commands {
     pub const RESET: &str = "Reset Servers Sharing❌";
 }
 
+/// Sharing controls, shown only to owners of a server since shared servers can't be shared further
 pub async fn share_server_management(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user_id: &u64,
     chat_id: &ChatId,
 ) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    if repo.get_servers_by_user_id(&user).await?.is_empty() {
+        bot.send_message(*chat_id, "You don't have any servers to share. Register one in /settings").await?;
+        return Ok(());
+    }
     let kb = InlineKeyboardMarkup::new(vec![
         vec![InlineKeyboardButton::callback(SHARE, SHARE)],
         vec![InlineKeyboardButton::callback(UN_SHARE, UN_SHARE)],
@@ -31,4 +38,261 @@ pub async fn share_server_management(
         .reply_markup(kb)
         .await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub async fn choose_server_to_share(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let servers = repo.get_servers_by_user_id(&user).await?;
+    if servers.is_empty() {
+        bot.send_message(*chat_id, "You don't have any servers to share. Register one in /settings").await?;
+        return Ok(());
+    }
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = servers.iter()
+        .map(|server| vec![InlineKeyboardButton::callback(
+            &server.alias,
+            format!("share_srv:{}", server.short_id),
+        )])
+        .collect();
+    buttons.push(vec![InlineKeyboardButton::callback(HIDE_MESSAGE, HIDE_MESSAGE)]);
+    bot.send_message(*chat_id, "Which server do you want to share?")
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+pub async fn choose_friend_to_share(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let server = match find_own_server(repo, &user, data.split(':').nth(1)).await? {
+        Some(server) => server,
+        None => {
+            bot.send_message(*chat_id, "Server was not found").await?;
+            return Ok(());
+        }
+    };
+    let shared_with: Vec<i64> = repo.get_shares_by_owner(&user).await?
+        .into_iter()
+        .filter(|share| share.server_id == server.id)
+        .map(|share| share.friend_user_id)
+        .collect();
+    let friends: Vec<User> = repo.get_friends(user_id).await?
+        .into_iter()
+        .filter(|friend| !shared_with.contains(&friend.id))
+        .collect();
+    if friends.is_empty() {
+        bot.send_message(
+            *chat_id,
+            format!("There is nobody to share {} with. Try adding a friend with /add_friend command", server.alias),
+        ).await?;
+        return Ok(());
+    }
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = friends.chunks(2)
+        .map(|chunk| chunk.iter()
+            .map(|friend| InlineKeyboardButton::callback(
+                friend.display_name(),
+                format!("share_to:{}:{}", server.short_id, friend.id),
+            ))
+            .collect())
+        .collect();
+    buttons.push(vec![InlineKeyboardButton::callback(HIDE_MESSAGE, HIDE_MESSAGE)]);
+    bot.send_message(*chat_id, format!("Whom do you want to share {} with?", server.alias))
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+pub async fn share_server_with_friend(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let (server, friend) = match find_server_and_friend(repo, &user, data).await? {
+        Some(it) => it,
+        None => {
+            bot.send_message(*chat_id, "Server or friend was not found").await?;
+            return Ok(());
+        }
+    };
+    if repo.find_friend(&user.id, &friend.id).await?.is_none() {
+        bot.send_message(*chat_id, format!("{} is not your friend", friend.display_name())).await?;
+        return Ok(());
+    }
+    repo.share_server(&server.id, &friend.id).await?;
+    bot.send_message(*chat_id, format!("{} is shared with {} 🤝", server.alias, friend.display_name())).await?;
+    bot.send_message(
+        friend,
+        format!("{} shared the server {} with you. Now you can download to it 🤝", user.display_name(), server.alias),
+    ).await?;
+    Ok(())
+}
+
+pub async fn choose_share_to_revoke(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let shares = repo.get_shares_by_owner(&user).await?;
+    if shares.is_empty() {
+        bot.send_message(*chat_id, "You don't share any servers").await?;
+        return Ok(());
+    }
+    let servers = repo.get_servers_by_user_id(&user).await?;
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+    for share in shares {
+        let server = match servers.iter().find(|server| server.id == share.server_id) {
+            Some(server) => server,
+            None => continue,
+        };
+        let friend = match repo.get_user(&share.friend_user_id).await? {
+            Some(friend) => friend,
+            None => continue,
+        };
+        buttons.push(vec![InlineKeyboardButton::callback(
+            format!("{} ➡️ {}", server.alias, friend.display_name()),
+            format!("unshare:{}:{}", server.short_id, friend.id),
+        )]);
+    }
+    buttons.push(vec![InlineKeyboardButton::callback(HIDE_MESSAGE, HIDE_MESSAGE)]);
+    bot.send_message(*chat_id, "Which sharing do you want to revoke?")
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+pub async fn revoke_share(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let (server, friend) = match find_server_and_friend(repo, &user, data).await? {
+        Some(it) => it,
+        None => {
+            bot.send_message(*chat_id, "Server or friend was not found").await?;
+            return Ok(());
+        }
+    };
+    repo.unshare_server(&server.id, &friend.id).await?;
+    bot.send_message(*chat_id, format!("{} is no longer shared with {}", server.alias, friend.display_name())).await?;
+    bot.send_message(
+        friend,
+        format!("{} stopped sharing the server {} with you", user.display_name(), server.alias),
+    ).await?;
+    Ok(())
+}
+
+pub async fn reset_sharing(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+) -> Result<(), BotError> {
+    let user = repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let shares = repo.delete_shares_by_owner(&user).await?;
+    for share in &shares {
+        let server = repo.find_server_by_id(&share.server_id).await?;
+        let friend = repo.get_user(&share.friend_user_id).await?;
+        if let (Some(server), Some(friend)) = (server, friend) {
+            bot.send_message(
+                friend,
+                format!("{} stopped sharing the server {} with you", user.display_name(), server.alias),
+            ).await?;
+        }
+    }
+    bot.send_message(*chat_id, format!("Sharing is reset. {} shares were revoked", shares.len())).await?;
+    Ok(())
+}
+
+async fn find_own_server(repo: &dyn Repository, user: &User, short_id: Option<&str>) -> Result<Option<Server>, BotError> {
+    let short_id = match short_id.and_then(|value| value.parse::<i32>().ok()) {
+        Some(short_id) => short_id,
+        None => return Ok(None),
+    };
+    Ok(repo.get_server_by_short_id(user, short_id).await?)
+}
+
+/// Parses `prefix:server_short_id:friend_id` callback data
+async fn find_server_and_friend(repo: &dyn Repository, user: &User, data: &str) -> Result<Option<(Server, User)>, BotError> {
+    let parts: Vec<&str> = data.split(':').collect();
+    if parts.len() != 3 {
+        return Ok(None);
+    }
+    let server = match find_own_server(repo, user, Some(parts[1])).await? {
+        Some(server) => server,
+        None => return Ok(None),
+    };
+    let friend = match parts[2].parse::<i64>() {
+        Ok(friend_id) => repo.get_user(&friend_id).await?,
+        Err(_) => None,
+    };
+    Ok(friend.map(|friend| (server, friend)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::conversation::test_bot::{fake_bot, user};
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::server::NewServer;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sharing_needs_own_server() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let owner = user(&repo, 1).await;
+        let friend = user(&repo, 2).await;
+        let server = NewServer::new(1, "http://127.0.0.1:1".to_owned(), "Home".to_owned(), None);
+        let server = repo.add_server(&owner, &server).await.unwrap();
+        repo.share_server(&server.id, &friend.id).await.unwrap();
+
+        share_server_management(&bot, &repo, &2, &ChatId(2)).await.unwrap();
+        assert_eq!(requests.texts(), vec!["You don't have any servers to share. Register one in /settings"]);
+        assert!(requests.last_buttons().is_empty());
+
+        share_server_management(&bot, &repo, &1, &ChatId(1)).await.unwrap();
+        assert_eq!(requests.texts().pop().unwrap(), "Manage Server sharing:");
+        assert!(requests.last_buttons().contains(&SHARE.to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_share_and_revoke() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let owner = user(&repo, 1).await;
+        let friend = user(&repo, 2).await;
+        let server = NewServer::new(1, "http://127.0.0.1:1".to_owned(), "Home".to_owned(), None);
+        let server = repo.add_server(&owner, &server).await.unwrap();
+        let data = format!("share_to:{}:2", server.short_id);
+
+        share_server_with_friend(&bot, &repo, &1, &ChatId(1), &data).await.unwrap();
+        assert_eq!(requests.texts(), vec!["user2 is not your friend"]);
+        assert!(repo.get_shared_servers(&friend).await.unwrap().is_empty());
+
+        repo.add_friend(&1, &2).await.unwrap();
+        share_server_with_friend(&bot, &repo, &1, &ChatId(1), &data).await.unwrap();
+        assert_eq!(repo.get_shared_servers(&friend).await.unwrap()[0].id, server.id);
+
+        let data = format!("unshare:{}:2", server.short_id);
+        revoke_share(&bot, &repo, &1, &ChatId(1), &data).await.unwrap();
+        assert!(repo.get_shared_servers(&friend).await.unwrap().is_empty());
+        assert_eq!(requests.texts().pop().unwrap(), "user1 stopped sharing the server Home with you");
+    }
+}
@@ -0,0 +1,389 @@
+use log::*;
+use teloxide::Bot;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use transmission_rpc::types::{Id, Torrent, TorrentGetField, TorrentSetArgs};
+use uuid::Uuid;
+
+use crate::conversation::tasks::{find_user_task, get_server};
+use crate::core::magnet::MagnetLink;
+use crate::core::units::format_bytes;
+use crate::db::{
+    models::server::Server,
+    repo::Repository,
+};
+use crate::errors::BotError;
+
+const FILES_PAGE_SIZE: usize = 8;
+const FILE_NAME_LENGTH: usize = 32;
+
+/// Callback data of the file checklist: `prefix:task_uuid:value:page`
+struct FilesCallback {
+    task_id: Uuid,
+    value: usize,
+    page: usize,
+}
+
+impl FilesCallback {
+    fn parse(data: &str) -> Option<Self> {
+        let parts: Vec<&str> = data.split(':').collect();
+        match parts[..] {
+            [_, task_id, page] => Some(FilesCallback {
+                task_id: Uuid::parse_str(task_id).ok()?,
+                value: 0,
+                page: page.parse().ok()?,
+            }),
+            [_, task_id, value, page] => Some(FilesCallback {
+                task_id: Uuid::parse_str(task_id).ok()?,
+                value: value.parse().ok()?,
+                page: page.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// `t_files:task_uuid:page` shows a page of the file checklist in the task message
+pub async fn show_task_files(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+) -> Result<(), BotError> {
+    update_task_files(bot, repo, user_id, data, message, None).await
+}
+
+/// `t_fw:task_uuid:file_index:page` toggles whether the file is downloaded
+pub async fn toggle_file_wanted(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+) -> Result<(), BotError> {
+    update_task_files(bot, repo, user_id, data, message, Some(FileChange::Wanted)).await
+}
+
+/// `t_fp:task_uuid:file_index:page` cycles the file priority normal -> high -> low
+pub async fn cycle_file_priority(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+) -> Result<(), BotError> {
+    update_task_files(bot, repo, user_id, data, message, Some(FileChange::Priority)).await
+}
+
+/// Sends the file checklist when the torrent has several files and they are known,
+/// right after adding a torrent file or once the metadata of a magnet arrives
+pub(crate) async fn offer_task_files(
+    bot: &Bot,
+    chat_id: ChatId,
+    server: &Server,
+    task_id: &Uuid,
+    hash: &str,
+) -> Result<bool, BotError> {
+    let torrent = match get_torrent_files(server, hash).await {
+        Some(torrent) if torrent.files.as_ref().is_some_and(|files| files.len() > 1) => torrent,
+        _ => return Ok(false),
+    };
+    let (text, keyboard) = render_files_page(task_id, &torrent, 0);
+    bot.send_message(chat_id, text).reply_markup(keyboard).await?;
+    Ok(true)
+}
+
+enum FileChange {
+    Wanted,
+    Priority,
+}
+
+async fn update_task_files(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+    change: Option<FileChange>,
+) -> Result<(), BotError> {
+    let callback = match FilesCallback::parse(data) {
+        Some(callback) => callback,
+        None => {
+            error!("Broken task files callback received: {}", &data);
+            return Ok(());
+        }
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let (task, magnet) = match find_user_task(repo, user, &callback.task_id).await? {
+        Some(it) => it,
+        None => return Err(BotError::logic("No task found!".to_string())),
+    };
+    let server = match get_server(bot, repo, user, &message.chat.id, Some(task.server_id)).await {
+        Some(server) => server,
+        None => return Ok(()),
+    };
+    let hash = MagnetLink::from(&magnet.url)?.hash();
+    let mut torrent = match get_torrent_files(&server, &hash).await {
+        Some(torrent) => torrent,
+        None => {
+            bot.send_message(message.chat.id, "Torrent was not found on the server!").await?;
+            return Ok(());
+        }
+    };
+    if let Some(change) = change {
+        if let Some(stat) = torrent.file_stats.as_ref().and_then(|stats| stats.get(callback.value)) {
+            let index = vec![callback.value as i32];
+            let args = match change {
+                FileChange::Wanted if stat.wanted => TorrentSetArgs {
+                    files_unwanted: Some(index),
+                    ..TorrentSetArgs::default()
+                },
+                FileChange::Wanted => TorrentSetArgs {
+                    files_wanted: Some(index),
+                    ..TorrentSetArgs::default()
+                },
+                FileChange::Priority => match next_priority(stat.priority) {
+                    1 => TorrentSetArgs { priority_high: Some(index), ..TorrentSetArgs::default() },
+                    -1 => TorrentSetArgs { priority_low: Some(index), ..TorrentSetArgs::default() },
+                    _ => TorrentSetArgs { priority_normal: Some(index), ..TorrentSetArgs::default() },
+                },
+            };
+            if let Err(err) = server.to_backend().torrent_set(args, vec![Id::Hash(hash.clone())]).await {
+                warn!("Failed to change file {} of {}: {}", callback.value, &hash, err);
+            }
+            torrent = get_torrent_files(&server, &hash).await.unwrap_or(torrent);
+        }
+    }
+    let (text, keyboard) = render_files_page(&task.id, &torrent, callback.page);
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+async fn get_torrent_files(server: &Server, hash: &str) -> Option<Torrent> {
+    let fields = vec![
+        TorrentGetField::Name,
+        TorrentGetField::Files,
+        TorrentGetField::FileStats,
+    ];
+    match server.to_backend().torrent_get(Some(fields), vec![Id::Hash(hash.to_owned())]).await {
+        Ok(torrents) => torrents.into_iter().next(),
+        Err(err) => {
+            debug!("Server {} is not reachable: {}", server.id, err);
+            None
+        }
+    }
+}
+
+fn render_files_page(task_id: &Uuid, torrent: &Torrent, page: usize) -> (String, InlineKeyboardMarkup) {
+    let name = torrent.name.clone().unwrap_or_default();
+    let back = vec![InlineKeyboardButton::callback("Back ⬅️", format!("t_status:{}", task_id))];
+    let files = torrent.files.clone().unwrap_or_default();
+    let stats = torrent.file_stats.clone().unwrap_or_default();
+    if files.is_empty() || files.len() != stats.len() {
+        return (
+            format!("{}\nThe file list is not known yet, try again later", name),
+            InlineKeyboardMarkup::new(vec![back]),
+        );
+    }
+    let pages = files.len().div_ceil(FILES_PAGE_SIZE);
+    let page = page.min(pages - 1);
+    let wanted_size: i64 = files.iter().zip(&stats)
+        .filter(|(_, stat)| stat.wanted)
+        .map(|(file, _)| file.length)
+        .sum();
+    let wanted_count = stats.iter().filter(|stat| stat.wanted).count();
+
+    let mut keys: Vec<Vec<InlineKeyboardButton>> = files.iter().zip(&stats)
+        .enumerate()
+        .skip(page * FILES_PAGE_SIZE)
+        .take(FILES_PAGE_SIZE)
+        .map(|(index, (file, stat))| vec![
+            InlineKeyboardButton::callback(
+                format!(
+                    "{} {} {}",
+                    if stat.wanted { "✅" } else { "⬜️" },
+                    short_file_name(&file.name),
+                    format_bytes(file.length)
+                ),
+                format!("t_fw:{}:{}:{}", task_id, index, page),
+            ),
+            InlineKeyboardButton::callback(
+                priority_icon(stat.priority),
+                format!("t_fp:{}:{}:{}", task_id, index, page),
+            ),
+        ])
+        .collect();
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback("⬅️", format!("t_files:{}:{}", task_id, page - 1)));
+    }
+    if page < pages - 1 {
+        navigation.push(InlineKeyboardButton::callback("➡️", format!("t_files:{}:{}", task_id, page + 1)));
+    }
+    if !navigation.is_empty() {
+        keys.push(navigation);
+    }
+    keys.push(back);
+    let text = format!(
+        "{}\nSelected {} of {} files, {} ({}/{})\nPriority: 🔼 high, ➖ normal, 🔽 low",
+        name,
+        wanted_count,
+        files.len(),
+        format_bytes(wanted_size),
+        page + 1,
+        pages
+    );
+    (text, InlineKeyboardMarkup::new(keys))
+}
+
+fn next_priority(priority: i8) -> i8 {
+    match priority {
+        0 => 1,
+        1 => -1,
+        _ => 0,
+    }
+}
+
+fn priority_icon(priority: i8) -> &'static str {
+    match priority {
+        1 => "🔼",
+        -1 => "🔽",
+        _ => "➖",
+    }
+}
+
+/// File name without the torrent directory, cut from the start to keep the extension visible
+fn short_file_name(path: &str) -> String {
+    let name = path.split_once('/').map(|(_, rest)| rest).unwrap_or(path);
+    let chars: Vec<char> = name.chars().collect();
+    if chars.len() <= FILE_NAME_LENGTH {
+        return name.to_string();
+    }
+    let tail: String = chars[chars.len() - (FILE_NAME_LENGTH - 1)..].iter().collect();
+    format!("…{}", tail)
+}
+
+#[cfg(test)]
+mod test {
+    use base64::engine::general_purpose;
+    use base64::Engine;
+    use serde_json::json;
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    use crate::conversation::tasks::start_download;
+    use crate::conversation::test_bot::{fake_bot, message, user};
+    use crate::core::backend::fake_transmission::FakeTransmission;
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::download_task::TaskStatus;
+    use crate::db::models::server::NewServer;
+
+    use super::*;
+
+    const ALBUM: &[u8] = b"d4:infod5:filesld6:lengthi100e4:pathl5:CD 0110:track1.mp3eed6:lengthi200e\
+4:pathl5:CD 0210:track2.mp3eee4:name5:Album12:piece lengthi16384e6:pieces20:bbbbbbbbbbbbbbbbbbbbee";
+    const ALBUM_HASH: &str = "6d54cd79a626be7977dffa4b9ddf0b0e172f5b55";
+
+    fn torrent(files: usize) -> Torrent {
+        serde_json::from_value(json!({
+            "name": "Album",
+            "files": (0..files)
+                .map(|index| json!({"name": format!("Album/track{}.mp3", index), "length": 100, "bytesCompleted": 0}))
+                .collect::<Vec<_>>(),
+            "fileStats": (0..files)
+                .map(|index| json!({"bytesCompleted": 0, "wanted": index != 0, "priority": 0}))
+                .collect::<Vec<_>>(),
+        })).unwrap()
+    }
+
+    fn callbacks(keyboard: &InlineKeyboardMarkup) -> Vec<String> {
+        keyboard.inline_keyboard.iter()
+            .flatten()
+            .filter_map(|button| match &button.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => Some(data.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_callback_parse() {
+        let task_id = Uuid::new_v4();
+        let page = FilesCallback::parse(&format!("t_files:{}:2", task_id)).unwrap();
+        assert_eq!((page.task_id, page.value, page.page), (task_id, 0, 2));
+        let file = FilesCallback::parse(&format!("t_fw:{}:5:1", task_id)).unwrap();
+        assert_eq!((file.task_id, file.value, file.page), (task_id, 5, 1));
+
+        assert!(FilesCallback::parse("t_files:not-a-uuid:0").is_none());
+        assert!(FilesCallback::parse(&format!("t_fw:{}:x:0", task_id)).is_none());
+        assert!(FilesCallback::parse(&format!("t_fw:{}:1:2:3", task_id)).is_none());
+        assert!(FilesCallback::parse("t_files").is_none());
+    }
+
+    #[test]
+    fn test_files_paging() {
+        let task_id = Uuid::new_v4();
+        let album = torrent(10);
+
+        let (text, keyboard) = render_files_page(&task_id, &album, 0);
+        assert_eq!(text, "Album\nSelected 9 of 10 files, 900 B (1/2)\nPriority: 🔼 high, ➖ normal, 🔽 low");
+        assert_eq!(keyboard.inline_keyboard.len(), FILES_PAGE_SIZE + 2);
+        assert!(keyboard.inline_keyboard[0][0].text.starts_with("⬜️ track0.mp3"));
+        let data = callbacks(&keyboard);
+        assert!(data.contains(&format!("t_files:{}:1", task_id)));
+        assert!(!data.iter().any(|data| data.starts_with("t_files:") && data.ends_with(":-1")));
+
+        // a page past the end shows the last one
+        let (text, keyboard) = render_files_page(&task_id, &album, 5);
+        assert!(text.contains("(2/2)"));
+        assert_eq!(keyboard.inline_keyboard.len(), 2 + 2);
+        assert_eq!(callbacks(&keyboard)[0], format!("t_fw:{}:8:1", task_id));
+        assert!(callbacks(&keyboard).contains(&format!("t_files:{}:0", task_id)));
+
+        let (text, keyboard) = render_files_page(&task_id, &torrent(0), 0);
+        assert_eq!(text, "Album\nThe file list is not known yet, try again later");
+        assert_eq!(callbacks(&keyboard), vec![format!("t_status:{}", task_id)]);
+    }
+
+    #[test]
+    fn test_callback_data_limit() {
+        let (_, keyboard) = render_files_page(&Uuid::new_v4(), &torrent(10_000), 1_000);
+        for data in callbacks(&keyboard) {
+            assert!(data.len() <= 64, "{} is longer than Telegram allows", data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_files_of_added_torrent() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = NewServer::new(1, transmission.base_url(), "Home".to_owned(), None);
+        repo.add_server(&user, &server).await.unwrap();
+        repo.add_directory(&user, "Music", "/music", None).await.unwrap();
+        let metainfo = general_purpose::STANDARD.encode(ALBUM);
+        let url = format!("magnet:?xt=urn:btih:{}&dn=Album", ALBUM_HASH);
+        let magnet_id = repo.register_torrent_file(&user, &url, &metainfo).await.unwrap();
+
+        start_download(&bot, &repo, &ChatId(1), &1, &format!("download:{}:1", magnet_id)).await.unwrap();
+
+        // the checklist is offered right away and the watcher does not offer it again
+        let task = repo.tasks().pop().unwrap();
+        assert!(matches!(task.status(), TaskStatus::Started));
+        assert!(requests.texts().pop().unwrap().starts_with("Album\nSelected 2 of 2 files"));
+        assert!(requests.last_buttons().contains(&format!("t_fp:{}:1:0", task.id)));
+
+        let priority = || transmission.torrents()[0]["fileStats"][1]["priority"].clone();
+        let data = format!("t_fp:{}:1:0", task.id);
+        for expected in [1, -1, 0] {
+            cycle_file_priority(&bot, &repo, &1, &data, &message(1, "")).await.unwrap();
+            assert_eq!(priority(), json!(expected));
+        }
+        toggle_file_wanted(&bot, &repo, &1, &format!("t_fw:{}:0:0", task.id), &message(1, "")).await.unwrap();
+        assert_eq!(transmission.torrents()[0]["fileStats"][0]["wanted"], json!(false));
+        assert!(requests.texts().pop().unwrap().starts_with("Album\nSelected 1 of 2 files"));
+    }
+}
@@ -1,36 +1,74 @@
+use base64::Engine;
+use base64::engine::general_purpose;
 use chrono::prelude::*;
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use log::*;
 use teloxide::Bot;
 use teloxide::prelude::*;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
-use transmission_rpc::{
-    TransClient,
-    types::{Id, Torrent, TorrentAddArgs, TorrentAddedOrDuplicate},
+use std::collections::HashMap;
+use transmission_rpc::types::{
+    Id, Torrent, TorrentAction, TorrentAddArgs, TorrentAddedOrDuplicate, TorrentGetField, TorrentSetArgs,
+    TorrentStatus,
 };
 use uuid::Uuid;
 use crate::conversation::commands::settings_commands::HIDE_MESSAGE;
 
 use crate::conversation::directories::directories_commands;
 use crate::conversation::servers::servers_commands;
+use crate::conversation::task_files::offer_task_files;
 use crate::core::magnet::MagnetLink;
+use crate::core::torrent::TorrentMeta;
+use crate::core::units::{format_bytes, format_eta};
 use crate::db::{
-    models::{directories::DownloadDirectory, server::Server, user::User},
-    repository::{
-        add_task, get_directories, get_directory, get_magnet_by_id, get_servers_by_user_id,
-        get_task_by_id, get_user, Pool, register_magnet,
+    models::{
+        directories::DownloadDirectory,
+        download_task::{DownloadTask, TaskStatus},
+        magnet::Magnet,
+        server::Server,
+        user::User,
     },
+    repo::Repository,
 };
 use crate::errors::BotError;
+use crate::metrics;
+
+const TASKS_PAGE_SIZE: i64 = 5;
+/// Less free space than this after a download is worth a warning
+const LOW_FREE_SPACE: i64 = 1024 * 1024 * 1024;
 
 pub mod task_commands {
     pub const TASK_STATUS: &str = "Update task status 👀";
     pub const TASK_REMOVE: &str = "Delete torrent ❌";
+    pub const TASK_PAUSE: &str = "Pause ⏸";
+    pub const TASK_RESUME: &str = "Resume ▶️";
+    pub const TASK_VERIFY: &str = "Verify 🔎";
+    pub const TASK_REANNOUNCE: &str = "Reannounce 📣";
+    pub const TASK_QUEUE_TOP: &str = "Move to top ⏫";
+    pub const TASK_FILES: &str = "Files 📂";
+    pub const TASK_REMOVE_KEEP_FILES: &str = "Remove from list, keep files";
+    pub const TASK_REMOVE_DELETE_FILES: &str = "Remove and delete files 🗑";
 }
 
-async fn get_server(bot: &Bot, pool: &Pool, user: &User, chat_id: &ChatId) -> Option<Server> {
-    let servers = get_servers_by_user_id(pool, user).await;
-    match servers {
-        Ok(ref servers) if servers.len() == 0 => {
+/// The requested server (own or shared) or the default one when no server was chosen
+pub(crate) async fn get_server(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user: &User,
+    chat_id: &ChatId,
+    server_id: Option<Uuid>,
+) -> Option<Server> {
+    let server = match server_id {
+        Some(id) => repo.get_available_server_by_id(user, &id).await,
+        None => match repo.get_default_server(user).await {
+            Ok(None) => repo.get_available_servers(user).await.map(|servers| servers.into_iter().next()),
+            other => other,
+        },
+    };
+    match server {
+        Ok(None) => {
             let keyboard = InlineKeyboardMarkup::new(
                 vec![vec![InlineKeyboardButton::callback(
                     servers_commands::REGISTER_SERVER,
@@ -43,33 +81,67 @@ async fn get_server(bot: &Bot, pool: &Pool, user: &User, chat_id: &ChatId) -> Op
             ).reply_markup(keyboard).await;
             None
         }
-        Ok(ref servers) => Some(servers.get(0).unwrap().clone()),
+        Ok(server) => server,
         _ => None,
     }
 }
 
+/// Directories of a shared server are managed by its owner
+async fn directories_owner(repo: &dyn Repository, user: &User, server: &Server) -> Result<Option<User>, BotError> {
+    if server.user_id == user.id {
+        return Ok(Some(user.clone()));
+    }
+    Ok(repo.get_user(&server.user_id).await?)
+}
+
 fn update_task_status_button(task_id: &Uuid, torrent: &Torrent) -> InlineKeyboardMarkup {
-    let percent = torrent.percent_done;
+    let finished = torrent.percent_done == Some(1.0_f32);
+    let mut keys = vec![];
+    if finished {
+        keys.push(vec![InlineKeyboardButton::callback(
+            task_commands::TASK_REMOVE,
+            format!("t_remove:{}", &task_id),
+        )]);
+    } else {
+        keys.push(vec![InlineKeyboardButton::callback(
+            task_commands::TASK_STATUS,
+            format!("t_status:{}", &task_id),
+        )]);
+    }
+    keys.push(task_action_buttons(task_id, torrent));
+    if matches!(torrent.status, Some(TorrentStatus::QueuedToDownload) | Some(TorrentStatus::QueuedToSeed)) {
+        keys.push(vec![InlineKeyboardButton::callback(
+            task_commands::TASK_QUEUE_TOP,
+            format!("t_top:{}", &task_id),
+        )]);
+    }
+    // metadata of a magnet may not be known yet, so the files are offered until it is a single file
+    if torrent.files.as_ref().is_none_or(|files| files.len() > 1) {
+        keys.push(vec![InlineKeyboardButton::callback(
+            task_commands::TASK_FILES,
+            format!("t_files:{}:0", &task_id),
+        )]);
+    }
+    if finished {
+        keys.push(vec![InlineKeyboardButton::callback(HIDE_MESSAGE, HIDE_MESSAGE)]);
+    }
+    InlineKeyboardMarkup::new(keys)
+}
 
-    match percent {
-        Some(ref value) if value.to_owned() == 1.0_f32 => {
-            InlineKeyboardMarkup::new(
-                vec![vec![InlineKeyboardButton::callback(
-                    task_commands::TASK_REMOVE,
-                    format!("t_remove:{}", &task_id),
-                )],
-                     vec![InlineKeyboardButton::callback(HIDE_MESSAGE, HIDE_MESSAGE)],
-                ],
-            )
-        }
-        _ => {
-            InlineKeyboardMarkup::new(
-                vec![vec![InlineKeyboardButton::callback(
-                    task_commands::TASK_STATUS,
-                    format!("t_status:{}", &task_id),
-                )]]
-            )
-        }
+/// Pause or resume depending on the torrent state, verification is pointless while it is running
+fn task_action_buttons(task_id: &Uuid, torrent: &Torrent) -> Vec<InlineKeyboardButton> {
+    match torrent.status {
+        Some(TorrentStatus::Stopped) => vec![
+            InlineKeyboardButton::callback(task_commands::TASK_RESUME, format!("t_resume:{}", &task_id)),
+            InlineKeyboardButton::callback(task_commands::TASK_VERIFY, format!("t_verify:{}", &task_id)),
+        ],
+        Some(TorrentStatus::QueuedToVerify) | Some(TorrentStatus::Verifying) => vec![
+            InlineKeyboardButton::callback(task_commands::TASK_PAUSE, format!("t_pause:{}", &task_id)),
+        ],
+        _ => vec![
+            InlineKeyboardButton::callback(task_commands::TASK_PAUSE, format!("t_pause:{}", &task_id)),
+            InlineKeyboardButton::callback(task_commands::TASK_REANNOUNCE, format!("t_reannounce:{}", &task_id)),
+        ],
     }
 }
 
@@ -81,50 +153,90 @@ fn hide_message_button() -> InlineKeyboardMarkup {
 
 pub async fn start_download(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     chat_id: &ChatId,
     user_id: &u64,
     data: &str,
 ) -> Result<(), BotError> {
-    let data_parts: Vec<String> = data.split(":").map(|part| String::from(part)).collect();
-    if data_parts.len() != 3 {
-        error!("Broken download callback received: {}", &data);
-        bot.send_message(*chat_id, "We messed up. Can't start downloading :(")
-            .await?;
-        return Ok(());
-    }
-    let magnet_id = Uuid::parse_str(data_parts[1].as_ref()).expect("Incorrect uuid received");
-    let dir_ordinal = data_parts[2].parse::<i32>().unwrap();
-    let user = &get_user(pool, &(*user_id as i64)).await?.unwrap();
-    let magnet = get_magnet_by_id(pool, user, magnet_id).await?.unwrap();
-    let dir = get_directory(pool, user, dir_ordinal).await?;
+    let data_parts: Vec<String> = data.split(':').map(String::from).collect();
+    let parsed = match data_parts[..] {
+        [_, ref magnet_id, ref dir_ordinal] => Uuid::parse_str(magnet_id).ok().zip(dir_ordinal.parse::<i32>().ok()),
+        _ => None,
+    };
+    let (magnet_id, dir_ordinal) = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            error!("Broken download callback received: {}", &data);
+            bot.send_message(*chat_id, "We messed up. Can't start downloading :(")
+                .await?;
+            return Ok(());
+        }
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let magnet = match repo.get_magnet_by_id(user, magnet_id).await? {
+        Some(magnet) => magnet,
+        None => return Err(BotError::logic("No magnet found!".to_string())),
+    };
 
-    let server = match get_server(bot, pool, user, chat_id).await {
+    let server = match get_server(bot, repo, user, chat_id, magnet.server_id).await {
         Some(server) => server,
         None => return Ok(()),
     };
+    let dir = match directories_owner(repo, user, &server).await? {
+        Some(owner) => repo.get_directory(&owner, dir_ordinal).await?,
+        None => None,
+    };
 
     match dir {
         Some(dir) => {
-            let magnet_link = MagnetLink::from(&magnet.url).unwrap();
-            let mut client: TransClient = server.to_client();
-            match client
-                .torrent_add(TorrentAddArgs {
+            let size = torrent_size(&magnet);
+            let free_space = server.free_space(&dir.path).await;
+            if let (Some(size), Some(free_space)) = (size, free_space) {
+                if size > free_space {
+                    bot.send_message(*chat_id, format!(
+                        "Not enough space in {} ❗️\n{} needed, {} free",
+                        &dir.alias,
+                        format_bytes(size),
+                        format_bytes(free_space)
+                    )).await?;
+                    return Ok(());
+                }
+                if free_space - size < LOW_FREE_SPACE {
+                    bot.send_message(*chat_id, format!(
+                        "⚠️ Only {} will be left in {} after the download",
+                        format_bytes(free_space - size),
+                        &dir.alias
+                    )).await?;
+                }
+            }
+            let magnet_link = MagnetLink::from(&magnet.url)?;
+            let add_args = match magnet.metainfo {
+                Some(ref metainfo) => TorrentAddArgs {
+                    metainfo: Some(metainfo.clone()),
+                    download_dir: Some(dir.path),
+                    ..TorrentAddArgs::default()
+                },
+                None => TorrentAddArgs {
                     filename: Some(magnet_link.clone().short_link()),
                     download_dir: Some(dir.path),
                     ..TorrentAddArgs::default()
-                })
-                .await
-            {
-                Ok(response) => {
-                    let arguments: TorrentAddedOrDuplicate = response.arguments;
+                },
+            };
+            match server.to_backend().torrent_add(add_args).await {
+                Ok(arguments) => {
                     match arguments {
                         TorrentAddedOrDuplicate::TorrentAdded(torrent) => {
-                            let task = add_task(pool, user, &server.id, &magnet.clone()).await?;
-                            let name: String = magnet_link.dn();
+                            metrics::DOWNLOADS_STARTED.inc();
+                            let task = repo.add_task(user, &server.id, &magnet.clone()).await?;
+                            let name: String = magnet_link.clone().dn();
                             bot.send_message(*chat_id, format!("Downloading {}\nto {}", &name, &dir.alias))
                                 .reply_markup(update_task_status_button(&task.id, &torrent))
                                 .await?;
+                            let hash = magnet_link.hash().to_lowercase();
+                            if offer_task_files(bot, *chat_id, &server, &task.id, &hash).await? {
+                                // the metadata is known already, the watcher has nothing to wait for
+                                repo.set_task_status(&task.id, TaskStatus::Started).await?;
+                            }
                         }
                         TorrentAddedOrDuplicate::TorrentDuplicate(_) => {
                             bot.send_message(*chat_id, "Such task already exists")
@@ -153,43 +265,51 @@ pub async fn start_download(
     Ok(())
 }
 
+/// Size of the torrent when it was sent as a file, magnets don't know it until the metadata is fetched
+fn torrent_size(magnet: &Magnet) -> Option<i64> {
+    let data = general_purpose::STANDARD.decode(magnet.metainfo.as_ref()?).ok()?;
+    TorrentMeta::from_bytes(&data).ok().map(|meta| meta.total_size())
+}
+
 pub async fn update_task_status(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user_id: &u64,
     data: &str,
     message: &Message,
 ) -> Result<(), BotError> {
-    let data_parts: Vec<String> = data.split(":").map(|part| String::from(part)).collect();
-    if data_parts.len() != 2 {
-        error!("Broken task status callback received: {}", &data);
-        bot.send_message(message.chat.id, "We messed up. Can't check the status :(")
-            .await?;
-        return Ok(());
-    }
-    let task_id = Uuid::parse_str(data_parts[1].as_ref()).expect("Incorrect uuid received");
-    let user = &get_user(pool, &(*user_id as i64)).await?.unwrap();
-    let task = match get_task_by_id(pool, &task_id).await? {
+    let data_parts: Vec<String> = data.split(':').map(String::from).collect();
+    let task_id = match data_parts[..] {
+        [_, ref task_id] => Uuid::parse_str(task_id).ok(),
+        _ => None,
+    };
+    let task_id = match task_id {
+        Some(task_id) => task_id,
+        None => {
+            error!("Broken task status callback received: {}", &data);
+            bot.send_message(message.chat.id, "We messed up. Can't check the status :(")
+                .await?;
+            return Ok(());
+        }
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let task = match repo.get_task_by_id(&task_id).await? {
         Some(task) => task,
         None => return Err(BotError::logic("No task found!".to_string())),
     };
-    let server = match get_server(bot, pool, user, &message.chat.id).await {
+    let server = match get_server(bot, repo, user, &message.chat.id, Some(task.server_id)).await {
         Some(server) => server,
         None => return Ok(()),
     };
-    let magnet = match get_magnet_by_id(pool, user, task.magnet_id).await {
+    let magnet = match repo.get_magnet_by_id(user, task.magnet_id).await {
         Ok(ref link) if link.is_some() => link.clone().unwrap(),
         _ => return Ok(()),
     };
 
-    let link = MagnetLink::from(&magnet.url).unwrap();
+    let link = MagnetLink::from(&magnet.url)?;
     let hash = link.clone().hash();
-    let mut client: TransClient = server.to_client();
-    match client
-        .torrent_get(None, Some(vec![Id::Hash(hash.clone())]))
-        .await
-    {
-        Ok(response) => match response.arguments.torrents.iter().next() {
+    match server.to_backend().torrent_get(None, vec![Id::Hash(hash.clone())]).await {
+        Ok(torrents) => match torrents.first() {
             Some(torrent) => {
                 let name = torrent.name.as_ref().unwrap_or(&hash);
                 bot.edit_message_text(
@@ -222,126 +342,469 @@ pub async fn update_task_status(
     Ok(())
 }
 
-pub async fn remove_task(
+/// Runs a torrent action for `t_pause:`, `t_resume:`, `t_verify:`, `t_reannounce:` and `t_top:` callbacks
+/// and refreshes the task message
+pub async fn run_task_action(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     user_id: &u64,
     data: &str,
     message: &Message,
 ) -> Result<(), BotError> {
-    let data_parts: Vec<String> = data.split(":").map(|part| String::from(part)).collect();
-    if data_parts.len() != 2 {
-        error!("Broken task removal callback received: {}", &data);
-        bot.send_message(message.chat.id, "We messed up. Can't remove the task :(")
-            .await?;
-        return Ok(());
-    }
-    let task_id = Uuid::parse_str(data_parts[1].as_ref()).expect("Incorrect uuid received");
-    let user = &get_user(pool, &(*user_id as i64)).await?.unwrap();
-    let task = get_task_by_id(pool, &task_id).await?.unwrap();
-    let server = match get_server(bot, pool, user, &message.chat.id).await {
+    let (action, task_id) = match data.split_once(':') {
+        Some((action, id)) => match Uuid::parse_str(id) {
+            Ok(id) => (action, id),
+            Err(_) => {
+                error!("Broken task action callback received: {}", &data);
+                return Ok(());
+            }
+        },
+        None => return Ok(()),
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let (task, magnet) = match find_user_task(repo, user, &task_id).await? {
+        Some(it) => it,
+        None => return Err(BotError::logic("No task found!".to_string())),
+    };
+    let server = match get_server(bot, repo, user, &message.chat.id, Some(task.server_id)).await {
         Some(server) => server,
         None => return Ok(()),
     };
-    let magnet = match get_magnet_by_id(pool, user, task.magnet_id).await {
-        Ok(ref link) if link.is_some() => link.clone().unwrap(),
-        _ => return Ok(()),
+    let link = MagnetLink::from(&magnet.url)?;
+    let ids = vec![Id::Hash(link.clone().hash())];
+    let mut backend = server.to_backend();
+    let result = match action {
+        "t_pause" => backend.torrent_action(TorrentAction::Stop, ids).await,
+        "t_resume" => backend.torrent_action(TorrentAction::Start, ids).await,
+        "t_verify" => backend.torrent_action(TorrentAction::Verify, ids).await,
+        "t_reannounce" => backend.torrent_action(TorrentAction::Reannounce, ids).await,
+        "t_top" => {
+            let args = TorrentSetArgs {
+                queue_position: Some(0),
+                ..TorrentSetArgs::default()
+            };
+            backend.torrent_set(args, ids).await
+        }
+        _ => {
+            error!("Unknown task action received: {}", &data);
+            return Ok(());
+        }
     };
+    if let Err(err) = result {
+        warn!("Task action {} failed for {}: {}", action, link.dn(), err);
+        bot.send_message(message.chat.id, "The server didn't accept the action :(").await?;
+        return Ok(());
+    }
+    update_task_status(bot, repo, user_id, &format!("t_status:{}", task.id), message).await
+}
 
-    let link = MagnetLink::from(&magnet.url).unwrap();
-    let hash = link.clone().hash();
-    let mut client: TransClient = server.to_client();
-    match client
-        .torrent_remove(vec![Id::Hash(hash.clone())], true)
-        .await
-    {
-        Ok(_) => {
+/// Turns the task message into a removal confirmation
+pub async fn remove_task(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+) -> Result<(), BotError> {
+    let data_parts: Vec<String> = data.split(':').map(String::from).collect();
+    let task_id = match data_parts[..] {
+        [_, ref task_id] => Uuid::parse_str(task_id).ok(),
+        _ => None,
+    };
+    let task_id = match task_id {
+        Some(task_id) => task_id,
+        None => {
+            error!("Broken task removal callback received: {}", &data);
+            bot.send_message(message.chat.id, "We messed up. Can't remove the task :(")
+                .await?;
+            return Ok(());
+        }
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    if find_user_task(repo, user, &task_id).await?.is_none() {
+        return Err(BotError::logic("No task found!".to_string()));
+    }
+    bot.edit_message_reply_markup(message.chat.id, message.id)
+        .reply_markup(removal_confirmation_buttons(&task_id, None))
+        .await?;
+    Ok(())
+}
+
+/// Options to keep or delete the downloaded files in a random order, so it is hard to tap by accident.
+/// The page is set when the confirmation is shown in the tasks list
+fn removal_confirmation_buttons(task_id: &Uuid, page: Option<i64>) -> InlineKeyboardMarkup {
+    let suffix = page.map(|page| format!(":{}", page)).unwrap_or_default();
+    let cancel = match page {
+        Some(page) => format!("t_page:{}", page),
+        None => format!("t_status:{}", task_id),
+    };
+    let mut buttons = vec![
+        vec![InlineKeyboardButton::callback(
+            task_commands::TASK_REMOVE_KEEP_FILES,
+            format!("t_rm:k:{}{}", task_id, suffix),
+        )],
+        vec![InlineKeyboardButton::callback(
+            task_commands::TASK_REMOVE_DELETE_FILES,
+            format!("t_rm:d:{}{}", task_id, suffix),
+        )],
+        vec![InlineKeyboardButton::callback("No", cancel)],
+    ];
+    buttons.shuffle(&mut StdRng::from_entropy());
+    InlineKeyboardMarkup::new(buttons)
+}
+
+/// Handles `t_rm:k|d:task_uuid[:page]` confirmations
+pub async fn confirm_task_removal(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+) -> Result<(), BotError> {
+    let data_parts: Vec<&str> = data.split(':').collect();
+    let parsed = match data_parts[..] {
+        [_, mode, task_id] => Uuid::parse_str(task_id).ok().map(|id| (mode, id, None)),
+        [_, mode, task_id, page] => Uuid::parse_str(task_id).ok()
+            .zip(page.parse::<i64>().ok())
+            .map(|(id, page)| (mode, id, Some(page))),
+        _ => None,
+    };
+    let (delete_files, task_id, page) = match parsed {
+        Some(("k", id, page)) => (false, id, page),
+        Some(("d", id, page)) => (true, id, page),
+        _ => {
+            error!("Broken task removal callback received: {}", &data);
+            bot.send_message(message.chat.id, "We messed up. Can't remove the task :(")
+                .await?;
+            return Ok(());
+        }
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let (task, magnet) = match find_user_task(repo, user, &task_id).await? {
+        Some(it) => it,
+        None => return Err(BotError::logic("No task found!".to_string())),
+    };
+    let server = match get_server(bot, repo, user, &message.chat.id, Some(task.server_id)).await {
+        Some(server) => server,
+        None => return Ok(()),
+    };
+    let link = delete_torrent(&server, &magnet, delete_files).await?;
+    let status = if delete_files { TaskStatus::Deleted } else { TaskStatus::Removed };
+    repo.set_task_status(&task.id, status).await?;
+    match page {
+        Some(page) => edit_tasks_page(bot, repo, user, page, message).await,
+        None => {
+            let files = if delete_files { "with its files" } else { "the files are kept" };
             bot.edit_message_text(
                 message.chat.id,
                 message.id,
-                format!("Torrent\n{}\nwas removed", &link.dn()),
+                format!("Torrent\n{}\nwas removed, {}", &link.dn(), files),
             ).reply_markup(hide_message_button()).await?;
+            Ok(())
         }
-        _ => {
-            return Err(BotError::logic(format!(
-                "Failed to remove the torrent: {}",
-                link.dn()
-            )));
+    }
+}
+
+async fn delete_torrent(server: &Server, magnet: &Magnet, delete_files: bool) -> Result<MagnetLink, BotError> {
+    let link = MagnetLink::from(&magnet.url)?;
+    let hash = link.clone().hash();
+    match server.to_backend().torrent_remove(vec![Id::Hash(hash.clone())], delete_files).await {
+        Ok(_) => Ok(link),
+        _ => Err(BotError::logic(format!(
+            "Failed to remove the torrent: {}",
+            link.dn()
+        ))),
+    }
+}
+
+fn progress_bar(torrent: &Torrent) -> Option<String> {
+    let percent = (torrent.percent_done? * 100.0) as i32;
+    let filled: String = (0..percent / 10).map(|_| "❇️").collect();
+    let empty: String = (percent / 10..10).map(|_| "◻️").collect();
+    Some(format!("{}{}[{}%]", filled, empty, percent))
+}
+
+fn torrent_status(torrent: &Torrent) -> String {
+    match progress_bar(torrent) {
+        Some(bar) => format!(
+            "{}\nUpdated at: {}",
+            bar,
+            Utc::now().format("%d.%m.%Y %H:%M:%S")
+        ),
+        None => String::default(),
+    }
+}
+
+/// Progress, speed and eta of the torrent in one line
+fn torrent_summary(torrent: &Torrent) -> String {
+    let bar = progress_bar(torrent).unwrap_or_default();
+    match torrent.status {
+        Some(TorrentStatus::Downloading) => format!(
+            "{} ⬇️ {}/s ⏳ {}",
+            bar,
+            format_bytes(torrent.rate_download.unwrap_or_default()),
+            format_eta(torrent.eta.unwrap_or(-1))
+        ),
+        Some(TorrentStatus::Seeding) => format!(
+            "{} ⬆️ {}/s",
+            bar,
+            format_bytes(torrent.rate_upload.unwrap_or_default())
+        ),
+        Some(TorrentStatus::Stopped) => format!("{} ⏸", bar),
+        Some(TorrentStatus::QueuedToDownload) | Some(TorrentStatus::QueuedToSeed) => format!("{} 🕒", bar),
+        Some(TorrentStatus::QueuedToVerify) | Some(TorrentStatus::Verifying) => format!("{} 🔎", bar),
+        None => bar,
+    }
+}
+
+pub async fn list_tasks(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+) -> Result<(), BotError> {
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let (text, keyboard) = render_tasks_page(repo, user, 0).await?;
+    bot.send_message(*chat_id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_tasks_page(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+) -> Result<(), BotError> {
+    let page = data.split(':').nth(1).and_then(|page| page.parse::<i64>().ok()).unwrap_or(0);
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    edit_tasks_page(bot, repo, user, page, message).await
+}
+
+async fn edit_tasks_page(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user: &User,
+    page: i64,
+    message: &Message,
+) -> Result<(), BotError> {
+    let (text, keyboard) = render_tasks_page(repo, user, page).await?;
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// Loads one page of tasks and their torrents with a single request per server
+async fn render_tasks_page(
+    repo: &dyn Repository,
+    user: &User,
+    page: i64,
+) -> Result<(String, InlineKeyboardMarkup), BotError> {
+    let total = repo.user_tasks_count(user).await?;
+    if total == 0 {
+        return Ok(("You don't have any downloads yet".to_string(), hide_message_button()));
+    }
+    let pages = (total + TASKS_PAGE_SIZE - 1) / TASKS_PAGE_SIZE;
+    let page = page.clamp(0, pages - 1);
+    let tasks = repo.get_user_tasks_page(user, page * TASKS_PAGE_SIZE, TASKS_PAGE_SIZE).await?;
+
+    let mut hashes_by_server: HashMap<Uuid, Vec<String>> = HashMap::new();
+    // task id -> (hash, name)
+    let mut links: HashMap<Uuid, (String, String)> = HashMap::new();
+    for (task, magnet) in &tasks {
+        if let Ok(link) = MagnetLink::from(&magnet.url) {
+            let hash = link.clone().hash().to_lowercase();
+            hashes_by_server.entry(task.server_id).or_default().push(hash.clone());
+            links.insert(task.id, (hash, link.dn()));
+        }
+    }
+    let mut torrents: HashMap<String, Torrent> = HashMap::new();
+    for (server_id, hashes) in hashes_by_server {
+        let server = match repo.get_available_server_by_id(user, &server_id).await? {
+            Some(server) => server,
+            None => continue,
+        };
+        torrents.extend(get_torrents(&server, hashes).await);
+    }
+
+    let mut lines = vec![format!("Your downloads ({}/{}):", page + 1, pages)];
+    let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![];
+    for (number, (task, _)) in tasks.iter().enumerate() {
+        let number = page * TASKS_PAGE_SIZE + number as i64 + 1;
+        let link = links.get(&task.id);
+        let torrent = link.and_then(|(hash, _)| torrents.get(hash));
+        let name = torrent.and_then(|torrent| torrent.name.clone())
+            .or(link.map(|(_, name)| name.clone()))
+            .unwrap_or_default();
+        let status = match torrent {
+            Some(torrent) => torrent_summary(torrent),
+            None => format!("not on the server ({})", task.status),
+        };
+        lines.push(format!("{}. {}\n{}", number, name, status));
+        keys.push(task_row_buttons(number, task, page));
+    }
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback("⬅️", format!("t_page:{}", page - 1)));
+    }
+    if page < pages - 1 {
+        navigation.push(InlineKeyboardButton::callback("➡️", format!("t_page:{}", page + 1)));
+    }
+    if !navigation.is_empty() {
+        keys.push(navigation);
+    }
+    keys.push(vec![InlineKeyboardButton::callback(HIDE_MESSAGE, HIDE_MESSAGE)]);
+    Ok((lines.join("\n\n"), InlineKeyboardMarkup::new(keys)))
+}
+
+fn task_row_buttons(number: i64, task: &DownloadTask, page: i64) -> Vec<InlineKeyboardButton> {
+    let mut buttons = vec![
+        InlineKeyboardButton::callback(format!("👀 {}", number), format!("t_show:{}", task.id)),
+    ];
+    if !matches!(task.status(), TaskStatus::Removed | TaskStatus::Deleted) {
+        buttons.push(
+            InlineKeyboardButton::callback(format!("❌ {}", number), format!("t_lremove:{}:{}", task.id, page))
+        );
+    }
+    buttons
+}
+
+/// Torrents of the server by their lowercase hash, nothing if the server is not reachable
+async fn get_torrents(server: &Server, hashes: Vec<String>) -> HashMap<String, Torrent> {
+    let fields = vec![
+        TorrentGetField::HashString,
+        TorrentGetField::Name,
+        TorrentGetField::PercentDone,
+        TorrentGetField::Status,
+        TorrentGetField::RateDownload,
+        TorrentGetField::RateUpload,
+        TorrentGetField::Eta,
+    ];
+    let ids = hashes.into_iter().map(Id::Hash).collect();
+    match server.to_backend().torrent_get(Some(fields), ids).await {
+        Ok(torrents) => torrents
+            .into_iter()
+            .filter_map(|torrent| torrent.hash_string.clone().map(|hash| (hash.to_lowercase(), torrent)))
+            .collect(),
+        Err(err) => {
+            debug!("Server {} is not reachable: {}", server.id, err);
+            HashMap::new()
+        }
+    }
+}
+
+/// Sends a separate task message with the status controls
+pub async fn show_task(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    chat_id: &ChatId,
+    data: &str,
+) -> Result<(), BotError> {
+    let task_id = match data.split(':').nth(1).and_then(|id| Uuid::parse_str(id).ok()) {
+        Some(id) => id,
+        None => {
+            error!("Broken task callback received: {}", &data);
+            return Ok(());
+        }
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let (task, magnet) = match find_user_task(repo, user, &task_id).await? {
+        Some(it) => it,
+        None => {
+            bot.send_message(*chat_id, "Task was not found").await?;
+            return Ok(());
         }
     };
+    let server = match get_server(bot, repo, user, chat_id, Some(task.server_id)).await {
+        Some(server) => server,
+        None => return Ok(()),
+    };
+    let link = MagnetLink::from(&magnet.url)?;
+    let hash = link.clone().hash().to_lowercase();
+    match get_torrents(&server, vec![hash.clone()]).await.get(&hash) {
+        Some(torrent) => {
+            bot.send_message(
+                *chat_id,
+                format!(
+                    "Downloading {}\n{}",
+                    torrent.name.as_ref().unwrap_or(&hash),
+                    torrent_status(torrent)
+                ),
+            ).reply_markup(update_task_status_button(&task.id, torrent))
+                .await?;
+        }
+        None => {
+            bot.send_message(*chat_id, format!("Torrent\n{}\nis not on the server", link.dn()))
+                .reply_markup(hide_message_button())
+                .await?;
+        }
+    }
     Ok(())
 }
 
-fn torrent_status(torrent: &Torrent) -> String {
-    let percent = match torrent.percent_done {
-        Some(percent) => (percent * 100.0) as i32,
-        _ => return String::default(),
+/// Shows the removal confirmation in place of the tasks list buttons
+pub async fn remove_listed_task(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user_id: &u64,
+    data: &str,
+    message: &Message,
+) -> Result<(), BotError> {
+    let data_parts: Vec<&str> = data.split(':').collect();
+    let ids = match data_parts[..] {
+        [_, task_id, page] => Uuid::parse_str(task_id).ok().zip(page.parse::<i64>().ok()),
+        _ => None,
     };
-    let filled: String = (0..percent / 10).map(|_| "❇️").collect();
-    let empty: String = (percent / 10..10).map(|_| "◻️").collect();
-    format!(
-        "{}{}[{}%]\nUpdated at: {}",
-        filled,
-        empty,
-        percent,
-        Utc::now().format("%d.%m.%Y %H:%M:%S")
-    )
+    let (task_id, page) = match ids {
+        Some(ids) => ids,
+        None => {
+            error!("Broken task removal callback received: {}", &data);
+            return Ok(());
+        }
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    match find_user_task(repo, user, &task_id).await? {
+        Some((_, magnet)) => {
+            let name = MagnetLink::from(&magnet.url).map(MagnetLink::dn).unwrap_or_default();
+            bot.edit_message_text(message.chat.id, message.id, format!("Remove {}?", name))
+                .reply_markup(removal_confirmation_buttons(&task_id, Some(page)))
+                .await?;
+            Ok(())
+        }
+        None => edit_tasks_page(bot, repo, user, page, message).await,
+    }
+}
+
+pub(crate) async fn find_user_task(
+    repo: &dyn Repository,
+    user: &User,
+    task_id: &Uuid,
+) -> Result<Option<(DownloadTask, Magnet)>, BotError> {
+    let task = match repo.get_task_by_id(task_id).await? {
+        Some(task) if task.user_id == user.id => task,
+        _ => return Ok(None),
+    };
+    Ok(repo.get_magnet_by_id(user, task.magnet_id).await?.map(|magnet| (task, magnet)))
 }
 
 pub async fn process_magnet(
     bot: &Bot,
-    pool: &Pool,
+    repo: &dyn Repository,
     message: &Message,
     link: &String,
 ) -> Result<(), BotError> {
     let magnet = MagnetLink::find(link);
     match magnet {
         Some(link) => {
-            let user = &get_user(pool, &(message.from().unwrap().id.0 as i64)).await?.unwrap();
-            let server_count = get_servers_by_user_id(pool, user).await?.len();
-            if server_count == 0 {
-                let keyboard = InlineKeyboardMarkup::new(
-                    vec![vec![InlineKeyboardButton::callback(
-                        servers_commands::REGISTER_SERVER,
-                        servers_commands::REGISTER_SERVER,
-                    )]]
-                );
-                let err_message = "No Servers found! Please register one first!".to_owned();
-                bot.send_message(message.chat.id, &err_message).reply_markup(keyboard)
-                    .await?;
-                return Err(BotError::logic(err_message));
-            }
-
-            let dirs: Vec<DownloadDirectory> = get_directories(pool, user).await?;
-            if dirs.len() == 0 {
-                let keyboard = InlineKeyboardMarkup::new(
-                    vec![vec![InlineKeyboardButton::callback(
-                        directories_commands::ADD_DIRECTORY,
-                        directories_commands::ADD_DIRECTORY,
-                    )]]
-                );
-                let err_message = "No Directories found! Please add one first!".to_owned();
-                bot.send_message(message.chat.id, &err_message).reply_markup(keyboard)
-                    .await?;
-                return Err(BotError::logic(err_message));
-            }
-
-            let magnet_id = register_magnet(pool, user, &link.clone().full_link()).await?;
-            let mut keys = dirs.iter().map(|dir|
-                vec![InlineKeyboardButton::callback(
-                    &dir.alias,
-                    format!("download:{}:{}", &magnet_id, &dir.ordinal),
-                )]
-            ).collect::<Vec<Vec<InlineKeyboardButton>>>();
-            keys.append(&mut vec![vec![InlineKeyboardButton::callback(
-                "-- Cancel --",
-                "cancel",
-            )]]);
-            let keyboard = InlineKeyboardMarkup::new(keys);
-
-            bot.send_message(message.chat.id, format!("{}\nChoose directory to download", link.dn()))
-                .reply_markup(keyboard)
-                .await?;
+            let user = &repo.get_user(&(message.from().unwrap().id.0 as i64)).await?.unwrap();
+            check_download_prerequisites(bot, repo, user, message).await?;
+            metrics::MAGNETS_PROCESSED.inc();
+            let magnet_id = repo.register_magnet(user, &link.clone().full_link()).await?;
+            offer_servers(bot, repo, user, &message.chat.id, &magnet_id, &link.description()).await?;
         }
         None => {
             let err_message = format!("Couldn't parse magnet from text: {}", link);
@@ -353,3 +816,465 @@ pub async fn process_magnet(
     };
     Ok(())
 }
+
+pub async fn process_torrent_file(
+    bot: &Bot,
+    repo: &dyn Repository,
+    message: &Message,
+    data: &[u8],
+) -> Result<(), BotError> {
+    let meta = match TorrentMeta::from_bytes(data) {
+        Ok(meta) => meta,
+        Err(err) => {
+            error!("Couldn't parse torrent file: {}", &err);
+            bot.send_message(message.chat.id, "Sorry. Couldn't read this torrent file :(")
+                .await?;
+            return Err(BotError::logic(err.to_string()));
+        }
+    };
+    let user = &repo.get_user(&(message.from().unwrap().id.0 as i64)).await?.unwrap();
+    check_download_prerequisites(bot, repo, user, message).await?;
+    metrics::MAGNETS_PROCESSED.inc();
+    let metainfo = general_purpose::STANDARD.encode(data);
+    let magnet_id = repo.register_torrent_file(user, &meta.to_magnet().full_link(), &metainfo).await?;
+    offer_servers(bot, repo, user, &message.chat.id, &magnet_id, &meta.description()).await
+}
+
+async fn check_download_prerequisites(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user: &User,
+    message: &Message,
+) -> Result<(), BotError> {
+    let server_count = repo.get_available_servers(user).await?.len();
+    if server_count == 0 {
+        let keyboard = InlineKeyboardMarkup::new(
+            vec![vec![InlineKeyboardButton::callback(
+                servers_commands::REGISTER_SERVER,
+                servers_commands::REGISTER_SERVER,
+            )]]
+        );
+        let err_message = "No Servers found! Please register one first!".to_owned();
+        bot.send_message(message.chat.id, &err_message).reply_markup(keyboard)
+            .await?;
+        return Err(BotError::logic(err_message));
+    }
+    Ok(())
+}
+
+/// Asks to choose a server when there are several, otherwise goes straight to directories
+async fn offer_servers(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user: &User,
+    chat_id: &ChatId,
+    magnet_id: &Uuid,
+    name: &str,
+) -> Result<(), BotError> {
+    let mut servers: Vec<Server> = repo.get_available_servers(user).await?;
+    if servers.len() == 1 {
+        repo.set_magnet_server(user, magnet_id, &servers[0].id).await?;
+        return offer_directories(bot, repo, user, chat_id, magnet_id, &servers[0], name).await;
+    }
+    servers.sort_by_key(|server| !(server.is_default && server.user_id == user.id));
+    let mut keys = vec![];
+    for server in &servers {
+        let label = if server.user_id == user.id {
+            format!("{}{}", if server.is_default { "⭐ " } else { "" }, server.alias)
+        } else {
+            let owner = repo.get_user(&server.user_id).await?
+                .map(|owner| owner.display_name())
+                .unwrap_or_default();
+            format!("{} ({})", server.alias, owner)
+        };
+        keys.push(vec![InlineKeyboardButton::callback(
+            label,
+            format!("m_server:{}:{}", magnet_id, server.short_id),
+        )]);
+    }
+    keys.push(vec![InlineKeyboardButton::callback("-- Cancel --", "cancel")]);
+
+    bot.send_message(*chat_id, format!("{}\nChoose server to download", name))
+        .reply_markup(InlineKeyboardMarkup::new(keys))
+        .await?;
+    Ok(())
+}
+
+pub async fn choose_server(
+    bot: &Bot,
+    repo: &dyn Repository,
+    chat_id: &ChatId,
+    user_id: &u64,
+    data: &str,
+) -> Result<(), BotError> {
+    let data_parts: Vec<&str> = data.split(':').collect();
+    let ids = match data_parts[..] {
+        [_, magnet_id, short_id] => Uuid::parse_str(magnet_id).ok().zip(short_id.parse::<i32>().ok()),
+        _ => None,
+    };
+    let (magnet_id, short_id) = match ids {
+        Some(ids) => ids,
+        None => {
+            error!("Broken server choice callback received: {}", &data);
+            bot.send_message(*chat_id, "We messed up. Can't start downloading :(")
+                .await?;
+            return Ok(());
+        }
+    };
+    let user = &repo.get_user(&(*user_id as i64)).await?.unwrap();
+    let magnet = repo.get_magnet_by_id(user, magnet_id).await?;
+    let server = repo.get_available_server_by_short_id(user, short_id).await?;
+    match (magnet, server) {
+        (Some(magnet), Some(server)) => {
+            repo.set_magnet_server(user, &magnet.id, &server.id).await?;
+            let name = MagnetLink::from(&magnet.url).map(MagnetLink::dn).unwrap_or_default();
+            offer_directories(bot, repo, user, chat_id, &magnet.id, &server, &name).await
+        }
+        _ => {
+            bot.send_message(*chat_id, "The server or the link is not available anymore")
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+async fn offer_directories(
+    bot: &Bot,
+    repo: &dyn Repository,
+    user: &User,
+    chat_id: &ChatId,
+    magnet_id: &Uuid,
+    server: &Server,
+    name: &str,
+) -> Result<(), BotError> {
+    let owner = match directories_owner(repo, user, server).await? {
+        Some(owner) => owner,
+        None => {
+            bot.send_message(*chat_id, format!("The owner of {} is not known anymore", server.alias))
+                .await?;
+            return Ok(());
+        }
+    };
+    let mut dirs: Vec<DownloadDirectory> = repo.get_server_directories(&owner, &server.id).await?;
+    if dirs.is_empty() && owner.id != user.id {
+        bot.send_message(
+            *chat_id,
+            format!("No Directories found for {}! Please ask {} to add one", server.alias, owner.display_name()),
+        ).await?;
+        return Ok(());
+    }
+    if dirs.is_empty() {
+        let keyboard = InlineKeyboardMarkup::new(
+            vec![vec![InlineKeyboardButton::callback(
+                directories_commands::ADD_DIRECTORY,
+                directories_commands::ADD_DIRECTORY,
+            )]]
+        );
+        bot.send_message(*chat_id, format!("No Directories found for {}! Please add one first!", server.alias))
+            .reply_markup(keyboard)
+            .await?;
+        return Ok(());
+    }
+    dirs.sort_by_key(|dir| !dir.is_default);
+    let mut keys = dirs.iter().map(|dir|
+        vec![InlineKeyboardButton::callback(
+            format!("{}{}", if dir.is_default { "⭐ " } else { "" }, dir.alias),
+            format!("download:{}:{}", magnet_id, &dir.ordinal),
+        )]
+    ).collect::<Vec<Vec<InlineKeyboardButton>>>();
+    keys.append(&mut vec![vec![InlineKeyboardButton::callback(
+        "-- Cancel --",
+        "cancel",
+    )]]);
+    let keyboard = InlineKeyboardMarkup::new(keys);
+
+    bot.send_message(*chat_id, format!("{}\nChoose directory to download", name))
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::conversation::test_bot::{fake_bot, message, user};
+    use crate::core::backend::fake_transmission::{FakeTransmission, FREE_SPACE};
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::server::{Authentication, NewServer};
+
+    use super::*;
+
+    const HASH: &str = "c9e15763f722f23e98a29decdfae341b98d53056";
+    const MAGNET: &str = "magnet:?xt=urn:btih:c9e15763f722f23e98a29decdfae341b98d53056&dn=Test";
+
+    async fn server(repo: &InMemoryRepository, user: &User, url: &str, auth: Option<Authentication>) -> Server {
+        let server = NewServer::new(user.id as u64, url.to_owned(), "Home".to_owned(), auth);
+        repo.add_server(user, &server).await.unwrap()
+    }
+
+    /// A registered torrent file of a single file with the given size
+    async fn torrent_file(repo: &InMemoryRepository, user: &User, size: i64) -> Uuid {
+        let data = format!("d4:infod6:lengthi{}e4:name8:test.iso12:piece lengthi262144e6:pieces20:aaaaaaaaaaaaaaaaaaaaee", size);
+        let meta = TorrentMeta::from_bytes(data.as_bytes()).unwrap();
+        let url = format!("magnet:?xt=urn:btih:{}&dn=test.iso", meta.info_hash);
+        repo.register_torrent_file(user, &url, &general_purpose::STANDARD.encode(data)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_magnet_offers_directories() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = server(&repo, &user, "http://127.0.0.1:1", None).await;
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+
+        process_magnet(&bot, &repo, &message(1, MAGNET), &MAGNET.to_owned()).await.unwrap();
+
+        let magnets = repo.magnets();
+        assert_eq!(magnets.len(), 1);
+        assert_eq!(magnets[0].server_id, Some(server.id));
+        assert_eq!(requests.texts(), vec!["Test\nChoose directory to download"]);
+        assert!(requests.last_buttons().contains(&format!("download:{}:1", magnets[0].id)));
+    }
+
+    #[tokio::test]
+    async fn test_magnet_offers_shared_servers() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let owner = user(&repo, 1).await;
+        let friend = user(&repo, 2).await;
+        let own = server(&repo, &friend, "http://127.0.0.1:1", None).await;
+        let shared = server(&repo, &owner, "http://127.0.0.1:2", None).await;
+        repo.share_server(&shared.id, &friend.id).await.unwrap();
+
+        process_magnet(&bot, &repo, &message(2, MAGNET), &MAGNET.to_owned()).await.unwrap();
+
+        let magnet_id = repo.magnets()[0].id;
+        assert_eq!(requests.texts(), vec!["Test\nChoose server to download"]);
+        assert_eq!(requests.last_buttons(), vec![
+            format!("m_server:{}:{}", magnet_id, own.short_id),
+            format!("m_server:{}:{}", magnet_id, shared.short_id),
+            "cancel".to_owned(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_magnet_without_servers() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        user(&repo, 1).await;
+
+        assert!(process_magnet(&bot, &repo, &message(1, MAGNET), &MAGNET.to_owned()).await.is_err());
+        assert!(repo.magnets().is_empty());
+        assert_eq!(requests.texts(), vec!["No Servers found! Please register one first!"]);
+    }
+
+    #[tokio::test]
+    async fn test_download_to_unreachable_server() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        server(&repo, &user, "http://127.0.0.1:1", None).await;
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        let magnet_id = repo.register_magnet(&user, MAGNET).await.unwrap();
+
+        start_download(&bot, &repo, &ChatId(1), &1, &format!("download:{}:1", magnet_id)).await.unwrap();
+
+        assert!(repo.tasks().is_empty());
+        assert_eq!(requests.texts(), vec!["Unable to add task"]);
+    }
+
+    #[tokio::test]
+    async fn test_download_without_directory() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        server(&repo, &user, "http://127.0.0.1:1", None).await;
+        let magnet_id = repo.register_magnet(&user, MAGNET).await.unwrap();
+
+        start_download(&bot, &repo, &ChatId(1), &1, &format!("download:{}:2", magnet_id)).await.unwrap();
+
+        assert_eq!(requests.methods(), vec!["SendMessage"]);
+        assert_eq!(requests.texts(), vec!["No Directories found! Please add one first!"]);
+    }
+
+    #[tokio::test]
+    async fn test_download_without_space() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        server(&repo, &user, &transmission.base_url(), None).await;
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        let magnet_id = torrent_file(&repo, &user, FREE_SPACE + 1).await;
+
+        start_download(&bot, &repo, &ChatId(1), &1, &format!("download:{}:1", magnet_id)).await.unwrap();
+
+        assert!(repo.tasks().is_empty());
+        assert!(transmission.torrents().is_empty());
+        assert_eq!(requests.texts(), vec![format!(
+            "Not enough space in Movies ❗️\n{} needed, {} free",
+            format_bytes(FREE_SPACE + 1),
+            format_bytes(FREE_SPACE)
+        )]);
+    }
+
+    #[tokio::test]
+    async fn test_download_with_low_space() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        server(&repo, &user, &transmission.base_url(), None).await;
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        let magnet_id = torrent_file(&repo, &user, FREE_SPACE - LOW_FREE_SPACE / 2).await;
+
+        start_download(&bot, &repo, &ChatId(1), &1, &format!("download:{}:1", magnet_id)).await.unwrap();
+
+        assert_eq!(repo.tasks().len(), 1);
+        assert_eq!(requests.texts(), vec![
+            format!("⚠️ Only {} will be left in Movies after the download", format_bytes(LOW_FREE_SPACE / 2)),
+            "Downloading test.iso\nto Movies".to_owned(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_broken_callbacks() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        server(&repo, &user, "http://127.0.0.1:1", None).await;
+        let magnet_id = repo.register_magnet(&user, MAGNET).await.unwrap();
+
+        for data in ["download:not-a-uuid:1".to_owned(), format!("download:{}:x", magnet_id), "download".to_owned()] {
+            start_download(&bot, &repo, &ChatId(1), &1, &data).await.unwrap();
+        }
+        let status = message(1, "Downloading Test");
+        for data in ["t_status:not-a-uuid", "t_status"] {
+            update_task_status(&bot, &repo, &1, data, &status).await.unwrap();
+        }
+        for data in ["t_remove:not-a-uuid", "t_rm:k:not-a-uuid", "t_rm:x:not-a-uuid:0"] {
+            remove_task(&bot, &repo, &1, data, &status).await.unwrap();
+            confirm_task_removal(&bot, &repo, &1, data, &status).await.unwrap();
+        }
+        let unknown = format!("download:{}:1", Uuid::new_v4());
+        assert!(start_download(&bot, &repo, &ChatId(1), &1, &unknown).await.is_err());
+
+        assert!(repo.tasks().is_empty());
+        assert_eq!(requests.texts(), [
+            vec!["We messed up. Can't start downloading :("; 3],
+            vec!["We messed up. Can't check the status :("; 2],
+            vec!["We messed up. Can't remove the task :("; 6],
+        ].concat());
+    }
+
+    #[tokio::test]
+    async fn test_download_and_status() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(Some(("admin", "secret"))).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let auth = Authentication { username: "admin".to_owned(), password: "secret".to_owned() };
+        server(&repo, &user, &transmission.base_url(), Some(auth)).await;
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        let magnet_id = repo.register_magnet(&user, MAGNET).await.unwrap();
+        let data = format!("download:{}:1", magnet_id);
+
+        start_download(&bot, &repo, &ChatId(1), &1, &data).await.unwrap();
+
+        let tasks = repo.tasks();
+        assert_eq!(tasks.len(), 1);
+        let torrents = transmission.torrents();
+        assert_eq!(torrents.len(), 1);
+        assert_eq!(torrents[0]["hashString"], HASH);
+        assert_eq!(torrents[0]["downloadDir"], "/movies");
+        assert_eq!(requests.texts(), vec!["Downloading Test\nto Movies"]);
+        assert!(requests.last_buttons().contains(&format!("t_status:{}", tasks[0].id)));
+
+        transmission.update_torrent(HASH, json!({"name": "Test", "percentDone": 0.5}));
+        let status = format!("t_status:{}", tasks[0].id);
+        update_task_status(&bot, &repo, &1, &status, &message(1, "Downloading Test")).await.unwrap();
+        let text = requests.texts().pop().unwrap();
+        assert!(text.starts_with("Downloading Test\n"));
+        assert!(text.contains("[50%]"));
+
+        start_download(&bot, &repo, &ChatId(1), &1, &data).await.unwrap();
+        assert_eq!(repo.tasks().len(), 1);
+        assert_eq!(requests.texts().pop().unwrap(), "Such task already exists");
+    }
+
+    #[tokio::test]
+    async fn test_task_actions() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        server(&repo, &user, &transmission.base_url(), None).await;
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        let magnet_id = repo.register_magnet(&user, MAGNET).await.unwrap();
+        start_download(&bot, &repo, &ChatId(1), &1, &format!("download:{}:1", magnet_id)).await.unwrap();
+        let task_id = repo.tasks()[0].id;
+        let status = || transmission.torrents()[0]["status"].clone();
+        let task_message = message(1, "Downloading Test");
+
+        run_task_action(&bot, &repo, &1, &format!("t_pause:{}", task_id), &task_message).await.unwrap();
+        assert_eq!(status(), json!(0));
+        assert!(requests.last_buttons().contains(&format!("t_resume:{}", task_id)));
+        assert!(requests.last_buttons().contains(&format!("t_verify:{}", task_id)));
+
+        run_task_action(&bot, &repo, &1, &format!("t_verify:{}", task_id), &task_message).await.unwrap();
+        assert_eq!(status(), json!(2));
+        assert_eq!(requests.last_buttons()[1], format!("t_pause:{}", task_id));
+        assert!(!requests.last_buttons().contains(&format!("t_verify:{}", task_id)));
+
+        run_task_action(&bot, &repo, &1, &format!("t_resume:{}", task_id), &task_message).await.unwrap();
+        assert_eq!(status(), json!(4));
+        assert!(requests.last_buttons().contains(&format!("t_reannounce:{}", task_id)));
+        assert_eq!(requests.methods().iter().filter(|method| *method == "EditMessageText").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_action_for_removed_torrent() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = server(&repo, &user, &transmission.base_url(), None).await;
+        let magnet_id = repo.register_magnet(&user, MAGNET).await.unwrap();
+        let magnet = repo.get_magnet_by_id(&user, magnet_id).await.unwrap().unwrap();
+        let task = repo.add_task(&user, &server.id, &magnet).await.unwrap();
+
+        run_task_action(&bot, &repo, &1, &format!("t_pause:{}", task.id), &message(1, "Downloading Test")).await.unwrap();
+
+        assert!(transmission.torrents().is_empty());
+        assert_eq!(requests.texts(), vec!["Torrent\nTest\nwas removed"]);
+        assert_eq!(requests.last_buttons(), vec![HIDE_MESSAGE]);
+    }
+
+    #[tokio::test]
+    async fn test_remove_listed_task() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        server(&repo, &user, &transmission.base_url(), None).await;
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        let magnet_id = repo.register_magnet(&user, MAGNET).await.unwrap();
+        start_download(&bot, &repo, &ChatId(1), &1, &format!("download:{}:1", magnet_id)).await.unwrap();
+        let task_id = repo.tasks()[0].id;
+        let list = message(1, "Your downloads");
+
+        remove_listed_task(&bot, &repo, &1, &format!("t_lremove:{}:0", task_id), &list).await.unwrap();
+        assert_eq!(requests.texts().pop().unwrap(), "Remove Test?");
+        assert!(requests.last_buttons().contains(&format!("t_rm:k:{}:0", task_id)));
+
+        confirm_task_removal(&bot, &repo, &1, &format!("t_rm:k:{}:0", task_id), &list).await.unwrap();
+        assert!(matches!(repo.tasks()[0].status(), TaskStatus::Removed));
+        assert!(transmission.torrents().is_empty());
+        let text = requests.texts().pop().unwrap();
+        assert!(text.starts_with("Your downloads (1/1):"));
+        assert!(text.contains("not on the server (removed)"));
+        assert!(!requests.last_buttons().contains(&format!("t_lremove:{}:0", task_id)));
+    }
+}
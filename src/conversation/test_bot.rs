@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use teloxide::types::Message;
+use teloxide::Bot;
+
+use crate::core::crypto::random_salt;
+use crate::db::models::user::{NewUser, User};
+use crate::db::repo::Repository;
+
+/// Requests the bot sent to the fake Telegram API, as the method name and its json body,
+/// and the files the bot can download by their id
+#[derive(Clone, Default)]
+pub struct SentRequests(Arc<Mutex<Vec<(String, Value)>>>, Arc<Mutex<HashMap<String, Vec<u8>>>>);
+
+impl SentRequests {
+    pub fn methods(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().map(|(method, _)| method.clone()).collect()
+    }
+
+    /// Makes the file available as if a user uploaded it
+    pub fn add_file(&self, id: &str, data: &[u8]) {
+        self.1.lock().unwrap().insert(id.to_owned(), data.to_vec());
+    }
+
+    /// Texts of the sent and edited messages
+    pub fn texts(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter()
+            .filter_map(|(_, body)| body["text"].as_str().map(str::to_owned))
+            .collect()
+    }
+
+    /// Callback data of every inline button of the last message with a keyboard
+    pub fn last_buttons(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter()
+            .rev()
+            .find_map(|(_, body)| body["reply_markup"]["inline_keyboard"].as_array().cloned())
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|row| row.as_array().cloned().unwrap_or_default())
+            .filter_map(|button| button["callback_data"].as_str().map(str::to_owned))
+            .collect()
+    }
+}
+
+/// A bot talking to a local server that answers like Telegram and records every request
+pub async fn fake_bot() -> (Bot, SentRequests) {
+    let requests = SentRequests::default();
+    let router = Router::new()
+        .route("/:token/:method", post(answer))
+        .route("/file/:token/:path", get(download))
+        .with_state(requests.clone());
+    let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    (Bot::new("token").set_api_url(url.parse().unwrap()), requests)
+}
+
+async fn answer(
+    State(requests): State<SentRequests>,
+    Path((_, method)): Path<(String, String)>,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    let result = match method.to_lowercase().as_str() {
+        "sendmessage" | "editmessagetext" => message_json(
+            body["chat_id"].as_i64().unwrap_or_default(),
+            body["text"].as_str().unwrap_or_default(),
+        ),
+        "getfile" => {
+            let id = body["file_id"].as_str().unwrap_or_default();
+            let size = requests.1.lock().unwrap().get(id).map(Vec::len).unwrap_or_default();
+            json!({"file_id": id, "file_unique_id": id, "file_size": size, "file_path": id})
+        }
+        _ => json!(true),
+    };
+    requests.0.lock().unwrap().push((method, body));
+    Json(json!({"ok": true, "result": result}))
+}
+
+async fn download(
+    State(requests): State<SentRequests>,
+    Path((_, path)): Path<(String, String)>,
+) -> Result<Vec<u8>, StatusCode> {
+    requests.1.lock().unwrap().get(&path).cloned().ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Registers the user as `/start` does
+pub async fn user(repo: &dyn Repository, id: i64) -> User {
+    repo.save_user(NewUser {
+        id,
+        chat: id,
+        first_name: "Test".to_owned(),
+        last_name: None,
+        username: Some(format!("user{}", id)),
+        salt: random_salt(),
+    }).await.unwrap()
+}
+
+/// A private chat message, the chat id is the user id as Telegram does
+pub fn message(user_id: i64, text: &str) -> Message {
+    serde_json::from_value(message_json(user_id, text)).unwrap()
+}
+
+/// A private chat message with a file, its size is what the client claims
+pub fn document(user_id: i64, file_name: &str, file_id: &str, size: usize) -> Message {
+    let mut json = message_json(user_id, "");
+    json.as_object_mut().unwrap().remove("text");
+    json["document"] = json!({
+        "file_id": file_id,
+        "file_unique_id": file_id,
+        "file_name": file_name,
+        "file_size": size,
+    });
+    serde_json::from_value(json).unwrap()
+}
+
+fn message_json(user_id: i64, text: &str) -> Value {
+    json!({
+        "message_id": 1,
+        "date": 0,
+        "chat": {"id": user_id, "type": "private", "first_name": "Test"},
+        "from": {"id": user_id, "is_bot": false, "first_name": "Test"},
+        "text": text,
+    })
+}
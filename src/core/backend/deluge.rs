@@ -0,0 +1,476 @@
+use async_trait::async_trait;
+use reqwest::{header, Client};
+use serde_json::{json, Value};
+use transmission_rpc::types::{
+    ErrorType, Id, SessionSetArgs, SessionStats, Torrent, TorrentAction, TorrentAddArgs,
+    TorrentAddedOrDuplicate, TorrentGetField, TorrentSetArgs, TorrentStatus,
+};
+use url::Url;
+use uuid::Uuid;
+
+use crate::core::magnet::MagnetLink;
+use crate::core::torrent::TorrentMeta;
+
+use super::{hashes, track, BackendResult, TorrentBackend};
+
+const TORRENT_KEYS: [&str; 15] = [
+    "name", "progress", "state", "message", "download_payload_rate", "upload_payload_rate", "eta",
+    "download_location", "save_path", "total_wanted", "total_done", "total_size", "files", "file_progress",
+    "file_priorities",
+];
+
+/// Deluge web JSON-RPC. The web ui logs in with the password only
+/// and has to be connected to a daemon, the first known one is used
+pub struct DelugeBackend {
+    client: Client,
+    url: Url,
+    password: String,
+    cookie: Option<String>,
+    request_id: u64,
+    server_id: Option<Uuid>,
+}
+
+impl DelugeBackend {
+    pub fn new(url: Url, password: String, client: Client, server_id: Option<Uuid>) -> Self {
+        DelugeBackend {
+            client,
+            url,
+            password,
+            cookie: None,
+            request_id: 0,
+            server_id,
+        }
+    }
+
+    async fn call(&mut self, method: &str, params: Value) -> BackendResult<Value> {
+        self.request_id += 1;
+        let mut request = self.client
+            .post(self.url.clone())
+            .json(&json!({"method": method, "params": params, "id": self.request_id}));
+        if let Some(cookie) = &self.cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let response = request.send().await?.error_for_status()?;
+        let cookie = response.headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find(|value| value.starts_with("_session_id="))
+            .and_then(|value| value.split(';').next())
+            .map(str::to_owned);
+        if cookie.is_some() {
+            self.cookie = cookie;
+        }
+        let mut body: Value = response.json().await?;
+        match body["error"].take() {
+            Value::Null => Ok(body["result"].take()),
+            error => Err(error["message"].as_str().unwrap_or("Deluge call failed").to_owned().into()),
+        }
+    }
+
+    async fn login(&mut self) -> BackendResult<()> {
+        let password = self.password.clone();
+        if self.call("auth.login", json!([password])).await? != json!(true) {
+            return Err("Deluge didn't accept the password".into());
+        }
+        if self.call("web.connected", json!([])).await? == json!(true) {
+            return Ok(());
+        }
+        let hosts = self.call("web.get_hosts", json!([])).await?;
+        let host = match hosts[0][0].as_str() {
+            Some(host) => host.to_owned(),
+            None => return Err("Deluge web ui knows no daemon".into()),
+        };
+        self.call("web.connect", json!([host])).await?;
+        Ok(())
+    }
+
+    /// Calls the daemon, logs in first or again when the session has expired
+    async fn daemon(&mut self, method: &str, params: Value) -> BackendResult<Value> {
+        if self.cookie.is_none() {
+            self.login().await?;
+        }
+        match self.call(method, params.clone()).await {
+            Err(err) if err.to_string().contains("Not authenticated") => {
+                self.login().await?;
+                self.call(method, params).await
+            }
+            result => result,
+        }
+    }
+
+    async fn torrents(&mut self, hashes: Vec<String>) -> BackendResult<Vec<Torrent>> {
+        let filter = match hashes.is_empty() {
+            true => json!({}),
+            false => json!({"id": hashes}),
+        };
+        let found = self.daemon("core.get_torrents_status", json!([filter, TORRENT_KEYS])).await?;
+        let mut torrents = vec![];
+        for (hash, torrent) in found.as_object().cloned().unwrap_or_default() {
+            torrents.push(to_torrent(&hash, &torrent)?);
+        }
+        Ok(torrents)
+    }
+
+    async fn stats(&mut self) -> BackendResult<SessionStats> {
+        let status = self.daemon("core.get_session_status", json!([[
+            "payload_download_rate", "payload_upload_rate", "total_payload_download", "total_payload_upload",
+        ]])).await?;
+        let torrents = self.daemon("core.get_torrents_status", json!([{}, ["state"]])).await?;
+        let states: Vec<TorrentStatus> = torrents.as_object().cloned().unwrap_or_default()
+            .values()
+            .map(|torrent| to_status(torrent["state"].as_str().unwrap_or_default()))
+            .collect();
+        let stats = json!({
+            "filesAdded": 0,
+            "downloadedBytes": status["total_payload_download"].as_f64().unwrap_or_default() as i64,
+            "uploadedBytes": status["total_payload_upload"].as_f64().unwrap_or_default() as i64,
+            "secondsActive": 0,
+        });
+        Ok(serde_json::from_value(json!({
+            "torrentCount": states.len(),
+            "activeTorrentCount": states.iter()
+                .filter(|status| matches!(status, TorrentStatus::Downloading | TorrentStatus::Seeding))
+                .count(),
+            "pausedTorrentCount": states.iter().filter(|status| matches!(status, TorrentStatus::Stopped)).count(),
+            "downloadSpeed": status["payload_download_rate"].as_f64().unwrap_or_default() as i64,
+            "uploadSpeed": status["payload_upload_rate"].as_f64().unwrap_or_default() as i64,
+            // Deluge keeps no totals across restarts
+            "current-stats": stats,
+            "cumulative-stats": stats,
+        }))?)
+    }
+
+    async fn set_session(&mut self, args: SessionSetArgs) -> BackendResult<()> {
+        if args.alt_speed_enabled.is_some() {
+            return Err("Deluge has no alternative speed limits".into());
+        }
+        // the limit is in KiB/s, -1 for no limit
+        let limit = |enabled: Option<bool>, limit: Option<i32>| match (enabled, limit) {
+            (Some(false), _) => Some(-1),
+            (_, Some(limit)) => Some(limit),
+            (Some(true), None) | (None, None) => None,
+        };
+        let mut config = serde_json::Map::new();
+        if let Some(limit) = limit(args.speed_limit_down_enabled, args.speed_limit_down) {
+            config.insert("max_download_speed".to_owned(), json!(limit));
+        }
+        if let Some(limit) = limit(args.speed_limit_up_enabled, args.speed_limit_up) {
+            config.insert("max_upload_speed".to_owned(), json!(limit));
+        }
+        if !config.is_empty() {
+            self.daemon("core.set_config", json!([config])).await?;
+        }
+        Ok(())
+    }
+
+    async fn add(&mut self, args: TorrentAddArgs) -> BackendResult<TorrentAddedOrDuplicate> {
+        let link = match (&args.filename, &args.metainfo) {
+            (Some(filename), _) => MagnetLink::find(filename),
+            (None, Some(metainfo)) => {
+                let data = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, metainfo)?;
+                Some(TorrentMeta::from_bytes(&data)?.to_magnet())
+            }
+            (None, None) => return Err("Metainfo or filename should be provided".into()),
+        };
+        let hash = link.clone().map(|link| link.hash().to_lowercase());
+        if let Some(hash) = &hash {
+            if let Some(torrent) = self.torrents(vec![hash.clone()]).await?.into_iter().next() {
+                return Ok(TorrentAddedOrDuplicate::TorrentDuplicate(torrent));
+            }
+        }
+        let mut options = json!({});
+        if let Some(dir) = &args.download_dir {
+            options["download_location"] = json!(dir);
+        }
+        let added = match (&args.filename, &args.metainfo) {
+            (Some(filename), _) => self.daemon("core.add_torrent_magnet", json!([filename, options])).await?,
+            (None, metainfo) => self.daemon("core.add_torrent_file", json!(["file.torrent", metainfo, options])).await?,
+        };
+        let hash = added.as_str().map(str::to_owned).or(hash);
+        let torrent = match &hash {
+            Some(hash) => self.torrents(vec![hash.clone()]).await?.into_iter().next(),
+            None => None,
+        };
+        let torrent = match torrent {
+            Some(torrent) => torrent,
+            None => serde_json::from_value(json!({
+                "hashString": hash,
+                "name": link.map(|link| link.dn()),
+            }))?,
+        };
+        Ok(TorrentAddedOrDuplicate::TorrentAdded(torrent))
+    }
+
+    async fn remove(&mut self, hashes: Vec<String>, delete_files: bool) -> BackendResult<()> {
+        for hash in hashes {
+            self.daemon("core.remove_torrent", json!([hash, delete_files])).await?;
+        }
+        Ok(())
+    }
+
+    async fn set(&mut self, args: TorrentSetArgs, hashes: Vec<String>) -> BackendResult<()> {
+        if args.queue_position == Some(0) {
+            self.daemon("core.queue_top", json!([hashes])).await?;
+        }
+        // the priorities of Deluge 2: 0 skips the file, 1 low, 4 normal, 7 high
+        let priorities = [
+            (args.files_unwanted, 0),
+            (args.files_wanted, 4),
+            (args.priority_low, 1),
+            (args.priority_normal, 4),
+            (args.priority_high, 7),
+        ];
+        if priorities.iter().all(|(indexes, _)| indexes.as_ref().is_none_or(Vec::is_empty)) {
+            return Ok(());
+        }
+        for hash in hashes {
+            let status = self.daemon("core.get_torrent_status", json!([hash, ["file_priorities"]])).await?;
+            let mut current: Vec<Value> = status["file_priorities"].as_array().cloned().unwrap_or_default();
+            for (indexes, priority) in &priorities {
+                for index in indexes.iter().flatten() {
+                    if let Some(current) = current.get_mut(*index as usize) {
+                        *current = json!(priority);
+                    }
+                }
+            }
+            self.daemon("core.set_torrent_options", json!([[hash], {"file_priorities": current}])).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TorrentBackend for DelugeBackend {
+    async fn version(&mut self) -> BackendResult<String> {
+        let result = self.daemon("daemon.info", json!([])).await
+            .map(|info| info.as_str().unwrap_or_default().to_owned());
+        track(&self.server_id, result)
+    }
+
+    async fn session_stats(&mut self) -> BackendResult<SessionStats> {
+        let result = self.stats().await;
+        track(&self.server_id, result)
+    }
+
+    async fn session_set(&mut self, args: SessionSetArgs) -> BackendResult<()> {
+        let result = self.set_session(args).await;
+        track(&self.server_id, result)
+    }
+
+    async fn free_space(&mut self, path: &str) -> BackendResult<i64> {
+        let result = self.daemon("core.get_free_space", json!([path])).await
+            .and_then(|space| space.as_i64().ok_or_else(|| "Deluge didn't tell the free space".into()));
+        track(&self.server_id, result)
+    }
+
+    /// Deluge returns all the fields anyway
+    async fn torrent_get(&mut self, _fields: Option<Vec<TorrentGetField>>, ids: Vec<Id>) -> BackendResult<Vec<Torrent>> {
+        let result = self.torrents(hashes(&ids)).await;
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_add(&mut self, args: TorrentAddArgs) -> BackendResult<TorrentAddedOrDuplicate> {
+        let result = self.add(args).await;
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_remove(&mut self, ids: Vec<Id>, delete_files: bool) -> BackendResult<()> {
+        let result = self.remove(hashes(&ids), delete_files).await;
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_action(&mut self, action: TorrentAction, ids: Vec<Id>) -> BackendResult<()> {
+        let method = match action {
+            TorrentAction::Start | TorrentAction::StartNow => "core.resume_torrents",
+            TorrentAction::Stop => "core.pause_torrents",
+            TorrentAction::Verify => "core.force_recheck",
+            TorrentAction::Reannounce => "core.force_reannounce",
+        };
+        let result = self.daemon(method, json!([hashes(&ids)])).await.map(|_| ());
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_set(&mut self, args: TorrentSetArgs, ids: Vec<Id>) -> BackendResult<()> {
+        let result = self.set(args, hashes(&ids)).await;
+        track(&self.server_id, result)
+    }
+}
+
+fn to_status(state: &str) -> TorrentStatus {
+    match state {
+        "Paused" | "Error" => TorrentStatus::Stopped,
+        "Checking" => TorrentStatus::Verifying,
+        "Queued" => TorrentStatus::QueuedToDownload,
+        "Seeding" => TorrentStatus::Seeding,
+        _ => TorrentStatus::Downloading,
+    }
+}
+
+/// The torrent as Transmission would describe it
+fn to_torrent(hash: &str, torrent: &Value) -> BackendResult<Torrent> {
+    let state = torrent["state"].as_str().unwrap_or_default();
+    let error = match state {
+        "Error" => ErrorType::LocalError,
+        _ => ErrorType::Ok,
+    };
+    let int = |key: &str| torrent[key].as_f64().unwrap_or_default() as i64;
+    let files = torrent["files"].as_array().cloned().unwrap_or_default();
+    let progress = torrent["file_progress"].as_array().cloned().unwrap_or_default();
+    let priorities = torrent["file_priorities"].as_array().cloned().unwrap_or_default();
+    let priority = |index: usize| priorities.get(index).and_then(Value::as_i64).unwrap_or(4);
+    let completed = |index: usize, file: &Value| {
+        (file["size"].as_f64().unwrap_or_default() * progress.get(index).and_then(Value::as_f64).unwrap_or_default()) as i64
+    };
+    let download_dir = match &torrent["download_location"] {
+        Value::String(location) => location.clone(),
+        _ => torrent["save_path"].as_str().unwrap_or_default().to_owned(),
+    };
+    // Deluge has no eta for a stalled download, Transmission uses -1
+    let eta = match int("eta") {
+        0 if state == "Downloading" => -1,
+        eta => eta,
+    };
+    Ok(serde_json::from_value(json!({
+        "hashString": hash,
+        "name": torrent["name"],
+        "percentDone": torrent["progress"].as_f64().unwrap_or_default() / 100.0,
+        "status": to_status(state) as u8,
+        "error": error as u8,
+        "errorString": match error {
+            ErrorType::LocalError => torrent["message"].as_str().unwrap_or_default(),
+            _ => "",
+        },
+        "rateDownload": int("download_payload_rate"),
+        "rateUpload": int("upload_payload_rate"),
+        "eta": eta,
+        "downloadDir": download_dir,
+        "leftUntilDone": int("total_wanted") - int("total_done"),
+        "totalSize": int("total_size"),
+        "metadataPercentComplete": 1.0,
+        "files": files.iter().enumerate().map(|(index, file)| json!({
+            "name": file["path"],
+            "length": file["size"],
+            "bytesCompleted": completed(index, file),
+        })).collect::<Vec<_>>(),
+        "fileStats": files.iter().enumerate().map(|(index, file)| json!({
+            "bytesCompleted": completed(index, file),
+            "wanted": priority(index) > 0,
+            "priority": match priority(index) {
+                0 | 4 => 0,
+                1..=3 => -1,
+                _ => 1,
+            },
+        })).collect::<Vec<_>>(),
+    }))?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::post;
+    use axum::{Json, Router};
+
+    use super::*;
+
+    const HASH: &str = "c9e15763f722f23e98a29decdfae341b98d53056";
+
+    #[derive(Default)]
+    struct FakeState {
+        connected: bool,
+        calls: Vec<String>,
+    }
+
+    /// The web ui with the password "secret" and one daemon, which is not connected at first
+    async fn fake_deluge() -> (Url, Arc<Mutex<FakeState>>) {
+        let state = Arc::new(Mutex::new(FakeState::default()));
+        let router = Router::new().route("/json", post(rpc)).with_state(state.clone());
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+        let url = format!("http://{}/json", server.local_addr()).parse().unwrap();
+        tokio::spawn(server);
+        (url, state)
+    }
+
+    async fn rpc(State(state): State<Arc<Mutex<FakeState>>>, headers: HeaderMap, Json(request): Json<Value>) -> Response {
+        let mut state = state.lock().unwrap();
+        let method = request["method"].as_str().unwrap_or_default().to_owned();
+        state.calls.push(method.clone());
+        let reply = |result: Value| Json(json!({"id": request["id"], "result": result, "error": null}));
+        if method == "auth.login" {
+            return match request["params"][0] == json!("secret") {
+                true => ([("Set-Cookie", "_session_id=abc; Path=/json")], reply(json!(true))).into_response(),
+                false => reply(json!(false)).into_response(),
+            };
+        }
+        if headers.get("Cookie").and_then(|value| value.to_str().ok()) != Some("_session_id=abc") {
+            return Json(json!({"id": request["id"], "result": null, "error": {"message": "Not authenticated", "code": 1}}))
+                .into_response();
+        }
+        let result = match method.as_str() {
+            "web.connected" => json!(state.connected),
+            "web.get_hosts" => json!([["host1", "127.0.0.1", 58846, "localclient"]]),
+            "web.connect" => {
+                state.connected = true;
+                json!(["core.get_torrents_status"])
+            }
+            "daemon.info" => json!("2.1.1"),
+            "core.get_torrents_status" => {
+                assert_eq!(request["params"][0], json!({"id": [HASH]}));
+                json!({HASH: {
+                    "name": "Test",
+                    "progress": 25.0,
+                    "state": "Downloading",
+                    "download_payload_rate": 1024,
+                    "upload_payload_rate": 0,
+                    "eta": 0,
+                    "download_location": "/movies",
+                    "total_wanted": 400,
+                    "total_done": 100,
+                    "total_size": 400,
+                    "files": [{"index": 0, "path": "Test/a.mkv", "size": 400}],
+                    "file_progress": [0.25],
+                    "file_priorities": [7],
+                }})
+            }
+            _ => json!(null),
+        };
+        reply(result).into_response()
+    }
+
+    #[tokio::test]
+    async fn test_login_and_connect() {
+        let (url, state) = fake_deluge().await;
+        assert!(DelugeBackend::new(url.clone(), "wrong".to_owned(), Client::new(), None).version().await.is_err());
+
+        let mut backend = DelugeBackend::new(url, "secret".to_owned(), Client::new(), None);
+        assert_eq!(backend.version().await.unwrap(), "2.1.1");
+        assert_eq!(
+            state.lock().unwrap().calls,
+            vec!["auth.login", "auth.login", "web.connected", "web.get_hosts", "web.connect", "daemon.info"]
+        );
+
+        let torrents = backend.torrent_get(None, vec![Id::Hash(HASH.to_uppercase())]).await.unwrap();
+        assert_eq!(torrents.len(), 1);
+        let torrent = &torrents[0];
+        assert_eq!(torrent.hash_string.as_deref(), Some(HASH));
+        assert_eq!(torrent.percent_done, Some(0.25));
+        assert_eq!(torrent.status, Some(TorrentStatus::Downloading));
+        assert_eq!(torrent.eta, Some(-1));
+        assert_eq!(torrent.left_until_done, Some(300));
+        assert_eq!(torrent.download_dir.as_deref(), Some("/movies"));
+        assert_eq!(torrent.files.as_ref().unwrap()[0].bytes_completed, 100);
+        assert_eq!(torrent.file_stats.as_ref().unwrap()[0].priority, 1);
+    }
+
+    #[tokio::test]
+    async fn test_no_turtle_mode() {
+        let (url, _) = fake_deluge().await;
+        let mut backend = DelugeBackend::new(url, "secret".to_owned(), Client::new(), None);
+        let args = SessionSetArgs { alt_speed_enabled: Some(true), ..SessionSetArgs::default() };
+        assert!(backend.session_set(args).await.is_err());
+    }
+}
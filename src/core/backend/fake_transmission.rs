@@ -0,0 +1,240 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::engine::general_purpose;
+use base64::Engine;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::core::magnet::MagnetLink;
+use crate::core::torrent::TorrentMeta;
+
+const SESSION_HEADER: &str = "X-Transmission-Session-Id";
+pub const VERSION: &str = "4.0.5 (fake)";
+pub const FREE_SPACE: i64 = 100 * 1024 * 1024 * 1024;
+
+/// A local Transmission RPC server. It asks for the session id like the real one does,
+/// checks the basic auth when it has credentials and keeps the added torrents in memory
+#[derive(Clone)]
+pub struct FakeTransmission {
+    state: Arc<Mutex<FakeState>>,
+    base_url: String,
+}
+
+struct FakeState {
+    session_id: String,
+    credentials: Option<(String, String)>,
+    conflicts: usize,
+    torrents: Vec<Value>,
+    session: Value,
+}
+
+impl FakeTransmission {
+    pub async fn start(credentials: Option<(&str, &str)>) -> FakeTransmission {
+        let state = Arc::new(Mutex::new(FakeState {
+            session_id: Uuid::new_v4().to_string(),
+            credentials: credentials.map(|(user, password)| (user.to_owned(), password.to_owned())),
+            conflicts: 0,
+            torrents: vec![],
+            session: json!({}),
+        }));
+        let router = Router::new()
+            .route("/transmission/rpc", post(rpc))
+            .with_state(state.clone());
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+        let base_url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        FakeTransmission { state, base_url }
+    }
+
+    /// The url without the rpc path, as the servers are stored
+    pub fn base_url(&self) -> String {
+        self.base_url.clone()
+    }
+
+    /// How many times a request came without the current session id
+    pub fn conflicts(&self) -> usize {
+        self.state().conflicts
+    }
+
+    pub fn torrents(&self) -> Vec<Value> {
+        self.state().torrents.clone()
+    }
+
+    /// Settings changed with `session-set`
+    pub fn session(&self) -> Value {
+        self.state().session.clone()
+    }
+
+    /// Overrides fields of the torrent, e.g. to make progress
+    pub fn update_torrent(&self, hash: &str, fields: Value) {
+        let mut state = self.state();
+        if let Some(torrent) = state.torrents.iter_mut().find(|torrent| has_id(torrent, &json!(hash))) {
+            for (key, value) in fields.as_object().unwrap() {
+                torrent[key] = value.clone();
+            }
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap()
+    }
+}
+
+async fn rpc(
+    State(state): State<Arc<Mutex<FakeState>>>,
+    headers: HeaderMap,
+    Json(request): Json<Value>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some((user, password)) = &state.credentials {
+        let expected = format!("Basic {}", general_purpose::STANDARD.encode(format!("{}:{}", user, password)));
+        if headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()) != Some(&expected) {
+            return (StatusCode::UNAUTHORIZED, "401: Unauthorized").into_response();
+        }
+    }
+    if headers.get(SESSION_HEADER).and_then(|value| value.to_str().ok()) != Some(&state.session_id) {
+        state.conflicts += 1;
+        return (StatusCode::CONFLICT, [(SESSION_HEADER, state.session_id.clone())], "409: Conflict").into_response();
+    }
+    let arguments = &request["arguments"];
+    let ids = arguments["ids"].as_array().cloned().unwrap_or_default();
+    let selected = |torrent: &Value| ids.is_empty() || ids.iter().any(|id| has_id(torrent, id));
+    let result = match request["method"].as_str().unwrap_or_default() {
+        "session-get" => json!({
+            "blocklist-enabled": false,
+            "download-dir": "/downloads",
+            "encryption": "preferred",
+            "rpc-version": 17,
+            "rpc-version-minimum": 14,
+            "version": VERSION,
+        }),
+        "session-set" => {
+            for (key, value) in arguments.as_object().cloned().unwrap_or_default() {
+                state.session[key] = value;
+            }
+            json!({})
+        }
+        "session-stats" => {
+            let stats = json!({
+                "filesAdded": state.torrents.len(),
+                "downloadedBytes": 0,
+                "uploadedBytes": 0,
+                "secondsActive": 0,
+            });
+            json!({
+                "torrentCount": state.torrents.len(),
+                "activeTorrentCount": state.torrents.len(),
+                "pausedTorrentCount": 0,
+                "downloadSpeed": 0,
+                "uploadSpeed": 0,
+                "current-stats": stats,
+                "cumulative-stats": stats,
+            })
+        }
+        "free-space" => json!({"path": arguments["path"], "size-bytes": FREE_SPACE}),
+        "torrent-get" => json!({"torrents": state.torrents.iter().filter(|torrent| selected(torrent)).collect::<Vec<_>>()}),
+        "torrent-add" => match added_link(arguments) {
+            Some(link) => {
+                let hash = link.clone().hash().to_lowercase();
+                match state.torrents.iter().find(|torrent| has_id(torrent, &json!(hash))) {
+                    Some(torrent) => json!({"torrent-duplicate": torrent}),
+                    None => {
+                        let mut torrent = json!({
+                            "id": state.torrents.len() + 1,
+                            "hashString": hash,
+                            "name": link.dn(),
+                            "downloadDir": arguments["download-dir"],
+                            "percentDone": 0.0,
+                            "metadataPercentComplete": 0.0,
+                            "status": 4,
+                            "error": 0,
+                        });
+                        // the files of a torrent file are known right away, of a magnet only with the metadata
+                        if let Some(meta) = added_meta(arguments) {
+                            torrent["files"] = meta.files.iter().map(|file| json!({
+                                "name": if meta.files.len() > 1 { format!("{}/{}", meta.name, file.path) } else { file.path.clone() },
+                                "length": file.length,
+                                "bytesCompleted": 0,
+                            })).collect();
+                            torrent["fileStats"] = meta.files.iter()
+                                .map(|_| json!({"bytesCompleted": 0, "wanted": true, "priority": 0}))
+                                .collect();
+                            torrent["metadataPercentComplete"] = json!(1.0);
+                        }
+                        state.torrents.push(torrent.clone());
+                        json!({"torrent-added": torrent})
+                    }
+                }
+            }
+            None => return rpc_failure("invalid or corrupt torrent file"),
+        },
+        "torrent-remove" => {
+            state.torrents.retain(|torrent| !selected(torrent));
+            json!({})
+        }
+        method @ ("torrent-start" | "torrent-stop" | "torrent-verify" | "torrent-reannounce" | "torrent-set") => {
+            let status = match method {
+                "torrent-stop" => Some(0),
+                "torrent-verify" => Some(2),
+                "torrent-start" => Some(4),
+                _ => None,
+            };
+            for torrent in state.torrents.iter_mut().filter(|torrent| selected(torrent)) {
+                if let Some(status) = status {
+                    torrent["status"] = json!(status);
+                }
+                set_file_stats(torrent, arguments);
+            }
+            json!({})
+        }
+        method => return rpc_failure(&format!("method name not recognized: {}", method)),
+    };
+    Json(json!({"arguments": result, "result": "success"})).into_response()
+}
+
+fn rpc_failure(message: &str) -> Response {
+    Json(json!({"arguments": {}, "result": message})).into_response()
+}
+
+fn added_link(arguments: &Value) -> Option<MagnetLink> {
+    if let Some(filename) = arguments["filename"].as_str() {
+        return MagnetLink::find(&filename.to_owned());
+    }
+    added_meta(arguments).map(|meta| meta.to_magnet())
+}
+
+fn added_meta(arguments: &Value) -> Option<TorrentMeta> {
+    let data = general_purpose::STANDARD.decode(arguments["metainfo"].as_str()?).ok()?;
+    TorrentMeta::from_bytes(&data).ok()
+}
+
+/// Applies the file selection and priorities of `torrent-set`
+fn set_file_stats(torrent: &mut Value, arguments: &Value) {
+    let changes = [
+        ("files-wanted", "wanted", json!(true)),
+        ("files-unwanted", "wanted", json!(false)),
+        ("priority-high", "priority", json!(1)),
+        ("priority-normal", "priority", json!(0)),
+        ("priority-low", "priority", json!(-1)),
+    ];
+    for (argument, field, value) in changes {
+        for index in arguments[argument].as_array().into_iter().flatten().filter_map(Value::as_u64) {
+            if let Some(stat) = torrent["fileStats"].get_mut(index as usize) {
+                stat[field] = value.clone();
+            }
+        }
+    }
+}
+
+/// Ids are either the numeric ids or the hashes
+fn has_id(torrent: &Value, id: &Value) -> bool {
+    match id.as_str() {
+        Some(hash) => torrent["hashString"].as_str().is_some_and(|own| own.eq_ignore_ascii_case(hash)),
+        None => torrent["id"] == *id,
+    }
+}
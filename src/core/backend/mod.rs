@@ -0,0 +1,116 @@
+use async_trait::async_trait;
+use transmission_rpc::types::{
+    Id, SessionSetArgs, SessionStats, Torrent, TorrentAction, TorrentAddArgs, TorrentAddedOrDuplicate,
+    TorrentGetField, TorrentSetArgs,
+};
+use log::warn;
+use uuid::Uuid;
+
+use crate::db::models::server::{Authentication, ServerEndpoint, ServerKind, TlsOptions};
+use crate::metrics;
+
+pub mod deluge;
+pub mod qbittorrent;
+pub mod transmission;
+#[cfg(test)]
+pub(crate) mod fake_transmission;
+
+use deluge::DelugeBackend;
+use qbittorrent::QBittorrentBackend;
+use transmission::TransmissionBackend;
+
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+pub type BackendResult<T> = Result<T, BackendError>;
+
+/// The torrent client of a server. The handlers talk only to this trait,
+/// the Transmission RPC types are the common language of all the clients
+#[async_trait]
+pub trait TorrentBackend: Send {
+    /// Version of the client, doubles as a check of the url and credentials
+    async fn version(&mut self) -> BackendResult<String>;
+    async fn session_stats(&mut self) -> BackendResult<SessionStats>;
+    async fn session_set(&mut self, args: SessionSetArgs) -> BackendResult<()>;
+    /// Free space in bytes of the disk where the path is
+    async fn free_space(&mut self, path: &str) -> BackendResult<i64>;
+
+    /// Torrents with the given ids, all the fields when none are given
+    async fn torrent_get(&mut self, fields: Option<Vec<TorrentGetField>>, ids: Vec<Id>) -> BackendResult<Vec<Torrent>>;
+    async fn torrent_add(&mut self, args: TorrentAddArgs) -> BackendResult<TorrentAddedOrDuplicate>;
+    async fn torrent_remove(&mut self, ids: Vec<Id>, delete_files: bool) -> BackendResult<()>;
+    async fn torrent_action(&mut self, action: TorrentAction, ids: Vec<Id>) -> BackendResult<()>;
+    async fn torrent_set(&mut self, args: TorrentSetArgs, ids: Vec<Id>) -> BackendResult<()>;
+}
+
+/// The backend of the client at the endpoint.
+/// Failed calls are counted per server, servers which are not saved yet are not counted
+pub fn connect(
+    endpoint: &ServerEndpoint,
+    auth: Option<Authentication>,
+    server_id: Option<Uuid>,
+) -> Box<dyn TorrentBackend> {
+    let url = endpoint.url.clone();
+    match endpoint.kind {
+        ServerKind::Transmission => Box::new(TransmissionBackend::new(url, auth, rpc_client(&endpoint.tls), server_id)),
+        ServerKind::QBittorrent => Box::new(QBittorrentBackend::new(url, auth, http_client(&endpoint.tls), server_id)),
+        // the web ui of Deluge asks only for the password
+        ServerKind::Deluge => Box::new(DelugeBackend::new(
+            url,
+            auth.map(|auth| auth.password).unwrap_or_default(),
+            http_client(&endpoint.tls),
+            server_id,
+        )),
+    }
+}
+
+/// Sets up the certificate checks of a client builder of either reqwest version
+macro_rules! with_tls {
+    ($builder:expr, $certificate:ty, $tls:expr) => {
+        match $tls {
+            TlsOptions::Verify => $builder.build(),
+            TlsOptions::AcceptInvalid => $builder.danger_accept_invalid_certs(true).build(),
+            TlsOptions::PinnedCa(pem) => <$certificate>::from_pem(pem.as_bytes()).and_then(|ca| {
+                $builder.tls_built_in_root_certs(false).add_root_certificate(ca).build()
+            }),
+        }
+    };
+}
+
+/// Whether the clients can be set up with the options, e.g. the pinned certificate is readable
+pub fn check_tls(tls: &TlsOptions) -> BackendResult<()> {
+    with_tls!(reqwest::Client::builder().use_rustls_tls(), reqwest::Certificate, tls)?;
+    with_tls!(rpc_reqwest::Client::builder().use_rustls_tls(), rpc_reqwest::Certificate, tls)?;
+    Ok(())
+}
+
+/// The options are checked when the server is saved, the default checks are the safe fallback
+fn http_client(tls: &TlsOptions) -> reqwest::Client {
+    with_tls!(reqwest::Client::builder().use_rustls_tls(), reqwest::Certificate, tls).unwrap_or_else(|err| {
+        warn!("Falling back to the default certificate checks: {}", err);
+        reqwest::Client::new()
+    })
+}
+
+fn rpc_client(tls: &TlsOptions) -> rpc_reqwest::Client {
+    with_tls!(rpc_reqwest::Client::builder().use_rustls_tls(), rpc_reqwest::Certificate, tls).unwrap_or_else(|err| {
+        warn!("Falling back to the default certificate checks: {}", err);
+        rpc_reqwest::Client::new()
+    })
+}
+
+/// Counts the failed call of the server
+fn track<T>(server_id: &Option<Uuid>, result: BackendResult<T>) -> BackendResult<T> {
+    if let (Err(_), Some(server_id)) = (&result, server_id) {
+        metrics::rpc_error(server_id);
+    }
+    result
+}
+
+/// Lowercase hashes of the ids, the numeric ids are known only to Transmission
+fn hashes(ids: &[Id]) -> Vec<String> {
+    ids.iter()
+        .filter_map(|id| match id {
+            Id::Hash(hash) => Some(hash.to_lowercase()),
+            Id::Id(_) => None,
+        })
+        .collect()
+}
@@ -0,0 +1,546 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose;
+use base64::Engine;
+use reqwest::{header, multipart, Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use transmission_rpc::types::{
+    ErrorType, Id, SessionSetArgs, SessionStats, Torrent, TorrentAction, TorrentAddArgs,
+    TorrentAddedOrDuplicate, TorrentGetField, TorrentSetArgs, TorrentStatus,
+};
+use url::Url;
+use uuid::Uuid;
+
+use crate::core::magnet::MagnetLink;
+use crate::core::torrent::TorrentMeta;
+use crate::db::models::server::Authentication;
+
+use super::{hashes, track, BackendResult, TorrentBackend};
+
+/// qBittorrent reports this eta when the download is not going to finish
+const INFINITE_ETA: i64 = 8640000;
+
+/// qBittorrent Web API, the session is the `SID` cookie of the login
+pub struct QBittorrentBackend {
+    client: Client,
+    url: String,
+    auth: Option<Authentication>,
+    cookie: Option<String>,
+    server_id: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+struct QbTorrent {
+    hash: String,
+    name: String,
+    progress: f32,
+    state: String,
+    dlspeed: i64,
+    upspeed: i64,
+    eta: i64,
+    save_path: String,
+    amount_left: i64,
+    total_size: i64,
+}
+
+#[derive(Deserialize)]
+struct QbFile {
+    name: String,
+    size: i64,
+    progress: f32,
+    priority: i8,
+}
+
+#[derive(Deserialize)]
+struct MainData {
+    server_state: ServerState,
+    #[serde(default)]
+    torrents: serde_json::Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct ServerState {
+    dl_info_speed: i64,
+    up_info_speed: i64,
+    dl_info_data: i64,
+    up_info_data: i64,
+    #[serde(default)]
+    alltime_dl: i64,
+    #[serde(default)]
+    alltime_ul: i64,
+    free_space_on_disk: i64,
+}
+
+impl QBittorrentBackend {
+    pub fn new(url: Url, auth: Option<Authentication>, client: Client, server_id: Option<Uuid>) -> Self {
+        QBittorrentBackend {
+            client,
+            url: url.to_string().trim_end_matches('/').to_owned(),
+            auth,
+            cookie: None,
+            server_id,
+        }
+    }
+
+    /// Without credentials the client is expected to trust the bot, e.g. by its subnet
+    async fn login(&mut self) -> BackendResult<()> {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return Err("qBittorrent asks for credentials".into()),
+        };
+        let response = self.client
+            .post(format!("{}/auth/login", self.url))
+            .form(&[("username", &auth.username), ("password", &auth.password)])
+            .send()
+            .await?
+            .error_for_status()?;
+        let cookie = response.headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find(|value| value.starts_with("SID="))
+            .and_then(|value| value.split(';').next())
+            .map(str::to_owned);
+        match (response.text().await?.trim(), cookie) {
+            ("Ok.", Some(cookie)) => {
+                self.cookie = Some(cookie);
+                Ok(())
+            }
+            _ => Err("qBittorrent didn't accept the credentials".into()),
+        }
+    }
+
+    /// Sends the request with the session cookie, logs in again when the session has expired
+    async fn send(&mut self, build: impl Fn(&Client) -> RequestBuilder) -> BackendResult<Response> {
+        if self.cookie.is_none() && self.auth.is_some() {
+            self.login().await?;
+        }
+        let mut response = self.request(&build).send().await?;
+        if response.status() == StatusCode::FORBIDDEN {
+            self.login().await?;
+            response = self.request(&build).send().await?;
+        }
+        Ok(response.error_for_status()?)
+    }
+
+    fn request(&self, build: &impl Fn(&Client) -> RequestBuilder) -> RequestBuilder {
+        match &self.cookie {
+            Some(cookie) => build(&self.client).header(header::COOKIE, cookie),
+            None => build(&self.client),
+        }
+    }
+
+    async fn get(&mut self, path: &str, query: &[(&str, String)]) -> BackendResult<Response> {
+        let url = format!("{}/{}", self.url, path);
+        self.send(|client| client.get(&url).query(query)).await
+    }
+
+    async fn post(&mut self, path: &str, form: &[(&str, String)]) -> BackendResult<Response> {
+        let url = format!("{}/{}", self.url, path);
+        self.send(|client| client.post(&url).form(form)).await
+    }
+
+    async fn main_data(&mut self) -> BackendResult<MainData> {
+        Ok(self.get("sync/maindata", &[]).await?.json().await?)
+    }
+
+    async fn torrents(&mut self, hashes: &[String], with_files: bool) -> BackendResult<Vec<Torrent>> {
+        let query = match hashes.is_empty() {
+            true => vec![],
+            false => vec![("hashes", hashes.join("|"))],
+        };
+        let found: Vec<QbTorrent> = self.get("torrents/info", &query).await?.json().await?;
+        let mut torrents = vec![];
+        for torrent in found {
+            let files = match with_files {
+                true => Some(self.get("torrents/files", &[("hash", torrent.hash.clone())]).await?.json().await?),
+                false => None,
+            };
+            torrents.push(to_torrent(torrent, files)?);
+        }
+        Ok(torrents)
+    }
+
+    async fn speed_limit(&mut self, path: &str, enabled: Option<bool>, limit: Option<i32>) -> BackendResult<()> {
+        if enabled.is_none() && limit.is_none() {
+            return Ok(());
+        }
+        // the limit is in KB/s, qBittorrent expects bytes/s and 0 for no limit
+        let limit = match enabled {
+            Some(false) => 0,
+            _ => limit.unwrap_or(0) as i64 * 1024,
+        };
+        self.post(path, &[("limit", limit.to_string())]).await?;
+        Ok(())
+    }
+
+    async fn add(&mut self, args: TorrentAddArgs) -> BackendResult<TorrentAddedOrDuplicate> {
+        let (link, data) = match (&args.filename, &args.metainfo) {
+            (Some(filename), _) => (MagnetLink::find(filename), None),
+            (None, Some(metainfo)) => {
+                let data = general_purpose::STANDARD.decode(metainfo)?;
+                (Some(TorrentMeta::from_bytes(&data)?.to_magnet()), Some(data))
+            }
+            (None, None) => return Err("Metainfo or filename should be provided".into()),
+        };
+        let hash = link.clone().map(|link| link.hash().to_lowercase());
+        if let Some(hash) = &hash {
+            if let Some(torrent) = self.torrents(std::slice::from_ref(hash), false).await?.into_iter().next() {
+                return Ok(TorrentAddedOrDuplicate::TorrentDuplicate(torrent));
+            }
+        }
+        let url = format!("{}/torrents/add", self.url);
+        let response = self.send(|client| {
+            let mut form = multipart::Form::new();
+            form = match (&data, &args.filename) {
+                (Some(data), _) => form.part("torrents", multipart::Part::bytes(data.clone()).file_name("file.torrent")),
+                (None, Some(filename)) => form.text("urls", filename.clone()),
+                (None, None) => form,
+            };
+            if let Some(dir) = &args.download_dir {
+                form = form.text("savepath", dir.clone());
+            }
+            client.post(&url).multipart(form)
+        }).await?;
+        if response.text().await?.trim() != "Ok." {
+            return Err("qBittorrent didn't accept the torrent".into());
+        }
+        let added = match &hash {
+            Some(hash) => self.torrents(std::slice::from_ref(hash), false).await?.into_iter().next(),
+            None => None,
+        };
+        // a magnet may be listed only after a while
+        let torrent = match added {
+            Some(torrent) => torrent,
+            None => serde_json::from_value(json!({
+                "hashString": hash,
+                "name": link.map(|link| link.dn()),
+            }))?,
+        };
+        Ok(TorrentAddedOrDuplicate::TorrentAdded(torrent))
+    }
+
+    async fn set(&mut self, args: TorrentSetArgs, hashes: Vec<String>) -> BackendResult<()> {
+        if args.queue_position == Some(0) {
+            self.post("torrents/topPrio", &[("hashes", hashes.join("|"))]).await?;
+        }
+        // qBittorrent has no low priority, 0 skips the file
+        let priorities = [
+            (args.files_unwanted, 0),
+            (args.files_wanted, 1),
+            (args.priority_low, 1),
+            (args.priority_normal, 1),
+            (args.priority_high, 6),
+        ];
+        for (indexes, priority) in priorities {
+            let indexes = match indexes {
+                Some(indexes) if !indexes.is_empty() => indexes,
+                _ => continue,
+            };
+            let ids = indexes.iter().map(i32::to_string).collect::<Vec<_>>().join("|");
+            for hash in &hashes {
+                self.post("torrents/filePrio", &[
+                    ("hash", hash.clone()),
+                    ("id", ids.clone()),
+                    ("priority", priority.to_string()),
+                ]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn action(&mut self, action: TorrentAction, hashes: Vec<String>) -> BackendResult<()> {
+        // qBittorrent 5 renamed pause and resume to stop and start
+        let (path, renamed) = match action {
+            TorrentAction::Start | TorrentAction::StartNow => ("torrents/resume", Some("torrents/start")),
+            TorrentAction::Stop => ("torrents/pause", Some("torrents/stop")),
+            TorrentAction::Verify => ("torrents/recheck", None),
+            TorrentAction::Reannounce => ("torrents/reannounce", None),
+        };
+        let form = [("hashes", hashes.join("|"))];
+        match (self.post(path, &form).await, renamed) {
+            (Err(err), Some(renamed)) if is_not_found(&err) => self.post(renamed, &form).await.map(|_| ()),
+            (result, _) => result.map(|_| ()),
+        }
+    }
+}
+
+#[async_trait]
+impl TorrentBackend for QBittorrentBackend {
+    async fn version(&mut self) -> BackendResult<String> {
+        let result = match self.get("app/version", &[]).await {
+            Ok(response) => response.text().await.map_err(Into::into),
+            Err(err) => Err(err),
+        };
+        track(&self.server_id, result)
+    }
+
+    async fn session_stats(&mut self) -> BackendResult<SessionStats> {
+        let result = match self.main_data().await {
+            Ok(data) => {
+                let states: Vec<TorrentStatus> = data.torrents.values()
+                    .map(|torrent| to_status(torrent["state"].as_str().unwrap_or_default()))
+                    .collect();
+                let server = data.server_state;
+                serde_json::from_value(json!({
+                    "torrentCount": states.len(),
+                    "activeTorrentCount": states.iter()
+                        .filter(|status| matches!(status, TorrentStatus::Downloading | TorrentStatus::Seeding))
+                        .count(),
+                    "pausedTorrentCount": states.iter().filter(|status| matches!(status, TorrentStatus::Stopped)).count(),
+                    "downloadSpeed": server.dl_info_speed,
+                    "uploadSpeed": server.up_info_speed,
+                    "current-stats": stats(server.dl_info_data, server.up_info_data),
+                    "cumulative-stats": stats(server.alltime_dl, server.alltime_ul),
+                })).map_err(Into::into)
+            }
+            Err(err) => Err(err),
+        };
+        track(&self.server_id, result)
+    }
+
+    async fn session_set(&mut self, args: SessionSetArgs) -> BackendResult<()> {
+        let mut result = Ok(());
+        if let Some(enabled) = args.alt_speed_enabled {
+            result = match self.get("transfer/speedLimitsMode", &[]).await {
+                Ok(response) => match response.text().await {
+                    Ok(mode) if (mode.trim() == "1") != enabled => {
+                        self.post("transfer/toggleSpeedLimitsMode", &[]).await.map(|_| ())
+                    }
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.into()),
+                },
+                Err(err) => Err(err),
+            };
+        }
+        if result.is_ok() {
+            result = self.speed_limit("transfer/setDownloadLimit", args.speed_limit_down_enabled, args.speed_limit_down)
+                .await;
+        }
+        if result.is_ok() {
+            result = self.speed_limit("transfer/setUploadLimit", args.speed_limit_up_enabled, args.speed_limit_up)
+                .await;
+        }
+        track(&self.server_id, result)
+    }
+
+    /// qBittorrent tells the free space of the default save path only
+    async fn free_space(&mut self, _path: &str) -> BackendResult<i64> {
+        let result = self.main_data().await.map(|data| data.server_state.free_space_on_disk);
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_get(&mut self, fields: Option<Vec<TorrentGetField>>, ids: Vec<Id>) -> BackendResult<Vec<Torrent>> {
+        let with_files = fields.is_none_or(|fields| fields.iter()
+            .any(|field| matches!(field, TorrentGetField::Files | TorrentGetField::FileStats)));
+        let result = self.torrents(&hashes(&ids), with_files).await;
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_add(&mut self, args: TorrentAddArgs) -> BackendResult<TorrentAddedOrDuplicate> {
+        let result = self.add(args).await;
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_remove(&mut self, ids: Vec<Id>, delete_files: bool) -> BackendResult<()> {
+        let form = [("hashes", hashes(&ids).join("|")), ("deleteFiles", delete_files.to_string())];
+        let result = self.post("torrents/delete", &form).await.map(|_| ());
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_action(&mut self, action: TorrentAction, ids: Vec<Id>) -> BackendResult<()> {
+        let result = self.action(action, hashes(&ids)).await;
+        track(&self.server_id, result)
+    }
+
+    async fn torrent_set(&mut self, args: TorrentSetArgs, ids: Vec<Id>) -> BackendResult<()> {
+        let result = self.set(args, hashes(&ids)).await;
+        track(&self.server_id, result)
+    }
+}
+
+fn is_not_found(err: &super::BackendError) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        .is_some_and(|status| status == StatusCode::NOT_FOUND)
+}
+
+fn stats(downloaded: i64, uploaded: i64) -> Value {
+    json!({
+        "filesAdded": 0,
+        "downloadedBytes": downloaded,
+        "uploadedBytes": uploaded,
+        "secondsActive": 0,
+    })
+}
+
+fn to_status(state: &str) -> TorrentStatus {
+    match state {
+        "pausedDL" | "pausedUP" | "stoppedDL" | "stoppedUP" | "error" | "missingFiles" => TorrentStatus::Stopped,
+        "checkingDL" | "checkingUP" | "checkingResumeData" => TorrentStatus::Verifying,
+        "queuedDL" => TorrentStatus::QueuedToDownload,
+        "queuedUP" => TorrentStatus::QueuedToSeed,
+        "uploading" | "stalledUP" | "forcedUP" => TorrentStatus::Seeding,
+        _ => TorrentStatus::Downloading,
+    }
+}
+
+/// The torrent as Transmission would describe it
+fn to_torrent(torrent: QbTorrent, files: Option<Vec<QbFile>>) -> BackendResult<Torrent> {
+    let error = match torrent.state.as_str() {
+        "error" | "missingFiles" => ErrorType::LocalError,
+        _ => ErrorType::Ok,
+    };
+    let files = files.unwrap_or_default();
+    Ok(serde_json::from_value(json!({
+        "hashString": torrent.hash,
+        "name": torrent.name,
+        "percentDone": torrent.progress,
+        "status": to_status(&torrent.state) as u8,
+        "error": error as u8,
+        "errorString": match error {
+            ErrorType::LocalError => format!("qBittorrent reports {}", torrent.state),
+            _ => String::new(),
+        },
+        "rateDownload": torrent.dlspeed,
+        "rateUpload": torrent.upspeed,
+        "eta": if torrent.eta >= INFINITE_ETA { -1 } else { torrent.eta },
+        "downloadDir": torrent.save_path,
+        "leftUntilDone": torrent.amount_left,
+        "totalSize": torrent.total_size,
+        "metadataPercentComplete": if torrent.state == "metaDL" { 0.0 } else { 1.0 },
+        "files": files.iter().map(|file| json!({
+            "name": file.name,
+            "length": file.size,
+            "bytesCompleted": (file.size as f64 * file.progress as f64) as i64,
+        })).collect::<Vec<_>>(),
+        "fileStats": files.iter().map(|file| json!({
+            "bytesCompleted": (file.size as f64 * file.progress as f64) as i64,
+            "wanted": file.priority > 0,
+            "priority": if file.priority > 1 { 1 } else { 0 },
+        })).collect::<Vec<_>>(),
+    }))?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use axum::extract::{Form, Query, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const HASH: &str = "c9e15763f722f23e98a29decdfae341b98d53056";
+
+    /// Accepts admin:secret and forgets the session after the first torrent request
+    async fn fake_qbittorrent() -> (String, Arc<Mutex<usize>>) {
+        let logins = Arc::new(Mutex::new(0));
+        let router = Router::new()
+            .route("/api/v2/auth/login", post(login))
+            .route("/api/v2/app/version", get(version))
+            .route("/api/v2/torrents/info", get(info))
+            .route("/api/v2/torrents/files", get(files))
+            .with_state(logins.clone());
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, logins)
+    }
+
+    async fn login(State(logins): State<Arc<Mutex<usize>>>, Form(form): Form<HashMap<String, String>>) -> Response {
+        if form.get("username").map(String::as_str) != Some("admin") || form.get("password").map(String::as_str) != Some("secret") {
+            return "Fails.".into_response();
+        }
+        let mut logins = logins.lock().unwrap();
+        *logins += 1;
+        ([("Set-Cookie", format!("SID=session{}; HttpOnly; path=/", logins))], "Ok.").into_response()
+    }
+
+    fn logged_in(headers: &HeaderMap, logins: &Arc<Mutex<usize>>) -> bool {
+        let expected = format!("SID=session{}", logins.lock().unwrap());
+        headers.get("Cookie").and_then(|value| value.to_str().ok()) == Some(expected.as_str())
+    }
+
+    async fn version(State(logins): State<Arc<Mutex<usize>>>, headers: HeaderMap) -> Response {
+        match logged_in(&headers, &logins) {
+            true => "v4.6.0".into_response(),
+            false => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+
+    async fn info(
+        State(logins): State<Arc<Mutex<usize>>>,
+        headers: HeaderMap,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        // the session of the first login expires
+        if !logged_in(&headers, &logins) || *logins.lock().unwrap() < 2 {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        assert_eq!(query.get("hashes").map(String::as_str), Some(HASH));
+        Json(json!([{
+            "hash": HASH,
+            "name": "Test",
+            "progress": 0.25,
+            "state": "stalledDL",
+            "dlspeed": 1024,
+            "upspeed": 0,
+            "eta": INFINITE_ETA,
+            "save_path": "/movies",
+            "amount_left": 300,
+            "total_size": 400,
+        }])).into_response()
+    }
+
+    async fn files() -> Json<Value> {
+        Json(json!([
+            {"name": "Test/a.mkv", "size": 300, "progress": 0.0, "priority": 6},
+            {"name": "Test/b.nfo", "size": 100, "progress": 1.0, "priority": 0},
+        ]))
+    }
+
+    fn backend(url: &str, password: &str) -> QBittorrentBackend {
+        let auth = Authentication { username: "admin".to_owned(), password: password.to_owned() };
+        QBittorrentBackend::new(format!("{}/api/v2", url).parse().unwrap(), Some(auth), Client::new(), None)
+    }
+
+    #[tokio::test]
+    async fn test_cookie_login() {
+        let (url, logins) = fake_qbittorrent().await;
+        assert!(backend(&url, "wrong").version().await.is_err());
+        assert!(QBittorrentBackend::new(format!("{}/api/v2", url).parse().unwrap(), None, Client::new(), None)
+            .version().await.is_err());
+
+        let mut backend = backend(&url, "secret");
+        assert_eq!(backend.version().await.unwrap(), "v4.6.0");
+        assert_eq!(*logins.lock().unwrap(), 1);
+
+        let torrents = backend.torrent_get(None, vec![Id::Hash(HASH.to_uppercase())]).await.unwrap();
+        // logged in again when the session expired
+        assert_eq!(*logins.lock().unwrap(), 2);
+        assert_eq!(torrents.len(), 1);
+        let torrent = &torrents[0];
+        assert_eq!(torrent.hash_string.as_deref(), Some(HASH));
+        assert_eq!(torrent.percent_done, Some(0.25));
+        assert_eq!(torrent.status, Some(TorrentStatus::Downloading));
+        assert_eq!(torrent.eta, Some(-1));
+        assert_eq!(torrent.download_dir.as_deref(), Some("/movies"));
+        let stats = torrent.file_stats.as_ref().unwrap();
+        assert_eq!((stats[0].wanted, stats[0].priority), (true, 1));
+        assert_eq!((stats[1].wanted, stats[1].bytes_completed), (false, 100));
+    }
+
+    #[test]
+    fn test_states() {
+        assert_eq!(to_status("pausedUP"), TorrentStatus::Stopped);
+        assert_eq!(to_status("stoppedDL"), TorrentStatus::Stopped);
+        assert_eq!(to_status("queuedDL"), TorrentStatus::QueuedToDownload);
+        assert_eq!(to_status("forcedUP"), TorrentStatus::Seeding);
+        assert_eq!(to_status("metaDL"), TorrentStatus::Downloading);
+    }
+}
@@ -0,0 +1,168 @@
+use async_trait::async_trait;
+use transmission_rpc::types::{
+    BasicAuth, Id, RpcResponse, RpcResponseArgument, SessionSetArgs, SessionStats, Torrent, TorrentAction,
+    TorrentAddArgs, TorrentAddedOrDuplicate, TorrentGetField, TorrentSetArgs,
+};
+use transmission_rpc::TransClient;
+use url::Url;
+use uuid::Uuid;
+
+use crate::db::models::server::Authentication;
+
+use super::{track, BackendResult, TorrentBackend};
+
+/// Transmission RPC, the session id handshake is done by the client
+pub struct TransmissionBackend {
+    client: TransClient,
+    server_id: Option<Uuid>,
+}
+
+impl TransmissionBackend {
+    pub fn new(url: Url, auth: Option<Authentication>, client: rpc_reqwest::Client, server_id: Option<Uuid>) -> Self {
+        let mut client = TransClient::new_with_client(url, client);
+        if let Some(auth) = auth {
+            client.set_auth(BasicAuth {
+                user: auth.username,
+                password: auth.password,
+            });
+        }
+        TransmissionBackend { client, server_id }
+    }
+
+    /// Arguments of a successful response, a response with any other result is an error too
+    fn arguments<T: RpcResponseArgument>(
+        &self,
+        response: transmission_rpc::types::Result<RpcResponse<T>>,
+    ) -> BackendResult<T> {
+        let arguments = response.and_then(|response| match response.is_ok() {
+            true => Ok(response.arguments),
+            false => Err(response.result.into()),
+        });
+        track(&self.server_id, arguments)
+    }
+}
+
+#[async_trait]
+impl TorrentBackend for TransmissionBackend {
+    async fn version(&mut self) -> BackendResult<String> {
+        let response = self.client.session_get().await;
+        self.arguments(response).map(|session| session.version)
+    }
+
+    async fn session_stats(&mut self) -> BackendResult<SessionStats> {
+        let response = self.client.session_stats().await;
+        self.arguments(response)
+    }
+
+    async fn session_set(&mut self, args: SessionSetArgs) -> BackendResult<()> {
+        let response = self.client.session_set(args).await;
+        self.arguments(response).map(|_| ())
+    }
+
+    async fn free_space(&mut self, path: &str) -> BackendResult<i64> {
+        let response = self.client.free_space(path.to_owned()).await;
+        self.arguments(response).map(|space| space.size_bytes)
+    }
+
+    async fn torrent_get(&mut self, fields: Option<Vec<TorrentGetField>>, ids: Vec<Id>) -> BackendResult<Vec<Torrent>> {
+        let response = self.client.torrent_get(fields, Some(ids)).await;
+        self.arguments(response).map(|torrents| torrents.torrents)
+    }
+
+    async fn torrent_add(&mut self, args: TorrentAddArgs) -> BackendResult<TorrentAddedOrDuplicate> {
+        let response = self.client.torrent_add(args).await;
+        self.arguments(response)
+    }
+
+    async fn torrent_remove(&mut self, ids: Vec<Id>, delete_files: bool) -> BackendResult<()> {
+        let response = self.client.torrent_remove(ids, delete_files).await;
+        self.arguments(response).map(|_| ())
+    }
+
+    async fn torrent_action(&mut self, action: TorrentAction, ids: Vec<Id>) -> BackendResult<()> {
+        let response = self.client.torrent_action(action, ids).await;
+        self.arguments(response).map(|_| ())
+    }
+
+    async fn torrent_set(&mut self, args: TorrentSetArgs, ids: Vec<Id>) -> BackendResult<()> {
+        let response = self.client.torrent_set(args, Some(ids)).await;
+        self.arguments(response).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::core::backend::fake_transmission::{FakeTransmission, FREE_SPACE, VERSION};
+    use crate::core::trans_url::TransUrl;
+    use transmission_rpc::types::TorrentStatus;
+
+    use super::*;
+
+    fn backend(transmission: &FakeTransmission, password: &str) -> TransmissionBackend {
+        let auth = Authentication { username: "admin".to_owned(), password: password.to_owned() };
+        let url = TransUrl::from(transmission.base_url()).to_rpc_url();
+        TransmissionBackend::new(url, Some(auth), rpc_reqwest::Client::new(), None)
+    }
+
+    #[tokio::test]
+    async fn test_session_handshake() {
+        let transmission = FakeTransmission::start(Some(("admin", "secret"))).await;
+        let mut backend = backend(&transmission, "secret");
+
+        assert_eq!(backend.version().await.unwrap(), VERSION);
+        assert_eq!(backend.free_space("/movies").await.unwrap(), FREE_SPACE);
+        // the session id is asked for once and reused afterwards
+        assert_eq!(transmission.conflicts(), 1);
+
+        let args = SessionSetArgs { alt_speed_enabled: Some(true), ..SessionSetArgs::default() };
+        backend.session_set(args).await.unwrap();
+        assert_eq!(transmission.session()["alt-speed-enabled"], json!(true));
+        assert_eq!(backend.session_stats().await.unwrap().torrent_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_credentials() {
+        let transmission = FakeTransmission::start(Some(("admin", "secret"))).await;
+        assert!(backend(&transmission, "wrong").version().await.is_err());
+        assert_eq!(transmission.conflicts(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_torrent_lifecycle() {
+        let transmission = FakeTransmission::start(None).await;
+        let mut backend = TransmissionBackend::new(
+            TransUrl::from(transmission.base_url()).to_rpc_url(),
+            None,
+            rpc_reqwest::Client::new(),
+            None,
+        );
+        let hash = "c9e15763f722f23e98a29decdfae341b98d53056";
+        let add = || TorrentAddArgs {
+            filename: Some(format!("magnet:?xt=urn:btih:{}", hash)),
+            download_dir: Some("/movies".to_owned()),
+            ..TorrentAddArgs::default()
+        };
+        let ids = || vec![Id::Hash(hash.to_owned())];
+
+        assert!(matches!(backend.torrent_add(add()).await.unwrap(), TorrentAddedOrDuplicate::TorrentAdded(_)));
+        assert!(matches!(backend.torrent_add(add()).await.unwrap(), TorrentAddedOrDuplicate::TorrentDuplicate(_)));
+
+        backend.torrent_action(TorrentAction::Stop, ids()).await.unwrap();
+        let torrents = backend.torrent_get(None, ids()).await.unwrap();
+        assert_eq!(torrents.len(), 1);
+        assert_eq!(torrents[0].download_dir.as_deref(), Some("/movies"));
+        assert_eq!(torrents[0].status, Some(TorrentStatus::Stopped));
+
+        backend.torrent_remove(ids(), true).await.unwrap();
+        assert!(backend.torrent_get(None, ids()).await.unwrap().is_empty());
+
+        // a failed rpc result is an error even though the response is fine
+        let broken = TorrentAddArgs {
+            metainfo: Some("bm90IGEgdG9ycmVudA==".to_owned()),
+            ..TorrentAddArgs::default()
+        };
+        assert!(backend.torrent_add(broken).await.is_err());
+    }
+}
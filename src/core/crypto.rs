@@ -6,7 +6,8 @@ use aes_gcm::aes::Aes256;
 
 use base64::Engine;
 use base64::engine::general_purpose;
-use rand::{distributions::Alphanumeric, Rng, thread_rng};
+use rand::{distributions::Alphanumeric, Rng, RngCore, thread_rng};
+use sha1::{Digest, Sha1};
 
 use crate::fromError;
 
@@ -64,21 +65,143 @@ impl Crypto {
         })
     }
 
-    pub fn encrypt(&self, data: &String) -> String {
+    #[allow(dead_code)]
+    pub fn encrypt(&self, data: &str) -> Result<String, CryptoError> {
         let buffer = self.cipher().encrypt(&self.nonce,data.as_bytes())
-            .expect("Encryption was unsuccessful");
-        general_purpose::STANDARD.encode(buffer)
+            .map_err(|_| EncryptionError("Encryption was unsuccessful".to_owned()))?;
+        Ok(general_purpose::STANDARD.encode(buffer))
     }
 
-    pub fn decrypt(&self, data: &String) -> String {
-        let buffer = general_purpose::STANDARD.decode(data)
-            .expect("String was not Base64 encoded");
+    #[allow(dead_code)]
+    pub fn decrypt(&self, data: &str) -> Result<String, CryptoError> {
+        let buffer = decode(data)?;
         let result = self.cipher().decrypt(&self.nonce, buffer.as_slice())
-            .expect("Unable to decrypt");
-        String::from_utf8(result).expect("Unable to write bytes to string")
+            .map_err(|_| AuthenticationError("Unable to decrypt".to_owned()))?;
+        to_string(result)
+    }
+}
+
+const CIPHERTEXT_VERSION: &str = "v2";
+
+/// Encrypts with the current secret and a random nonce per value.
+/// Ciphertexts look like `v2:<key id>:<base64(nonce + data)>`, so they can be decrypted
+/// with one of the previous secrets after a rotation.
+/// Values without the prefix were encrypted with the user salt as the nonce
+pub(crate) struct Keyring {
+    current: KeyEntry,
+    previous: Vec<KeyEntry>,
+}
+
+struct KeyEntry {
+    id: String,
+    cipher: AesGcm<Aes256, U16>,
+    secret: String,
+}
+
+impl KeyEntry {
+    fn new(secret: String) -> Result<Self, CryptoError> {
+        let key_size: usize = AesGcm::<Aes256, U16>::key_size();
+        if secret.len() != key_size {
+            return Err(CryptoError::from(KeySizeError(format!(
+                "Key has wrong length: {}. Expected: {}",
+                secret.len(),
+                key_size
+            ))));
+        }
+        let id = Sha1::digest(secret.as_bytes())
+            .iter()
+            .take(4)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Ok(KeyEntry {
+            id,
+            cipher: AesGcm::new_from_slice(secret.as_bytes()).unwrap(),
+            secret,
+        })
     }
 }
 
+impl Keyring {
+    pub fn new(current: String, previous: Vec<String>) -> Result<Self, CryptoError> {
+        Ok(Keyring {
+            current: KeyEntry::new(current)?,
+            previous: previous.into_iter().map(KeyEntry::new).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// `SECRET` is the current key and `PREVIOUS_SECRETS` is a comma separated list of the rotated ones
+    pub fn from_env() -> Result<Self, CryptoError> {
+        let current = std::env::var("SECRET")
+            .map_err(|_| KeySizeError("SECRET is not set".to_owned()))?;
+        let previous = std::env::var("PREVIOUS_SECRETS")
+            .map(|secrets| secrets.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_owned).collect())
+            .unwrap_or_default();
+        Keyring::new(current, previous)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &KeyEntry> {
+        std::iter::once(&self.current).chain(self.previous.iter())
+    }
+
+    pub fn encrypt(&self, data: &str) -> Result<String, CryptoError> {
+        let mut nonce = Nonce::<U16>::default();
+        thread_rng().fill_bytes(&mut nonce);
+        let buffer = self.current.cipher.encrypt(&nonce, data.as_bytes())
+            .map_err(|_| EncryptionError("Encryption was unsuccessful".to_owned()))?;
+        let payload = [nonce.as_slice(), buffer.as_slice()].concat();
+        Ok(format!("{}:{}:{}", CIPHERTEXT_VERSION, self.current.id, general_purpose::STANDARD.encode(payload)))
+    }
+
+    /// The salt is needed only for the values encrypted before the keyring
+    pub fn decrypt(&self, data: &str, salt: &str) -> Result<String, CryptoError> {
+        match data.split_once(':') {
+            Some((CIPHERTEXT_VERSION, rest)) => {
+                let (key_id, encoded) = rest.split_once(':')
+                    .ok_or_else(|| Base64DecodeError("Ciphertext has no key id".to_owned()))?;
+                let key = self.keys()
+                    .find(|key| key.id == key_id)
+                    .ok_or_else(|| AuthenticationError(format!("Unknown encryption key: {}", key_id)))?;
+                let payload = decode(encoded)?;
+                let nonce_size = Nonce::<U16>::default().len();
+                if payload.len() < nonce_size {
+                    return Err(CryptoError::from(AuthenticationError("Ciphertext is too short".to_owned())));
+                }
+                let (nonce, buffer) = payload.split_at(nonce_size);
+                let result = key.cipher.decrypt(Nonce::<U16>::from_slice(nonce), buffer)
+                    .map_err(|_| AuthenticationError("Unable to decrypt".to_owned()))?;
+                to_string(result)
+            }
+            _ => self.decrypt_legacy(data, salt),
+        }
+    }
+
+    fn decrypt_legacy(&self, data: &str, salt: &str) -> Result<String, CryptoError> {
+        let buffer = decode(data)?;
+        let result = self.keys()
+            .filter_map(|key| Crypto::new(key.secret.clone(), salt.to_owned()).ok())
+            .find_map(|crypto| crypto.cipher().decrypt(&crypto.nonce, buffer.as_slice()).ok())
+            .ok_or_else(|| AuthenticationError("None of the keys can decrypt the value".to_owned()))?;
+        to_string(result)
+    }
+
+    /// Whether the value is already encrypted with a random nonce and the current key
+    pub fn is_current(&self, data: &str) -> bool {
+        data.strip_prefix(CIPHERTEXT_VERSION)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .and_then(|rest| rest.split_once(':'))
+            .is_some_and(|(key_id, _)| key_id == self.current.id)
+    }
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, CryptoError> {
+    general_purpose::STANDARD.decode(data)
+        .map_err(|err| CryptoError::from(Base64DecodeError(err.to_string())))
+}
+
+fn to_string(bytes: Vec<u8>) -> Result<String, CryptoError> {
+    String::from_utf8(bytes).map_err(|err| CryptoError::from(Utf8Error(err.to_string())))
+}
+
 #[derive(Debug)]
 pub struct CryptoError(CryptoErrorKind);
 
@@ -97,11 +220,23 @@ pub struct NonceSizeError(String);
 #[derive(Debug)]
 pub struct Base64DecodeError(String);
 
+#[derive(Debug)]
+pub struct EncryptionError(String);
+
+#[derive(Debug)]
+pub struct AuthenticationError(String);
+
+#[derive(Debug)]
+pub struct Utf8Error(String);
+
 #[derive(Debug)]
 pub(crate) enum CryptoErrorKind {
     KeySizeError(KeySizeError),
     NonceSizeError(NonceSizeError),
     Base64DecodeError(Base64DecodeError),
+    EncryptionError(EncryptionError),
+    AuthenticationError(AuthenticationError),
+    Utf8Error(Utf8Error),
 }
 
 fromError!(KeySizeError, CryptoError, CryptoErrorKind::KeySizeError);
@@ -111,6 +246,9 @@ fromError!(
     CryptoError,
     CryptoErrorKind::Base64DecodeError
 );
+fromError!(EncryptionError, CryptoError, CryptoErrorKind::EncryptionError);
+fromError!(AuthenticationError, CryptoError, CryptoErrorKind::AuthenticationError);
+fromError!(Utf8Error, CryptoError, CryptoErrorKind::Utf8Error);
 
 impl fmt::Display for CryptoError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -120,6 +258,11 @@ impl fmt::Display for CryptoError {
             CryptoErrorKind::Base64DecodeError(error) => {
                 write!(f, "Base64DecodeError: {}", error.0)
             }
+            CryptoErrorKind::EncryptionError(error) => write!(f, "EncryptionError: {}", error.0),
+            CryptoErrorKind::AuthenticationError(error) => {
+                write!(f, "AuthenticationError: {}", error.0)
+            }
+            CryptoErrorKind::Utf8Error(error) => write!(f, "Utf8Error: {}", error.0),
         }
     }
 }
@@ -144,13 +287,71 @@ mod test {
         let key = "Some secret key!Some secret key!Some secret key!".to_owned();
         let message = "Some message to test crypto ".to_owned();
         let crypto = Crypto::new_one(key).unwrap();
-        let enc = crypto.encrypt(&message);
+        let enc = crypto.encrypt(&message).unwrap();
         println!("Encrypted data: {}", enc);
-        let dec = crypto.decrypt(&enc);
-        let dec2 = crypto.decrypt(&enc);
+        let dec = crypto.decrypt(&enc).unwrap();
+        let dec2 = crypto.decrypt(&enc).unwrap();
         assert_eq!(message, dec);
         println!("Decrypted data: {}", dec);
         assert_eq!(message, dec2);
         println!("Decrypted data 2: {}", dec2);
     }
+
+    mod keyring {
+        use super::super::{Crypto, Keyring};
+
+        const SECRET: &str = "encryption-super-secret-example!";
+        const OLD_SECRET: &str = "an-old-secret-which-was-rotated!";
+        const SALT: &str = "0123456789abcdef";
+
+        #[test]
+        pub fn test_random_nonce() {
+            let keyring = Keyring::new(SECRET.to_owned(), vec![]).unwrap();
+            let first = keyring.encrypt("password").unwrap();
+            let second = keyring.encrypt("password").unwrap();
+            assert_ne!(first, second);
+            assert!(first.starts_with("v2:"));
+            assert_eq!(keyring.decrypt(&first, SALT).unwrap(), "password");
+            assert_eq!(keyring.decrypt(&second, SALT).unwrap(), "password");
+            assert!(keyring.is_current(&first));
+        }
+
+        #[test]
+        pub fn test_rotation() {
+            let old = Keyring::new(OLD_SECRET.to_owned(), vec![]).unwrap();
+            let encrypted = old.encrypt("password").unwrap();
+            let rotated = Keyring::new(SECRET.to_owned(), vec![OLD_SECRET.to_owned()]).unwrap();
+            assert!(!rotated.is_current(&encrypted));
+            assert_eq!(rotated.decrypt(&encrypted, SALT).unwrap(), "password");
+        }
+
+        #[test]
+        pub fn test_legacy() {
+            let legacy = Crypto::new(OLD_SECRET.to_owned(), SALT.to_owned())
+                .unwrap()
+                .encrypt("password")
+                .unwrap();
+            let keyring = Keyring::new(SECRET.to_owned(), vec![OLD_SECRET.to_owned()]).unwrap();
+            assert!(!keyring.is_current(&legacy));
+            assert_eq!(keyring.decrypt(&legacy, SALT).unwrap(), "password");
+        }
+
+        #[test]
+        pub fn test_unreadable_values() {
+            let old = Keyring::new(OLD_SECRET.to_owned(), vec![]).unwrap();
+            let encrypted = old.encrypt("password").unwrap();
+            let keyring = Keyring::new(SECRET.to_owned(), vec![]).unwrap();
+            assert!(keyring.decrypt(&encrypted, SALT).is_err());
+            assert!(keyring.decrypt("not base64!", SALT).is_err());
+            assert!(keyring.decrypt("v2:no-key-id", SALT).is_err());
+            let own = keyring.encrypt("password").unwrap();
+            let (prefix, _) = own.rsplit_once(':').unwrap();
+            assert!(keyring.decrypt(&format!("{}:AAAA", prefix), SALT).is_err());
+        }
+
+        #[test]
+        pub fn test_wrong_key_size() {
+            assert!(Keyring::new("short".to_owned(), vec![]).is_err());
+        }
+    }
 }
@@ -1,63 +1,85 @@
+use crate::core::units::format_bytes;
 use crate::errors::MagnetMappingError;
 use log::*;
-use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use serde::Serialize;
 use url::form_urlencoded;
 use url::Url;
 
-#[derive(Debug, Clone, Serialize)]
+const BTIH: &str = "urn:btih:";
+const BTMH: &str = "urn:btmh:";
+/// Multihash prefix of a SHA-256 digest, the only one of BitTorrent v2
+const SHA256_MULTIHASH: &str = "1220";
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A magnet link with its exact topics normalised to lowercase hex:
+/// `urn:btih:` for BitTorrent v1, `urn:btmh:` for v2 and both for a hybrid torrent
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct MagnetLink {
-    xt: String,
+    xt: Vec<String>,
     tr: Vec<String>,
     dn: String,
+    xl: Option<u64>,
+    ws: Vec<String>,
+    x_pe: Vec<String>,
 }
 
 /// https://url.spec.whatwg.org/#fragment-percent-encode-set
-const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
+/// and the characters which would end or alter the parameter
+const PARAMETER: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`')
+    .add(b'#').add(b'%').add(b'&').add(b'+').add(b'=');
 
 impl MagnetLink {
     pub fn from(string: &String) -> Result<Self, MagnetMappingError> {
         debug!("Parsing magnet: {}", string);
-        let url: Url = Url::parse(string.as_ref()).expect("Invalid magnet");
-        let parameters = url.query_pairs();
-
-        let xts: Vec<String> = parameters
-            .filter(|pair| pair.0 == "xt")
-            .map(|pair| pair.1.to_string())
-            .collect();
-        let xt = match xts.len() {
-            0 => Err(MagnetMappingError::new("No XT parameter found")),
-            1 => Ok(xts.first().unwrap().to_owned()),
-            _ => Err(MagnetMappingError::new(
-                "There were found more then 1 xt parameter",
-            )),
-        }?;
-
-        let tr: Vec<String> = parameters
-            .filter(|pair| pair.0 == "tr")
-            .map(|pair| pair.1.to_string())
-            .collect();
-
-        let dns: Vec<String> = parameters
-            .filter(|pair| pair.0 == "dn")
-            .map(|pair| pair.1.to_string())
-            .map(|encoded_name| {
-                percent_decode_str(&encoded_name)
-                    .decode_utf8()
-                    .unwrap()
-                    .to_string()
-            })
-            .collect();
+        let url = Url::parse(string.as_ref()).map_err(|_| MagnetMappingError::new("Invalid magnet"))?;
+        if url.scheme() != "magnet" {
+            return Err(MagnetMappingError::new("Not a magnet link"));
+        }
+        // the params may be numbered when repeated, e.g. xt.1 and xt.2
+        let values = |name: &str| -> Vec<String> {
+            url.query_pairs()
+                .filter(|(key, _)| key == name || key.strip_prefix(name).is_some_and(|rest| rest.starts_with('.')))
+                .map(|(_, value)| value.to_string())
+                .collect()
+        };
 
-        let dn = match dns.len() {
-            0 => Ok(hash_from_xt(&xt)),
-            1 => Ok(dns.first().unwrap().to_owned()),
-            _ => Err(MagnetMappingError::new(
-                "There were found more then 1 dn parameter",
-            )),
-        }?;
+        let mut xt: Vec<String> = vec![];
+        for topic in values("xt") {
+            // other networks' topics, e.g. urn:ed2k, are of no use to the torrent clients
+            if let Some(topic) = normalise_xt(&topic)? {
+                if !xt.contains(&topic) {
+                    xt.push(topic);
+                }
+            }
+        }
+        if xt.is_empty() {
+            return Err(MagnetMappingError::new("No BitTorrent xt parameter found"));
+        }
+        // v1 first, so the hash is the one the clients know the hybrid torrent by
+        xt.sort();
 
-        Ok(MagnetLink { xt, tr, dn })
+        let mut link = MagnetLink {
+            xt,
+            tr: values("tr"),
+            dn: String::new(),
+            xl: values("xl").first().and_then(|xl| xl.parse().ok()),
+            ws: values("ws"),
+            x_pe: values("x.pe"),
+        };
+        link.dn = values("dn").into_iter().next().unwrap_or_else(|| link.clone().hash());
+        Ok(link)
+    }
+
+    pub fn from_hash(hash: &str, tr: Vec<String>, dn: &str) -> Self {
+        MagnetLink {
+            xt: vec![format!("{}{}", BTIH, hash.to_lowercase())],
+            tr,
+            dn: dn.to_owned(),
+            xl: None,
+            ws: vec![],
+            x_pe: vec![],
+        }
     }
 
     pub fn find(string: &String) -> Option<Self> {
@@ -73,40 +95,101 @@ impl MagnetLink {
             .flatten()
     }
 
+    /// The hex info hash the clients identify the torrent by: the v1 one,
+    /// or for a v2 only torrent its SHA-256 truncated to the v1 length
     pub fn hash(self) -> String {
-        hash_from_xt(&self.xt)
+        let v1 = self.xt.iter().find_map(|xt| xt.strip_prefix(BTIH));
+        let v2 = self.xt.iter().find_map(|xt| xt.strip_prefix(BTMH));
+        match (v1, v2) {
+            (Some(hash), _) => hash.to_owned(),
+            (None, Some(multihash)) => multihash[SHA256_MULTIHASH.len()..][..40].to_owned(),
+            (None, None) => String::new(),
+        }
     }
 
     pub fn dn(self) -> String {
         self.dn
     }
 
+    /// Short human readable summary to show before the download starts
+    pub fn description(&self) -> String {
+        match self.xl {
+            Some(size) => format!("{}\n{}", self.dn, format_bytes(size as i64)),
+            None => self.dn.clone(),
+        }
+    }
+
     pub fn short_link(self) -> String {
         let mut serializer = form_urlencoded::Serializer::new(String::new());
         self.tr.iter().for_each(|tr| {
             let _ = serializer.append_pair("tr", tr);
         });
         let tracker_params = serializer.finish();
-        format!("magnet:?xt={}&{}", &self.xt, tracker_params)
+        format!("magnet:?{}&{}", self.topics(), tracker_params)
     }
 
+    /// The link with all the params it was parsed from, parsing it again gives the same link
     pub fn full_link(self) -> String {
         let mut serializer = form_urlencoded::Serializer::new(String::new());
         self.tr.iter().for_each(|tr| {
             let _ = serializer.append_pair("tr", tr);
         });
-        let tracker_params = serializer.finish();
-        let encoded_dn: String = utf8_percent_encode(&self.dn, FRAGMENT).collect();
-        format!(
-            "magnet:?xt={}&dn={}&{}",
-            &self.xt, &encoded_dn, tracker_params
-        )
+        self.ws.iter().for_each(|ws| {
+            let _ = serializer.append_pair("ws", ws);
+        });
+        self.x_pe.iter().for_each(|peer| {
+            let _ = serializer.append_pair("x.pe", peer);
+        });
+        let params = serializer.finish();
+        let encoded_dn: String = utf8_percent_encode(&self.dn, PARAMETER).collect();
+        let size = self.xl.map(|xl| format!("&xl={}", xl)).unwrap_or_default();
+        format!("magnet:?{}&dn={}{}&{}", self.topics(), &encoded_dn, size, params)
+    }
+
+    fn topics(&self) -> String {
+        self.xt.iter().map(|xt| format!("xt={}", xt)).collect::<Vec<_>>().join("&")
     }
 }
 
-fn hash_from_xt(xt: &String) -> String {
-    let parts: Vec<String> = xt.split(":").map(|part| String::from(part)).collect();
-    parts[2].clone()
+/// The BitTorrent topic with the hash in lowercase hex, nothing for the topics of other networks
+fn normalise_xt(xt: &str) -> Result<Option<String>, MagnetMappingError> {
+    let lowercased = xt.to_ascii_lowercase();
+    if let Some(hash) = lowercased.strip_prefix(BTIH) {
+        let hex = match hash.len() {
+            40 if is_hex(hash) => hash.to_owned(),
+            32 => base32_to_hex(hash).ok_or(MagnetMappingError::new("Invalid base32 btih hash"))?,
+            _ => return Err(MagnetMappingError::new("Invalid btih hash")),
+        };
+        return Ok(Some(format!("{}{}", BTIH, hex)));
+    }
+    if let Some(multihash) = lowercased.strip_prefix(BTMH) {
+        if multihash.len() != SHA256_MULTIHASH.len() + 64 || !multihash.starts_with(SHA256_MULTIHASH) || !is_hex(multihash) {
+            return Err(MagnetMappingError::new("Invalid btmh hash"));
+        }
+        return Ok(Some(format!("{}{}", BTMH, multihash)));
+    }
+    Ok(None)
+}
+
+fn is_hex(string: &str) -> bool {
+    string.chars().all(|char| char.is_ascii_hexdigit())
+}
+
+/// RFC 4648 base32 without padding, as the 32 characters of a v1 info hash
+fn base32_to_hex(base32: &str) -> Option<String> {
+    let mut bits: u64 = 0;
+    let mut bits_count = 0;
+    let mut hex = String::new();
+    for char in base32.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|letter| *letter == char.to_ascii_uppercase())?;
+        bits = (bits << 5) | value as u64;
+        bits_count += 5;
+        if bits_count >= 8 {
+            bits_count -= 8;
+            hex.push_str(&format!("{:02x}", (bits >> bits_count) & 0xff));
+        }
+    }
+    Some(hex)
 }
 
 #[cfg(test)]
@@ -122,7 +205,7 @@ mod test {
             "http://sometracker.com/announce2".to_owned(),
         ];
         let link = MagnetLink::from(&magnet).unwrap();
-        assert_eq!(link.clone().xt, urn);
+        assert_eq!(link.clone().xt, vec![urn]);
         assert_eq!(link.clone().tr, trackers);
     }
 
@@ -136,7 +219,7 @@ mod test {
         ];
         let link = MagnetLink::find(magnet);
         assert!(link.clone().is_some());
-        assert_eq!(link.clone().unwrap().xt, urn);
+        assert_eq!(link.clone().unwrap().xt, vec![urn]);
         assert_eq!(link.clone().unwrap().tr, trackers);
     }
 
@@ -150,23 +233,18 @@ mod test {
         ];
         let link = MagnetLink::find(magnet);
         assert!(link.clone().is_some());
-        assert_eq!(link.clone().unwrap().xt, urn);
+        assert_eq!(link.clone().unwrap().xt, vec![urn]);
         assert_eq!(link.clone().unwrap().tr, trackers);
     }
 
     #[test]
     pub fn test_short_magnet_string_generation() {
         let magnet = String::from("magnet:?xt=urn:btih:e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb&tr=http%3A%2F%2Fsometracker.com%2Fannounce&tr=http%3A%2F%2Fsometracker.com%2Fannounce2");
-        let urn = String::from("urn:btih:e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb");
         let trackers = vec![
             "http://sometracker.com/announce".to_owned(),
             "http://sometracker.com/announce2".to_owned(),
         ];
-        let link = MagnetLink {
-            xt: urn,
-            tr: trackers,
-            dn: "test".to_owned(),
-        };
+        let link = MagnetLink::from_hash("e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb", trackers, "test");
         let actual: String = link.short_link();
         assert_eq!(actual, magnet);
     }
@@ -174,16 +252,11 @@ mod test {
     #[test]
     pub fn test_short_magnet_hash() {
         let hash = String::from("e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb");
-        let urn = String::from("urn:btih:e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb");
         let trackers = vec![
             "http://sometracker.com/announce".to_owned(),
             "http://sometracker.com/announce2".to_owned(),
         ];
-        let link = MagnetLink {
-            xt: urn,
-            tr: trackers,
-            dn: "test".to_owned(),
-        };
+        let link = MagnetLink::from_hash("e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb", trackers, "test");
         let actual: String = link.hash();
         assert_eq!(actual, hash);
     }
@@ -217,4 +290,76 @@ mod test {
         let link = MagnetLink::find(text);
         assert_eq!(expected, &link.unwrap().full_link())
     }
+
+    #[test]
+    pub fn test_base32_hash() {
+        let magnet = String::from("magnet:?xt=urn:btih:4JE74TOJK67EWTHD5SVMFAH56HDRXRN3&dn=test");
+        let link = MagnetLink::from(&magnet).unwrap();
+        assert_eq!(link.hash(), "e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb");
+    }
+
+    #[test]
+    pub fn test_uppercase_hex_hash() {
+        let magnet = String::from("magnet:?xt=urn:btih:E249FE4DC957BE4B4CE3ECAAC280FDF1C71BC5BB");
+        let link = MagnetLink::from(&magnet).unwrap();
+        assert_eq!(link.clone().hash(), "e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb");
+        assert_eq!(link.dn(), "e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb");
+    }
+
+    #[test]
+    pub fn test_v2_hash() {
+        let magnet = String::from("magnet:?xt=urn:btmh:1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e&dn=bittorrent-v2-test");
+        let link = MagnetLink::from(&magnet).unwrap();
+        assert_eq!(link.hash(), "caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa");
+    }
+
+    #[test]
+    pub fn test_hybrid_link() {
+        let magnet = String::from("magnet:?xt=urn:btmh:1220d8dd32ac93357c368556af3ac1d95c9d76bd0dff6fa9833ecdac3d53134efabb&xt=urn:btih:631a31dd0a46257d5078c0dee4e66e26f73e42ac&dn=bittorrent-v1-v2-hybrid-test");
+        let link = MagnetLink::from(&magnet).unwrap();
+        assert_eq!(link.xt.len(), 2);
+        assert_eq!(link.clone().hash(), "631a31dd0a46257d5078c0dee4e66e26f73e42ac");
+        // both topics are given to the client
+        assert!(link.short_link().starts_with("magnet:?xt=urn:btih:631a31dd0a46257d5078c0dee4e66e26f73e42ac&xt=urn:btmh:1220d8dd"));
+    }
+
+    #[test]
+    pub fn test_numbered_and_foreign_topics() {
+        let magnet = String::from("magnet:?xt.1=urn:ed2k:354b15e68fb8f36d7cd88ff94116cdc1&xt.2=urn:btih:e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb");
+        let link = MagnetLink::from(&magnet).unwrap();
+        assert_eq!(link.xt, vec!["urn:btih:e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb".to_owned()]);
+    }
+
+    #[test]
+    pub fn test_invalid_links() {
+        let invalid = [
+            "not a magnet",
+            "http://example.com/?xt=urn:btih:e249fe4dc957be4b4ce3ecaac280fdf1c71bc5bb",
+            "magnet:?dn=no-topic",
+            "magnet:?xt=urn:ed2k:354b15e68fb8f36d7cd88ff94116cdc1",
+            "magnet:?xt=urn:btih:e249fe4dc957",
+            "magnet:?xt=urn:btih:zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz",
+            "magnet:?xt=urn:btih:1JE74TOJK67EWTHD5SVMFAH56HDRXRN3",
+            "magnet:?xt=urn:btmh:1114caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa",
+            "magnet:?xt=urn:btih",
+        ];
+        for magnet in invalid {
+            assert!(MagnetLink::from(&magnet.to_owned()).is_err(), "{} should be invalid", magnet);
+        }
+        assert!(MagnetLink::find(&"magnet:?xt=urn:btih:broken".to_owned()).is_none());
+    }
+
+    #[test]
+    pub fn test_extra_params_round_trip() {
+        let magnet = String::from("magnet:?xt=urn:btih:4JE74TOJK67EWTHD5SVMFAH56HDRXRN3&xt=urn:btmh:1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e&dn=Tom+%26+Jerry%3D100%25%23&xl=1073741824&tr=udp%3A%2F%2Ftracker.example.com%3A80&ws=http%3A%2F%2Fmirror.example.com%2Ffile&x.pe=10.0.0.1%3A6881");
+        let link = MagnetLink::from(&magnet).unwrap();
+        assert_eq!(link.dn, "Tom & Jerry=100%#");
+        assert_eq!(link.xl, Some(1073741824));
+        assert_eq!(link.ws, vec!["http://mirror.example.com/file".to_owned()]);
+        assert_eq!(link.x_pe, vec!["10.0.0.1:6881".to_owned()]);
+        assert_eq!(link.description(), "Tom & Jerry=100%#\n1.0 GB");
+
+        let full_link = link.clone().full_link();
+        assert_eq!(MagnetLink::from(&full_link).unwrap(), link);
+    }
 }
@@ -1,5 +1,8 @@
+pub mod backend;
 pub(crate) mod crypto;
 pub mod magnet;
-pub mod rutracker;
+pub mod trackers;
 pub mod trans_url;
+pub mod torrent;
+pub mod units;
 pub(crate) mod flaresolver;
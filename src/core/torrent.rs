@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+
+use sha1::{Digest, Sha1};
+
+use crate::core::magnet::MagnetLink;
+use crate::core::units::format_bytes;
+use crate::errors::TorrentParseError;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Bencode {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    Dict(BTreeMap<Vec<u8>, Bencode>),
+}
+
+impl Bencode {
+    fn get(&self, key: &str) -> Option<&Bencode> {
+        match self {
+            Bencode::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Bencode::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn as_string(&self) -> Option<String> {
+        match self {
+            Bencode::Bytes(bytes) => Some(String::from_utf8_lossy(bytes).to_string()),
+            _ => None,
+        }
+    }
+}
+
+const MAX_DEPTH: usize = 64;
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Decoder { data, pos: 0 }
+    }
+
+    fn peek(&self) -> Result<u8, TorrentParseError> {
+        self.data
+            .get(self.pos)
+            .copied()
+            .ok_or(TorrentParseError::new("Unexpected end of data"))
+    }
+
+    fn read_until(&mut self, terminator: u8) -> Result<&'a [u8], TorrentParseError> {
+        let start = self.pos;
+        let length = self.data[start..]
+            .iter()
+            .position(|b| *b == terminator)
+            .ok_or(TorrentParseError::new("Unterminated value"))?;
+        self.pos = start + length + 1;
+        Ok(&self.data[start..start + length])
+    }
+
+    fn read_number(&mut self, terminator: u8) -> Result<i64, TorrentParseError> {
+        let digits = self.read_until(terminator)?;
+        std::str::from_utf8(digits)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or(TorrentParseError::new("Invalid number"))
+    }
+
+    fn decode(&mut self) -> Result<Bencode, TorrentParseError> {
+        self.decode_nested(0)
+    }
+
+    /// Real torrents nest a few levels deep, the limit keeps a crafted file from overflowing the stack
+    fn decode_nested(&mut self, depth: usize) -> Result<Bencode, TorrentParseError> {
+        if depth > MAX_DEPTH {
+            return Err(TorrentParseError::new("Values are nested too deep"));
+        }
+        match self.peek()? {
+            b'i' => {
+                self.pos += 1;
+                Ok(Bencode::Int(self.read_number(b'e')?))
+            }
+            b'l' => {
+                self.pos += 1;
+                let mut list = vec![];
+                while self.peek()? != b'e' {
+                    list.push(self.decode_nested(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Bencode::List(list))
+            }
+            b'd' => {
+                self.pos += 1;
+                let mut dict = BTreeMap::new();
+                while self.peek()? != b'e' {
+                    let key = match self.decode_nested(depth + 1)? {
+                        Bencode::Bytes(key) => key,
+                        _ => return Err(TorrentParseError::new("Dictionary key is not a string")),
+                    };
+                    let value = self.decode_nested(depth + 1)?;
+                    dict.insert(key, value);
+                }
+                self.pos += 1;
+                Ok(Bencode::Dict(dict))
+            }
+            b'0'..=b'9' => {
+                let length = self.read_number(b':')? as usize;
+                let end = self.pos + length;
+                if end > self.data.len() {
+                    return Err(TorrentParseError::new("String is longer than the data"));
+                }
+                let bytes = self.data[self.pos..end].to_vec();
+                self.pos = end;
+                Ok(Bencode::Bytes(bytes))
+            }
+            _ => Err(TorrentParseError::new("Unknown bencode value")),
+        }
+    }
+
+    /// Returns the raw bytes of the `info` value of the top level dictionary,
+    /// since the info-hash has to be computed over the original encoding
+    fn raw_info(&mut self) -> Result<&'a [u8], TorrentParseError> {
+        self.pos = 0;
+        if self.peek()? != b'd' {
+            return Err(TorrentParseError::new("Torrent is not a dictionary"));
+        }
+        self.pos += 1;
+        while self.peek()? != b'e' {
+            let key = self.decode()?;
+            let start = self.pos;
+            self.decode()?;
+            if key == Bencode::Bytes(b"info".to_vec()) {
+                return Ok(&self.data[start..self.pos]);
+            }
+        }
+        Err(TorrentParseError::new("No info dictionary found"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TorrentFile {
+    pub path: String,
+    pub length: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TorrentMeta {
+    pub name: String,
+    pub info_hash: String,
+    pub piece_length: i64,
+    pub files: Vec<TorrentFile>,
+    pub announce: Option<String>,
+    pub announce_list: Vec<Vec<String>>,
+    pub private: bool,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+}
+
+impl TorrentMeta {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, TorrentParseError> {
+        let mut decoder = Decoder::new(data);
+        let root = decoder.decode()?;
+        let raw_info = decoder.raw_info()?;
+        let info = root
+            .get("info")
+            .ok_or(TorrentParseError::new("No info dictionary found"))?;
+        let name = info
+            .get("name")
+            .and_then(Bencode::as_string)
+            .ok_or(TorrentParseError::new("Torrent has no name"))?;
+        let info_hash = Sha1::digest(raw_info)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let piece_length = info
+            .get("piece length")
+            .and_then(Bencode::as_int)
+            .ok_or(TorrentParseError::new("Torrent has no piece length"))?;
+        let files = parse_files(info, &name)?;
+        let announce = root.get("announce").and_then(Bencode::as_string);
+        let announce_list = match root.get("announce-list") {
+            Some(Bencode::List(tiers)) => tiers
+                .iter()
+                .map(|tier| match tier {
+                    Bencode::List(urls) => urls.iter().filter_map(Bencode::as_string).collect(),
+                    _ => vec![],
+                })
+                .filter(|tier: &Vec<String>| !tier.is_empty())
+                .collect(),
+            _ => vec![],
+        };
+        let private = info.get("private").and_then(Bencode::as_int) == Some(1);
+        let comment = root.get("comment").and_then(Bencode::as_string);
+        let created_by = root.get("created by").and_then(Bencode::as_string);
+        Ok(TorrentMeta {
+            name,
+            info_hash,
+            piece_length,
+            files,
+            announce,
+            announce_list,
+            private,
+            comment,
+            created_by,
+        })
+    }
+
+    pub fn total_size(&self) -> i64 {
+        self.files.iter().map(|file| file.length).sum()
+    }
+
+    /// All the trackers of the torrent without duplicates,
+    /// `announce-list` takes precedence over `announce` as BEP-12 says
+    pub fn trackers(&self) -> Vec<String> {
+        let mut trackers: Vec<String> = vec![];
+        self.announce_list
+            .iter()
+            .flatten()
+            .chain(self.announce.iter())
+            .for_each(|tracker| {
+                if !trackers.contains(tracker) {
+                    trackers.push(tracker.clone());
+                }
+            });
+        trackers
+    }
+
+    /// Short human readable summary to show before the download starts
+    pub fn description(&self) -> String {
+        let mut lines = vec![
+            self.name.clone(),
+            format!(
+                "{} in {} file(s), pieces of {}",
+                format_bytes(self.total_size()),
+                self.files.len(),
+                format_bytes(self.piece_length)
+            ),
+        ];
+        if self.private {
+            lines.push("Private torrent 🔒".to_owned());
+        }
+        if let Some(comment) = &self.comment {
+            lines.push(format!("Comment: {}", comment));
+        }
+        if let Some(created_by) = &self.created_by {
+            lines.push(format!("Created by: {}", created_by));
+        }
+        lines.join("\n")
+    }
+
+    pub fn to_magnet(&self) -> MagnetLink {
+        MagnetLink::from_hash(&self.info_hash, self.trackers(), &self.name)
+    }
+}
+
+fn parse_files(info: &Bencode, name: &str) -> Result<Vec<TorrentFile>, TorrentParseError> {
+    if let Some(length) = info.get("length").and_then(Bencode::as_int) {
+        return Ok(vec![TorrentFile {
+            path: name.to_owned(),
+            length,
+        }]);
+    }
+    match info.get("files") {
+        Some(Bencode::List(files)) => files
+            .iter()
+            .map(|file| {
+                let length = file
+                    .get("length")
+                    .and_then(Bencode::as_int)
+                    .ok_or(TorrentParseError::new("File has no length"))?;
+                let path = match file.get("path") {
+                    Some(Bencode::List(parts)) => parts
+                        .iter()
+                        .filter_map(Bencode::as_string)
+                        .collect::<Vec<String>>()
+                        .join("/"),
+                    _ => return Err(TorrentParseError::new("File has no path")),
+                };
+                Ok(TorrentFile { path, length })
+            })
+            .collect(),
+        _ => Err(TorrentParseError::new("Torrent has neither length nor files")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SINGLE_FILE: &[u8] = b"d8:announce27:http://tracker.com/announce7:comment4:test10:created by7:tester \
+13:creation datei1600000000e4:infod6:lengthi1048576e4:name8:test.iso12:piece lengthi262144e\
+6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+
+    const MULTI_FILE: &[u8] = b"d8:announce28:http://tracker1.com/announce13:announce-listll28:http://tracker1.com/announceel\
+28:http://tracker2.com/announceee4:infod5:filesld6:lengthi100e4:pathl5:CD 0110:track1.mp3eed6:lengthi200e\
+4:pathl5:CD 0210:track2.mp3eee4:name5:Album12:piece lengthi16384e6:pieces20:bbbbbbbbbbbbbbbbbbbb\
+7:privatei1eee";
+
+    #[test]
+    pub fn test_single_file_torrent() {
+        let meta = TorrentMeta::from_bytes(SINGLE_FILE).unwrap();
+        assert_eq!(meta.name, "test.iso");
+        assert_eq!(meta.info_hash, "1d4df2ea1c677bfadc9d6e67c36eaa4bf6f7503d");
+        assert_eq!(meta.piece_length, 262144);
+        assert_eq!(
+            meta.files,
+            vec![TorrentFile {
+                path: "test.iso".to_owned(),
+                length: 1048576
+            }]
+        );
+        assert_eq!(meta.total_size(), 1048576);
+        assert_eq!(meta.announce, Some("http://tracker.com/announce".to_owned()));
+        assert!(meta.announce_list.is_empty());
+        assert!(!meta.private);
+        assert_eq!(meta.comment, Some("test".to_owned()));
+        assert_eq!(meta.created_by, Some("tester ".to_owned()));
+    }
+
+    #[test]
+    pub fn test_multi_file_torrent() {
+        let meta = TorrentMeta::from_bytes(MULTI_FILE).unwrap();
+        assert_eq!(meta.name, "Album");
+        assert_eq!(meta.info_hash, "7f0b87f2ba41e4118a35270766ea696717ca13f0");
+        assert_eq!(meta.piece_length, 16384);
+        assert_eq!(
+            meta.files,
+            vec![
+                TorrentFile {
+                    path: "CD 01/track1.mp3".to_owned(),
+                    length: 100
+                },
+                TorrentFile {
+                    path: "CD 02/track2.mp3".to_owned(),
+                    length: 200
+                },
+            ]
+        );
+        assert_eq!(meta.total_size(), 300);
+        assert!(meta.private);
+        assert_eq!(meta.comment, None);
+        assert_eq!(
+            meta.announce_list,
+            vec![
+                vec!["http://tracker1.com/announce".to_owned()],
+                vec!["http://tracker2.com/announce".to_owned()],
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_trackers_are_deduplicated() {
+        let meta = TorrentMeta::from_bytes(MULTI_FILE).unwrap();
+        assert_eq!(
+            meta.trackers(),
+            vec![
+                "http://tracker1.com/announce".to_owned(),
+                "http://tracker2.com/announce".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_magnet_from_torrent() {
+        let meta = TorrentMeta::from_bytes(SINGLE_FILE).unwrap();
+        let magnet = meta.to_magnet();
+        assert_eq!(magnet.clone().hash(), "1d4df2ea1c677bfadc9d6e67c36eaa4bf6f7503d");
+        assert_eq!(magnet.clone().dn(), "test.iso");
+        assert_eq!(
+            magnet.full_link(),
+            "magnet:?xt=urn:btih:1d4df2ea1c677bfadc9d6e67c36eaa4bf6f7503d&dn=test.iso&tr=http%3A%2F%2Ftracker.com%2Fannounce"
+        );
+    }
+
+    #[test]
+    pub fn test_description() {
+        let meta = TorrentMeta::from_bytes(MULTI_FILE).unwrap();
+        assert_eq!(
+            meta.description(),
+            "Album\n300 B in 2 file(s), pieces of 16.0 KB\nPrivate torrent 🔒"
+        );
+    }
+
+    #[test]
+    pub fn test_truncated_torrent() {
+        let result = TorrentMeta::from_bytes(&SINGLE_FILE[..50]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    pub fn test_not_a_torrent() {
+        assert!(TorrentMeta::from_bytes(b"<html></html>").is_err());
+        assert!(TorrentMeta::from_bytes(b"i42e").is_err());
+        assert!(TorrentMeta::from_bytes(b"").is_err());
+    }
+
+    #[test]
+    pub fn test_deeply_nested_torrent() {
+        let mut data = b"d4:info".to_vec();
+        data.extend(vec![b'l'; 100_000]);
+        data.extend(vec![b'e'; 100_001]);
+        assert!(TorrentMeta::from_bytes(&data).is_err());
+
+        let mut nested = b"d4:infod4:name4:test12:piece lengthi1e6:lengthi1e5:extra".to_vec();
+        nested.extend(vec![b'l'; MAX_DEPTH - 2]);
+        nested.extend(vec![b'e'; MAX_DEPTH - 2]);
+        nested.extend(b"ee");
+        assert!(TorrentMeta::from_bytes(&nested).is_ok());
+    }
+
+    #[test]
+    pub fn test_torrent_without_info() {
+        let result = TorrentMeta::from_bytes(b"d8:announce27:http://tracker.com/announcee");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use crate::core::trackers::{absolute_url, find_href, has_prefix, TrackerLink, TrackerScraper};
+
+/// Kinozal doesn't publish magnets, only .torrent files on a separate host
+pub struct Kinozal;
+
+impl TrackerScraper for Kinozal {
+    fn name(&self) -> &'static str {
+        "kinozal"
+    }
+
+    fn matches(&self, url: &str) -> bool {
+        has_prefix(url, &[
+            "https://kinozal.tv/details.php?id=",
+            "http://kinozal.tv/details.php?id=",
+        ])
+    }
+
+    fn extract(&self, url: &str, html: &str) -> Option<TrackerLink> {
+        find_href(html, r#"a[href*="download.php?id="]"#)
+            .and_then(|href| absolute_url(url, &href))
+            .map(TrackerLink::TorrentFile)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fixture() {
+        let html = include_str!("fixtures/kinozal.html");
+        let link = Kinozal.extract("https://kinozal.tv/details.php?id=2012345", html);
+        assert_eq!(
+            link,
+            Some(TrackerLink::TorrentFile("https://dl.kinozal.tv/download.php?id=2012345".to_string()))
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::core::trackers::{find_href, has_prefix, TrackerLink, TrackerScraper};
+
+/// 1337x and its mirrors
+pub struct LeetX;
+
+impl TrackerScraper for LeetX {
+    fn name(&self) -> &'static str {
+        "1337x"
+    }
+
+    fn matches(&self, url: &str) -> bool {
+        has_prefix(url, &[
+            "https://1337x.to/torrent/",
+            "https://1337x.st/torrent/",
+            "https://x1337x.ws/torrent/",
+        ])
+    }
+
+    fn extract(&self, _url: &str, html: &str) -> Option<TrackerLink> {
+        find_href(html, r#"a[href^="magnet:"]"#).map(TrackerLink::Magnet)
+    }
+
+    fn needs_flaresolver(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fixture() {
+        let html = include_str!("fixtures/1337x.html");
+        let link = LeetX.extract("https://1337x.to/torrent/5836915/Some-Linux-ISO/", html);
+        assert_eq!(
+            link,
+            Some(TrackerLink::Magnet(
+                "magnet:?xt=urn:btih:A3B5C7D9E1F3A5B7C9D1E3F5A7B9C1D3E5F7A9B1&dn=Some+Linux+ISO&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337%2Fannounce".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use std::error::Error;
+
+use log::info;
+use reqwest::{header, Url};
+use scraper::{Html, Selector};
+
+use crate::core::flaresolver::Flaresolver;
+
+pub mod kinozal;
+pub mod leetx;
+pub mod nnmclub;
+pub mod rutracker;
+
+/// Torrents of even huge releases stay within a few megabytes
+pub(crate) const MAX_TORRENT_FILE_SIZE: usize = 5 * 1024 * 1024;
+
+/// What a tracker page offers to download
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackerLink {
+    Magnet(String),
+    /// Absolute url of a .torrent file
+    TorrentFile(String),
+}
+
+pub trait TrackerScraper: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether the page url belongs to the tracker
+    fn matches(&self, url: &str) -> bool;
+
+    /// Finds a magnet or a .torrent link on the page
+    fn extract(&self, url: &str, html: &str) -> Option<TrackerLink>;
+
+    /// Pages behind a Cloudflare challenge have to be fetched with Flaresolverr
+    fn needs_flaresolver(&self) -> bool {
+        false
+    }
+}
+
+pub fn scrapers() -> Vec<Box<dyn TrackerScraper>> {
+    vec![
+        Box::new(rutracker::Rutracker),
+        Box::new(nnmclub::NnmClub),
+        Box::new(kinozal::Kinozal),
+        Box::new(leetx::LeetX),
+    ]
+}
+
+pub fn find_scraper(url: &str) -> Option<Box<dyn TrackerScraper>> {
+    scrapers().into_iter().find(|scraper| scraper.matches(url))
+}
+
+pub async fn get_page_html(url: &str, solver: Option<&Flaresolver>) -> Result<Option<String>, reqwest::Error> {
+    match solver {
+        Some(solver) => solver.get_page_html(url.to_string()).await,
+        None => reqwest::Client::new()
+            .get(url)
+            .send()
+            .await?
+            .text()
+            .await
+            .map(Some),
+    }
+}
+
+/// Downloads the .torrent file found on a tracker page. The url comes from a third-party page,
+/// so pages instead of files and anything bigger than a torrent file can be are refused
+pub async fn get_torrent_file(url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut response = reqwest::Client::new()
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+    if content_type.starts_with("text/") || content_type.contains("html") || content_type.contains("json") {
+        return Err(format!("Expected a torrent file, got {}", content_type).into());
+    }
+    if response.content_length().is_some_and(|length| length > MAX_TORRENT_FILE_SIZE as u64) {
+        return Err("The torrent file is too big".into());
+    }
+    let mut data = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        if data.len() + chunk.len() > MAX_TORRENT_FILE_SIZE {
+            return Err("The torrent file is too big".into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+fn has_prefix(url: &str, prefixes: &[&str]) -> bool {
+    let url = url.to_lowercase();
+    prefixes.iter().any(|prefix| url.starts_with(prefix))
+}
+
+/// The `href` of the first element matching the selector
+fn find_href(html: &str, selector: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse(selector).unwrap();
+    document
+        .select(&selector)
+        .find_map(|e| e.value().attr("href"))
+        .map(|s| {
+            info!("Found link: {}", s);
+            s.to_string()
+        })
+}
+
+/// Resolves a link relative to the page it was found on
+fn absolute_url(page_url: &str, href: &str) -> Option<String> {
+    Url::parse(page_url)
+        .and_then(|base| base.join(href))
+        .map(|url| url.to_string())
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_scraper() {
+        let cases = [
+            ("https://rutracker.org/forum/viewtopic.php?t=5956127", Some("rutracker")),
+            ("https://nnmclub.to/forum/viewtopic.php?t=1650201", Some("nnmclub")),
+            ("https://kinozal.tv/details.php?id=2012345", Some("kinozal")),
+            ("https://1337x.to/torrent/5836915/Some-Linux-ISO/", Some("1337x")),
+            ("https://example.com/forum/viewtopic.php?t=1", None),
+        ];
+        for (url, name) in cases {
+            assert_eq!(find_scraper(url).map(|scraper| scraper.name()), name, "{}", url);
+        }
+    }
+
+    async fn serve(content_type: &'static str, body: Vec<u8>) -> String {
+        let router = axum::Router::new().route(
+            "/file.torrent",
+            axum::routing::get(move || {
+                let body = body.clone();
+                async move { ([("content-type", content_type)], body) }
+            }),
+        );
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+        let url = format!("http://{}/file.torrent", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test]
+    async fn test_get_torrent_file() {
+        let url = serve("application/x-bittorrent", b"d4:infodee".to_vec()).await;
+        assert_eq!(get_torrent_file(&url).await.unwrap(), b"d4:infodee");
+
+        let url = serve("text/html; charset=utf-8", b"<html>Please log in</html>".to_vec()).await;
+        assert!(get_torrent_file(&url).await.is_err());
+
+        let url = serve("application/octet-stream", vec![b'x'; MAX_TORRENT_FILE_SIZE + 1]).await;
+        assert!(get_torrent_file(&url).await.is_err());
+    }
+
+    #[test]
+    fn test_absolute_url() {
+        assert_eq!(
+            absolute_url("https://nnmclub.to/forum/viewtopic.php?t=1", "download.php?id=2"),
+            Some("https://nnmclub.to/forum/download.php?id=2".to_string())
+        );
+        assert_eq!(
+            absolute_url("https://kinozal.tv/details.php?id=1", "//dl.kinozal.tv/download.php?id=1"),
+            Some("https://dl.kinozal.tv/download.php?id=1".to_string())
+        );
+    }
+}
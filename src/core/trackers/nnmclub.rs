@@ -0,0 +1,51 @@
+use crate::core::trackers::{absolute_url, find_href, has_prefix, TrackerLink, TrackerScraper};
+
+pub struct NnmClub;
+
+impl TrackerScraper for NnmClub {
+    fn name(&self) -> &'static str {
+        "nnmclub"
+    }
+
+    fn matches(&self, url: &str) -> bool {
+        has_prefix(url, &[
+            "https://nnmclub.to/forum/viewtopic.php?t=",
+            "https://nnm-club.me/forum/viewtopic.php?t=",
+        ])
+    }
+
+    fn extract(&self, url: &str, html: &str) -> Option<TrackerLink> {
+        find_href(html, r#"a[href^="magnet:"]"#)
+            .map(TrackerLink::Magnet)
+            .or_else(|| find_href(html, r#"a[href^="download.php?id="]"#)
+                .and_then(|href| absolute_url(url, &href))
+                .map(TrackerLink::TorrentFile))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fixture() {
+        let html = include_str!("fixtures/nnmclub.html");
+        let link = NnmClub.extract("https://nnmclub.to/forum/viewtopic.php?t=1650201", html);
+        assert_eq!(
+            link,
+            Some(TrackerLink::Magnet(
+                "magnet:?xt=urn:btih:7F0B87F2C0A1E0B5B1F6A2B8D2E4A6C8E0F1A3B5&tr=http%3A%2F%2Fbt01.nnm-club.cc%3A2710%2Fannounce".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_torrent_file_fallback() {
+        let html = r#"<html><body><a href="download.php?id=1128810">Скачать</a></body></html>"#;
+        let link = NnmClub.extract("https://nnmclub.to/forum/viewtopic.php?t=1650201", html);
+        assert_eq!(
+            link,
+            Some(TrackerLink::TorrentFile("https://nnmclub.to/forum/download.php?id=1128810".to_string()))
+        );
+    }
+}
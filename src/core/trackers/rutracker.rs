@@ -0,0 +1,58 @@
+use log::info;
+use scraper::{Html, Selector};
+
+use crate::core::trackers::{has_prefix, TrackerLink, TrackerScraper};
+
+pub struct Rutracker;
+
+impl TrackerScraper for Rutracker {
+    fn name(&self) -> &'static str {
+        "rutracker"
+    }
+
+    fn matches(&self, url: &str) -> bool {
+        has_prefix(url, &["https://rutracker.org/forum/viewtopic.php?t="])
+    }
+
+    fn extract(&self, _url: &str, html: &str) -> Option<TrackerLink> {
+        find_magnet(html.to_string()).map(TrackerLink::Magnet)
+    }
+
+    fn needs_flaresolver(&self) -> bool {
+        true
+    }
+}
+
+pub fn find_magnet(html: String) -> Option<String> {
+    let document = Html::parse_document(&html);
+    let selector = Selector::parse("a.magnet-link").unwrap();
+    document
+        .select(&selector)
+        .next()
+        .map(|e| {
+            info!("Found a.magnet-link section, getting href from it: {:?}", e);
+            e.value().attr("href")
+        })
+        .flatten()
+        .map(|s| {
+            info!("Found magnet link: {}", s);
+            s.to_string()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fixture() {
+        let html = include_str!("fixtures/rutracker.html");
+        let link = Rutracker.extract("https://rutracker.org/forum/viewtopic.php?t=5956127", html);
+        assert_eq!(
+            link,
+            Some(TrackerLink::Magnet(
+                "magnet:?xt=urn:btih:1D4DF2EA6B3C31AC31E3B0F3E96E4EE1F3C64C4E&tr=http%3A%2F%2Fbt.t-ru.org%2Fann".to_string()
+            ))
+        );
+    }
+}
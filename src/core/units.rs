@@ -0,0 +1,48 @@
+const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+pub fn format_bytes(bytes: i64) -> String {
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[unit]),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+/// Transmission reports -1 and -2 when the eta is unknown
+pub fn format_eta(seconds: i64) -> String {
+    match seconds {
+        s if s < 0 => "∞".to_string(),
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h {}m", s / 3600, s % 3600 / 60),
+        s => format!("{}d {}h", s / 86400, s % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(1048576), "1.0 MB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GB");
+    }
+
+    #[test]
+    fn test_format_eta() {
+        assert_eq!(format_eta(-1), "∞");
+        assert_eq!(format_eta(42), "42s");
+        assert_eq!(format_eta(125), "2m");
+        assert_eq!(format_eta(3 * 3600 + 25 * 60), "3h 25m");
+        assert_eq!(format_eta(2 * 86400 + 5 * 3600), "2d 5h");
+    }
+}
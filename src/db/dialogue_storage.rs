@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use teloxide::dispatching::dialogue::Storage;
+use teloxide::types::ChatId;
+
+use crate::db::repository::{delete_dialogue_state, get_dialogue_state, save_dialogue_state, Pool};
+use crate::errors::DbError;
+
+/// Dialogue storage which keeps the states in the `dialogues` table as json,
+/// so users are not dropped out of a dialogue by a restart
+pub struct PgStorage {
+    pool: Pool,
+}
+
+impl PgStorage {
+    pub fn new(pool: Pool) -> Arc<Self> {
+        Arc::new(PgStorage { pool })
+    }
+}
+
+impl<D> Storage<D> for PgStorage
+where
+    D: Send + Serialize + DeserializeOwned + 'static,
+{
+    type Error = DbError;
+
+    fn remove_dialogue(self: Arc<Self>, ChatId(chat_id): ChatId) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move { delete_dialogue_state(&self.pool, chat_id).await })
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        ChatId(chat_id): ChatId,
+        dialogue: D,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let state = serde_json::to_string(&dialogue)?;
+            save_dialogue_state(&self.pool, chat_id, state).await
+        })
+    }
+
+    fn get_dialogue(self: Arc<Self>, ChatId(chat_id): ChatId) -> BoxFuture<'static, Result<Option<D>, Self::Error>> {
+        Box::pin(async move {
+            match get_dialogue_state(&self.pool, chat_id).await? {
+                Some(state) => match serde_json::from_str(&state) {
+                    Ok(dialogue) => Ok(Some(dialogue)),
+                    // a state saved by an older version, the user starts over rather than getting stuck
+                    Err(err) => {
+                        warn!("Dropping unreadable dialogue state of chat {}: {}", chat_id, err);
+                        delete_dialogue_state(&self.pool, chat_id).await?;
+                        Ok(None)
+                    }
+                },
+                None => Ok(None),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DbConfig;
+    use crate::router::State;
+
+    fn pool() -> Pool {
+        dotenvy::from_filename("test.env").ok();
+        DbConfig::get_pool()
+    }
+
+    #[tokio::test]
+    pub async fn test_dialogue_roundtrip() -> Result<(), DbError> {
+        let storage = PgStorage::new(pool());
+        let chat_id = ChatId(rand::random::<i32>() as i64);
+
+        Arc::clone(&storage).update_dialogue(chat_id, State::AddDirectory).await?;
+        let state: Option<State> = Arc::clone(&storage).get_dialogue(chat_id).await?;
+        assert!(matches!(state, Some(State::AddDirectory)));
+
+        Arc::clone(&storage).update_dialogue(chat_id, State::RegisterServer).await?;
+        let state: Option<State> = Arc::clone(&storage).get_dialogue(chat_id).await?;
+        assert!(matches!(state, Some(State::RegisterServer)));
+
+        <PgStorage as Storage<State>>::remove_dialogue(Arc::clone(&storage), chat_id).await?;
+        let state: Option<State> = Arc::clone(&storage).get_dialogue(chat_id).await?;
+        assert!(state.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_unreadable_dialogue() -> Result<(), DbError> {
+        let pool = pool();
+        let storage = PgStorage::new(pool.clone());
+        let chat_id = ChatId(rand::random::<i32>() as i64);
+
+        save_dialogue_state(&pool, chat_id.0, r#"{"RemovedState":{"id":1}}"#.to_owned()).await?;
+        let state: Option<State> = Arc::clone(&storage).get_dialogue(chat_id).await?;
+        assert!(state.is_none());
+        assert!(get_dialogue_state(&pool, chat_id.0).await?.is_none());
+        Ok(())
+    }
+}
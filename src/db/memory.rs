@@ -0,0 +1,466 @@
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::errors::DbError;
+
+use super::models::{
+    directories::DownloadDirectory,
+    download_task::{DownloadTask, TaskStatus},
+    magnet::Magnet,
+    server::{Authentication, NewServer, Server, ServerEndpoint},
+    server_share::ServerShare,
+    user::{NewUser, User},
+};
+use super::repo::Repository;
+
+/// Keeps everything in memory, so the handlers can be tested without Postgres
+#[derive(Default)]
+pub struct InMemoryRepository {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    users: Vec<User>,
+    dirs: Vec<DownloadDirectory>,
+    servers: Vec<Server>,
+    shares: Vec<ServerShare>,
+    tasks: Vec<DownloadTask>,
+    magnets: Vec<Magnet>,
+    // user id and friend id
+    friends: Vec<(i64, i64)>,
+}
+
+impl MemoryState {
+    fn owner_of(&self, server_id: &Uuid) -> Option<i64> {
+        self.servers.iter().find(|server| server.id == *server_id).map(|server| server.user_id)
+    }
+
+    fn shared_servers(&self, user: &User) -> Vec<Server> {
+        self.shares.iter()
+            .filter(|share| share.friend_user_id == user.id)
+            .filter_map(|share| self.servers.iter().find(|server| server.id == share.server_id))
+            .cloned()
+            .collect()
+    }
+}
+
+impl InMemoryRepository {
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().expect("Memory state is poisoned")
+    }
+
+    pub fn magnets(&self) -> Vec<Magnet> {
+        self.state().magnets.clone()
+    }
+
+    pub fn tasks(&self) -> Vec<DownloadTask> {
+        self.state().tasks.clone()
+    }
+
+    fn update_directory(&self, user: &User, ordinal: i32, update: impl FnOnce(&mut DownloadDirectory)) {
+        if let Some(dir) = self.state().dirs.iter_mut().find(|dir| dir.user_id == user.id && dir.ordinal == ordinal) {
+            update(dir);
+        }
+    }
+
+    fn register(&self, user: &User, url: &str, metainfo: Option<String>) -> Result<Uuid, DbError> {
+        let magnet = Magnet {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            url: url.to_owned(),
+            created_at: Utc::now().naive_utc(),
+            metainfo,
+            server_id: None,
+        };
+        let id = magnet.id;
+        self.state().magnets.push(magnet);
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl Repository for InMemoryRepository {
+    async fn get_user(&self, id: &i64) -> Result<Option<User>, DbError> {
+        Ok(self.state().users.iter().find(|user| user.id == *id).cloned())
+    }
+
+    async fn save_user(&self, user: NewUser) -> Result<User, DbError> {
+        let user = User {
+            id: user.id,
+            chat: user.chat,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            username: user.username,
+            salt: user.salt,
+            created_at: Utc::now().naive_utc(),
+            last_dir_ordinal: 0,
+        };
+        self.state().users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn add_directory(
+        &self,
+        user: &User,
+        alias: &str,
+        path: &str,
+        server_id: Option<Uuid>,
+    ) -> Result<DownloadDirectory, DbError> {
+        let mut state = self.state();
+        let ordinal = match state.users.iter_mut().find(|stored| stored.id == user.id) {
+            Some(stored) => {
+                stored.last_dir_ordinal += 1;
+                stored.last_dir_ordinal
+            }
+            None => return Err("User not found!".to_owned().into()),
+        };
+        let dir = DownloadDirectory {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            alias: alias.to_owned(),
+            path: path.to_owned(),
+            ordinal,
+            created_at: Utc::now().naive_utc(),
+            server_id,
+            is_default: false,
+            position: ordinal,
+        };
+        state.dirs.push(dir.clone());
+        Ok(dir)
+    }
+
+    async fn get_directory(&self, user: &User, ordinal: i32) -> Result<Option<DownloadDirectory>, DbError> {
+        Ok(self.state().dirs.iter()
+            .find(|dir| dir.user_id == user.id && dir.ordinal == ordinal)
+            .cloned())
+    }
+
+    async fn get_directories(&self, user: &User) -> Result<Vec<DownloadDirectory>, DbError> {
+        let mut dirs: Vec<DownloadDirectory> = self.state().dirs.iter()
+            .filter(|dir| dir.user_id == user.id)
+            .cloned()
+            .collect();
+        dirs.sort_by_key(|dir| dir.position);
+        Ok(dirs)
+    }
+
+    async fn get_server_directories(&self, user: &User, server_id: &Uuid) -> Result<Vec<DownloadDirectory>, DbError> {
+        let mut dirs: Vec<DownloadDirectory> = self.state().dirs.iter()
+            .filter(|dir| dir.user_id == user.id && dir.server_id.is_none_or(|id| id == *server_id))
+            .cloned()
+            .collect();
+        dirs.sort_by_key(|dir| dir.position);
+        Ok(dirs)
+    }
+
+    async fn rename_directory(&self, user: &User, ordinal: i32, alias: &str) -> Result<(), DbError> {
+        self.update_directory(user, ordinal, |dir| dir.alias = alias.to_owned());
+        Ok(())
+    }
+
+    async fn set_directory_path(&self, user: &User, ordinal: i32, path: &str) -> Result<(), DbError> {
+        self.update_directory(user, ordinal, |dir| dir.path = path.to_owned());
+        Ok(())
+    }
+
+    async fn swap_directories(&self, user: &User, first: i32, second: i32) -> Result<(), DbError> {
+        let positions = {
+            let state = self.state();
+            let position = |ordinal: i32| state.dirs.iter()
+                .find(|dir| dir.user_id == user.id && dir.ordinal == ordinal)
+                .map(|dir| dir.position);
+            position(first).zip(position(second))
+        };
+        let (first_position, second_position) = positions.ok_or(DbError::from("Directory not found!".to_owned()))?;
+        self.update_directory(user, first, |dir| dir.position = second_position);
+        self.update_directory(user, second, |dir| dir.position = first_position);
+        Ok(())
+    }
+
+    async fn set_default_directory(&self, user: &User, ordinal: i32) -> Result<(), DbError> {
+        for dir in self.state().dirs.iter_mut().filter(|dir| dir.user_id == user.id) {
+            dir.is_default = dir.ordinal == ordinal;
+        }
+        Ok(())
+    }
+
+    async fn delete_directory(&self, user: &User, ordinal: i32) -> Result<(), DbError> {
+        self.state().dirs.retain(|dir| dir.user_id != user.id || dir.ordinal != ordinal);
+        Ok(())
+    }
+
+    async fn delete_directories(&self, user: &User) -> Result<(), DbError> {
+        self.state().dirs.retain(|dir| dir.user_id != user.id);
+        Ok(())
+    }
+
+    /// The password is kept as is, there is nothing to encrypt it for
+    async fn add_server(&self, user: &User, server: &NewServer) -> Result<Server, DbError> {
+        let mut state = self.state();
+        let own = state.servers.iter().filter(|server| server.user_id == user.id).count();
+        let auth = server.auth();
+        let endpoint = server.endpoint();
+        let server = Server {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            url: server.url().get_base_url(),
+            username: auth.clone().map(|auth| auth.username),
+            password: auth.map(|auth| auth.password),
+            created_at: Utc::now().naive_utc(),
+            alias: server.alias(),
+            short_id: state.servers.len() as i32 + 1,
+            is_default: own == 0,
+            kind: endpoint.kind.to_string(),
+            endpoint: Some(endpoint.url.to_string()),
+            tls: endpoint.tls.to_string(),
+            ca_cert: endpoint.tls.ca_cert(),
+        };
+        state.servers.push(server.clone());
+        Ok(server)
+    }
+
+    async fn get_available_servers(&self, user: &User) -> Result<Vec<Server>, DbError> {
+        let state = self.state();
+        let mut servers: Vec<Server> = state.servers.iter()
+            .filter(|server| server.user_id == user.id)
+            .cloned()
+            .collect();
+        servers.extend(state.shared_servers(user));
+        Ok(servers)
+    }
+
+    async fn get_default_server(&self, user: &User) -> Result<Option<Server>, DbError> {
+        let state = self.state();
+        let own: Vec<&Server> = state.servers.iter().filter(|server| server.user_id == user.id).collect();
+        Ok(own.iter()
+            .find(|server| server.is_default)
+            .or(own.first())
+            .map(|server| (*server).clone()))
+    }
+
+    async fn find_server_by_id(&self, id: &Uuid) -> Result<Option<Server>, DbError> {
+        Ok(self.state().servers.iter().find(|server| server.id == *id).cloned())
+    }
+
+    async fn get_servers_by_user_id(&self, user: &User) -> Result<Vec<Server>, DbError> {
+        let mut servers: Vec<Server> = self.state().servers.iter()
+            .filter(|server| server.user_id == user.id)
+            .cloned()
+            .collect();
+        servers.sort_by_key(|server| server.short_id);
+        Ok(servers)
+    }
+
+    async fn get_shared_servers(&self, user: &User) -> Result<Vec<Server>, DbError> {
+        Ok(self.state().shared_servers(user))
+    }
+
+    /// Nothing is encrypted, so every password is readable
+    async fn get_unreadable_servers(&self, _user: &User) -> Result<Vec<Server>, DbError> {
+        Ok(vec![])
+    }
+
+    async fn get_server_by_short_id(&self, user: &User, short_id: i32) -> Result<Option<Server>, DbError> {
+        Ok(self.state().servers.iter()
+            .find(|server| server.user_id == user.id && server.short_id == short_id)
+            .cloned())
+    }
+
+    async fn get_server_details_by_short_id(&self, user: &User, short_id: i32) -> Result<Option<Server>, DbError> {
+        Ok(self.get_server_by_short_id(user, short_id).await?
+            .map(|server| Server { password: None, ..server }))
+    }
+
+    async fn set_default_server(&self, user: &User, id: &Uuid) -> Result<(), DbError> {
+        for server in self.state().servers.iter_mut().filter(|server| server.user_id == user.id) {
+            server.is_default = server.id == *id;
+        }
+        Ok(())
+    }
+
+    async fn update_server(
+        &self,
+        user: &User,
+        id: &Uuid,
+        url: &str,
+        alias: &str,
+        endpoint: &ServerEndpoint,
+        auth: Option<Authentication>,
+    ) -> Result<Option<Server>, DbError> {
+        let mut state = self.state();
+        let server = match state.servers.iter_mut().find(|server| server.user_id == user.id && server.id == *id) {
+            Some(server) => server,
+            None => return Ok(None),
+        };
+        server.url = url.to_owned();
+        server.alias = alias.to_owned();
+        if let Some(auth) = auth {
+            server.username = Some(auth.username);
+            server.password = Some(auth.password);
+        }
+        server.kind = endpoint.kind.to_string();
+        server.endpoint = Some(endpoint.url.to_string());
+        server.tls = endpoint.tls.to_string();
+        server.ca_cert = endpoint.tls.ca_cert();
+        Ok(Some(server.clone()))
+    }
+
+    async fn delete_servers(&self, user: &User) -> Result<(), DbError> {
+        self.state().servers.retain(|server| server.user_id != user.id);
+        Ok(())
+    }
+
+    async fn add_task(&self, user: &User, server_id: &Uuid, magnet: &Magnet) -> Result<DownloadTask, DbError> {
+        let task = DownloadTask {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            server_id: *server_id,
+            magnet_id: magnet.id,
+            status: TaskStatus::Created.to_string(),
+            description: None,
+            created_at: Utc::now().naive_utc(),
+        };
+        self.state().tasks.push(task.clone());
+        Ok(task)
+    }
+
+    async fn get_task_by_id(&self, id: &Uuid) -> Result<Option<DownloadTask>, DbError> {
+        Ok(self.state().tasks.iter().find(|task| task.id == *id).cloned())
+    }
+
+    async fn set_task_status(&self, id: &Uuid, status: TaskStatus) -> Result<(), DbError> {
+        if let Some(task) = self.state().tasks.iter_mut().find(|task| task.id == *id) {
+            task.status = status.to_string();
+        }
+        Ok(())
+    }
+
+    async fn get_active_tasks(&self) -> Result<Vec<DownloadTask>, DbError> {
+        Ok(self.state().tasks.iter()
+            .filter(|task| matches!(task.status(), TaskStatus::Created | TaskStatus::Started))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_user_tasks_page(&self, user: &User, offset: i64, limit: i64) -> Result<Vec<(DownloadTask, Magnet)>, DbError> {
+        let state = self.state();
+        // the tasks are kept in the order they were added
+        Ok(state.tasks.iter()
+            .rev()
+            .filter(|task| task.user_id == user.id)
+            .filter_map(|task| state.magnets.iter()
+                .find(|magnet| magnet.id == task.magnet_id)
+                .map(|magnet| (task.clone(), magnet.clone())))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn user_tasks_count(&self, user: &User) -> Result<i64, DbError> {
+        Ok(self.state().tasks.iter().filter(|task| task.user_id == user.id).count() as i64)
+    }
+
+    async fn tasks_count_by_server_id(&self, id: &Uuid) -> Result<i64, DbError> {
+        Ok(self.state().tasks.iter().filter(|task| task.server_id == *id).count() as i64)
+    }
+
+    async fn register_magnet(&self, user: &User, url: &str) -> Result<Uuid, DbError> {
+        self.register(user, url, None)
+    }
+
+    async fn register_torrent_file(&self, user: &User, url: &str, metainfo: &str) -> Result<Uuid, DbError> {
+        self.register(user, url, Some(metainfo.to_owned()))
+    }
+
+    async fn set_magnet_server(&self, user: &User, id: &Uuid, server_id: &Uuid) -> Result<(), DbError> {
+        if let Some(magnet) = self.state().magnets.iter_mut()
+            .find(|magnet| magnet.id == *id && magnet.user_id == user.id) {
+            magnet.server_id = Some(*server_id);
+        }
+        Ok(())
+    }
+
+    async fn get_magnet_by_id(&self, user: &User, id: Uuid) -> Result<Option<Magnet>, DbError> {
+        Ok(self.state().magnets.iter()
+            .find(|magnet| magnet.id == id && magnet.user_id == user.id)
+            .cloned())
+    }
+
+    async fn get_friends(&self, user_id: &u64) -> Result<Vec<User>, DbError> {
+        let state = self.state();
+        Ok(state.friends.iter()
+            .filter(|(id, _)| *id == *user_id as i64)
+            .filter_map(|(_, friend_id)| state.users.iter().find(|user| user.id == *friend_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn find_friend(&self, user_id: &i64, friend_id: &i64) -> Result<Option<User>, DbError> {
+        let state = self.state();
+        if !state.friends.contains(&(*user_id, *friend_id)) {
+            return Ok(None);
+        }
+        Ok(state.users.iter().find(|user| user.id == *friend_id).cloned())
+    }
+
+    async fn add_friend(&self, user_id: &i64, friend_id: &i64) -> Result<Uuid, DbError> {
+        self.state().friends.push((*user_id, *friend_id));
+        Ok(Uuid::new_v4())
+    }
+
+    async fn delete_friend(&self, user_id: &i64, friend_id: &i64) -> Result<(), DbError> {
+        self.state().friends.retain(|friends| *friends != (*user_id, *friend_id));
+        Ok(())
+    }
+
+    async fn share_server(&self, server_id: &Uuid, friend_id: &i64) -> Result<(), DbError> {
+        let mut state = self.state();
+        if !state.shares.iter().any(|share| share.server_id == *server_id && share.friend_user_id == *friend_id) {
+            state.shares.push(ServerShare {
+                id: Uuid::new_v4(),
+                server_id: *server_id,
+                friend_user_id: *friend_id,
+                created_at: Utc::now().naive_utc(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn unshare_server(&self, server_id: &Uuid, friend_id: &i64) -> Result<(), DbError> {
+        self.state().shares.retain(|share| share.server_id != *server_id || share.friend_user_id != *friend_id);
+        Ok(())
+    }
+
+    async fn get_shares_by_owner(&self, owner: &User) -> Result<Vec<ServerShare>, DbError> {
+        let state = self.state();
+        Ok(state.shares.iter()
+            .filter(|share| state.owner_of(&share.server_id) == Some(owner.id))
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_shares_by_owner(&self, owner: &User) -> Result<Vec<ServerShare>, DbError> {
+        let shares = self.get_shares_by_owner(owner).await?;
+        self.state().shares.retain(|share| !shares.contains(share));
+        Ok(shares)
+    }
+
+    async fn delete_shares_between(&self, user_id: &i64, friend_id: &i64) -> Result<(), DbError> {
+        let mut state = self.state();
+        let between = |share: &ServerShare, owner: Option<i64>| {
+            (owner == Some(*user_id) && share.friend_user_id == *friend_id)
+                || (owner == Some(*friend_id) && share.friend_user_id == *user_id)
+        };
+        let removed: Vec<Uuid> = state.shares.iter()
+            .filter(|share| between(share, state.owner_of(&share.server_id)))
+            .map(|share| share.id)
+            .collect();
+        state.shares.retain(|share| !removed.contains(&share.id));
+        Ok(())
+    }
+}
+
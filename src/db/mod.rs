@@ -1,3 +1,7 @@
 pub mod db_config;
 pub(crate) mod models;
 pub mod repository;
+pub mod repo;
+#[cfg(test)]
+pub(crate) mod memory;
+pub mod dialogue_storage;
@@ -0,0 +1,8 @@
+use crate::schema::dialogues;
+
+#[derive(Insertable)]
+#[diesel(table_name = dialogues)]
+pub struct NewDialogue {
+    pub chat_id: i64,
+    pub state: String,
+}
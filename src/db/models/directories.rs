@@ -10,6 +10,10 @@ pub struct DownloadDirectory {
     pub path: String,
     pub ordinal: i32,
     pub created_at: NaiveDateTime,
+    pub server_id: Option<Uuid>,
+    pub is_default: bool,
+    /// The place in the lists, the ordinal stays the same when the directory is moved
+    pub position: i32,
 }
 
 #[derive(Insertable)]
@@ -20,16 +24,20 @@ pub struct NewDownloadDirectory {
     alias: String,
     path: String,
     ordinal: i32,
+    server_id: Option<Uuid>,
+    position: i32,
 }
 
 impl NewDownloadDirectory {
-    pub fn new(user_id: i64, alias: String, path: String, ordinal: i32) -> Self {
+    pub fn new(user_id: i64, alias: String, path: String, ordinal: i32, server_id: Option<Uuid>) -> Self {
         NewDownloadDirectory {
             id: Uuid::new_v4(),
             user_id,
             alias,
             path,
             ordinal,
+            server_id,
+            position: ordinal,
         }
     }
 }
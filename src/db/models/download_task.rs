@@ -15,7 +15,6 @@ pub struct DownloadTask {
 }
 
 impl DownloadTask {
-    #[allow(dead_code)]
     pub fn status(self: &Self) -> TaskStatus {
         TaskStatus::from(self.status.clone())
     }
@@ -57,6 +56,10 @@ pub enum TaskStatus {
     Started,
     Finished,
     Error,
+    /// Removed from the server, the files are kept
+    Removed,
+    /// Removed from the server together with the files
+    Deleted,
 }
 
 impl From<diesel::sql_types::Text> for TaskStatus {
@@ -73,6 +76,8 @@ impl From<String> for TaskStatus {
             "started" => TaskStatus::Started,
             "finished" => TaskStatus::Finished,
             "error" => TaskStatus::Error,
+            "removed" => TaskStatus::Removed,
+            "deleted" => TaskStatus::Deleted,
             _ => panic!(),
         }
     }
@@ -85,6 +90,8 @@ impl ToString for TaskStatus {
             TaskStatus::Started => "started",
             TaskStatus::Finished => "finished",
             TaskStatus::Error => "error",
+            TaskStatus::Removed => "removed",
+            TaskStatus::Deleted => "deleted",
         }
         .to_owned()
     }
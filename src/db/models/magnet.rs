@@ -8,6 +8,8 @@ pub struct Magnet {
     pub user_id: i64,
     pub url: String,
     pub created_at: NaiveDateTime,
+    pub metainfo: Option<String>,
+    pub server_id: Option<Uuid>,
 }
 
 #[derive(Insertable)]
@@ -16,14 +18,16 @@ pub struct NewMagnet {
     id: Uuid,
     user_id: i64,
     url: String,
+    metainfo: Option<String>,
 }
 
 impl NewMagnet {
-    pub fn new(user_id: i64, url: String) -> Self {
+    pub fn new(user_id: i64, url: String, metainfo: Option<String>) -> Self {
         NewMagnet {
             id: Uuid::new_v4(),
             user_id,
             url,
+            metainfo,
         }
     }
 }
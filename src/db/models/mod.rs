@@ -4,3 +4,5 @@ pub(crate) mod magnet;
 pub(crate) mod server;
 pub(crate) mod user;
 pub(crate) mod friends;
+pub(crate) mod dialogue;
+pub(crate) mod server_share;
@@ -1,10 +1,12 @@
 use crate::core::trans_url::TransUrl;
 use chrono::NaiveDateTime;
+use std::fmt;
+use url::Url;
 use uuid::Uuid;
 
 use crate::schema::servers;
 
-#[derive(Insertable)]
+#[derive(Insertable, Clone)]
 #[diesel(table_name = servers)]
 pub struct NewServer {
     id: Uuid,
@@ -12,6 +14,12 @@ pub struct NewServer {
     url: String,
     username: Option<String>,
     password: Option<String>,
+    alias: String,
+    is_default: bool,
+    kind: String,
+    endpoint: Option<String>,
+    tls: String,
+    ca_cert: Option<String>,
 }
 
 impl NewServer {
@@ -33,7 +41,7 @@ impl NewServer {
 }
 
 impl NewServer {
-    pub fn new(user_id: u64, url: String, auth: Option<Authentication>) -> Self {
+    pub fn new(user_id: u64, url: String, alias: String, auth: Option<Authentication>) -> Self {
         let username = auth.clone().map(|a| a.username);
         let password = auth.map(|a| a.password);
         NewServer {
@@ -42,6 +50,53 @@ impl NewServer {
             url,
             username,
             password,
+            alias,
+            is_default: false,
+            kind: ServerKind::Transmission.to_string(),
+            endpoint: None,
+            tls: TlsOptions::Verify.to_string(),
+            ca_cert: None,
+        }
+    }
+
+    pub fn alias(&self) -> String {
+        self.alias.clone()
+    }
+
+    pub fn kind(&self) -> ServerKind {
+        ServerKind::from(self.kind.clone())
+    }
+
+    pub fn with_default(self, is_default: bool) -> Self {
+        NewServer { is_default, ..self }
+    }
+
+    pub fn tls(&self) -> TlsOptions {
+        TlsOptions::from_columns(&self.tls, &self.ca_cert)
+    }
+
+    pub fn endpoint(&self) -> ServerEndpoint {
+        ServerEndpoint::from_columns(self.kind(), &self.url(), &self.endpoint, self.tls())
+    }
+
+    /// Every url of every kind of client the server may answer at, in the order they are probed
+    pub fn candidate_endpoints(&self) -> Vec<ServerEndpoint> {
+        ServerKind::ALL
+            .into_iter()
+            .flat_map(|kind| kind.endpoints(&self.url()).into_iter().map(move |url| (kind, url)))
+            .map(|(kind, url)| ServerEndpoint { kind, url, tls: self.tls() })
+            .collect()
+    }
+
+    pub fn with_tls(self, tls: TlsOptions) -> Self {
+        NewServer { tls: tls.to_string(), ca_cert: tls.ca_cert(), ..self }
+    }
+
+    pub fn with_endpoint(self, endpoint: &ServerEndpoint) -> Self {
+        NewServer {
+            kind: endpoint.kind.to_string(),
+            endpoint: Some(endpoint.url.to_string()),
+            ..self.with_tls(endpoint.tls.clone())
         }
     }
 }
@@ -54,6 +109,13 @@ pub struct Server {
     pub username: Option<String>,
     pub password: Option<String>,
     pub created_at: NaiveDateTime,
+    pub alias: String,
+    pub short_id: i32,
+    pub is_default: bool,
+    pub kind: String,
+    pub endpoint: Option<String>,
+    pub tls: String,
+    pub ca_cert: Option<String>,
 }
 
 impl Server {
@@ -61,6 +123,15 @@ impl Server {
         TransUrl::from(self.url.clone())
     }
 
+    pub fn kind(&self) -> ServerKind {
+        ServerKind::from(self.kind.clone())
+    }
+
+    pub fn endpoint(&self) -> ServerEndpoint {
+        let tls = TlsOptions::from_columns(&self.tls, &self.ca_cert);
+        ServerEndpoint::from_columns(self.kind(), &self.url(), &self.endpoint, tls)
+    }
+
     pub fn auth(self: &Self) -> Option<Authentication> {
         self.username
             .clone()
@@ -79,3 +150,110 @@ pub struct Authentication {
     pub username: String,
     pub password: String,
 }
+
+/// The torrent client running on the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerKind {
+    Transmission,
+    QBittorrent,
+    Deluge,
+}
+
+impl ServerKind {
+    /// In the order the kinds are tried when a server is registered
+    pub const ALL: [ServerKind; 3] = [ServerKind::Transmission, ServerKind::QBittorrent, ServerKind::Deluge];
+
+    /// Urls the api of the client may answer at, the most likely first
+    pub fn endpoints(&self, url: &TransUrl) -> Vec<Url> {
+        match self {
+            ServerKind::Transmission => url.rpc_urls(),
+            ServerKind::QBittorrent => vec![url.to_api_url("/api/v2")],
+            ServerKind::Deluge => vec![url.to_api_url("/json")],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServerKind::Transmission => "Transmission",
+            ServerKind::QBittorrent => "qBittorrent",
+            ServerKind::Deluge => "Deluge",
+        }
+    }
+}
+
+impl From<String> for ServerKind {
+    fn from(str: String) -> Self {
+        match str.as_ref() {
+            "qbittorrent" => ServerKind::QBittorrent,
+            "deluge" => ServerKind::Deluge,
+            _ => ServerKind::Transmission,
+        }
+    }
+}
+
+impl fmt::Display for ServerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ServerKind::Transmission => "transmission",
+            ServerKind::QBittorrent => "qbittorrent",
+            ServerKind::Deluge => "deluge",
+        })
+    }
+}
+
+/// How the certificate of a server behind https is checked
+#[derive(Debug, Clone, PartialEq)]
+pub enum TlsOptions {
+    /// Against the certificate authorities known to the system
+    Verify,
+    /// Any certificate is accepted, e.g. a self-signed one
+    AcceptInvalid,
+    /// Only against the certificate authority in PEM
+    PinnedCa(String),
+}
+
+impl TlsOptions {
+    fn from_columns(tls: &str, ca_cert: &Option<String>) -> Self {
+        match (tls, ca_cert) {
+            ("insecure", _) => TlsOptions::AcceptInvalid,
+            ("pinned", Some(pem)) => TlsOptions::PinnedCa(pem.clone()),
+            _ => TlsOptions::Verify,
+        }
+    }
+
+    pub fn ca_cert(&self) -> Option<String> {
+        match self {
+            TlsOptions::PinnedCa(pem) => Some(pem.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TlsOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TlsOptions::Verify => "verify",
+            TlsOptions::AcceptInvalid => "insecure",
+            TlsOptions::PinnedCa(_) => "pinned",
+        })
+    }
+}
+
+/// Where and how the api of the torrent client is reached
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerEndpoint {
+    pub kind: ServerKind,
+    pub url: Url,
+    pub tls: TlsOptions,
+}
+
+impl ServerEndpoint {
+    /// Servers registered before the endpoints were stored use the default one of their kind
+    fn from_columns(kind: ServerKind, url: &TransUrl, endpoint: &Option<String>, tls: TlsOptions) -> Self {
+        let url = endpoint
+            .as_ref()
+            .and_then(|endpoint| endpoint.parse().ok())
+            .unwrap_or_else(|| kind.endpoints(url).remove(0));
+        ServerEndpoint { kind, url, tls }
+    }
+}
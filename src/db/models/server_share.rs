@@ -0,0 +1,19 @@
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use crate::schema::server_shares;
+
+#[derive(Queryable, PartialEq, Clone, Debug)]
+pub struct ServerShare {
+    pub id: Uuid,
+    pub server_id: Uuid,
+    pub friend_user_id: i64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Clone)]
+#[diesel(table_name = server_shares)]
+pub struct NewServerShare {
+    pub server_id: Uuid,
+    pub friend_user_id: i64,
+}
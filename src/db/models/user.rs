@@ -13,6 +13,14 @@ pub struct User {
     pub username: Option<String>,
     pub salt: String,
     pub created_at: NaiveDateTime,
+    /// The last ordinal given to a directory, never reused so old keyboards can't pick another one
+    pub last_dir_ordinal: i32,
+}
+
+impl User {
+    pub fn display_name(&self) -> String {
+        self.username.clone().unwrap_or(self.first_name.clone())
+    }
 }
 
 impl Into<Recipient> for User {
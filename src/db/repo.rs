@@ -0,0 +1,324 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::errors::DbError;
+
+use super::models::{
+    directories::DownloadDirectory,
+    download_task::{DownloadTask, TaskStatus},
+    magnet::Magnet,
+    server::{Authentication, NewServer, Server, ServerEndpoint},
+    server_share::ServerShare,
+    user::{NewUser, User},
+};
+use super::repository::{self, Pool};
+
+/// Data access used by the conversation handlers, so they can run against
+/// the database or against [`InMemoryRepository`](super::memory::InMemoryRepository) in tests.
+/// The pool itself is the diesel implementation
+#[async_trait]
+pub trait Repository: Send + Sync {
+    // USERS
+    async fn get_user(&self, id: &i64) -> Result<Option<User>, DbError>;
+    async fn save_user(&self, user: NewUser) -> Result<User, DbError>;
+
+    // DIRECTORIES
+    async fn add_directory(
+        &self,
+        user: &User,
+        alias: &str,
+        path: &str,
+        server_id: Option<Uuid>,
+    ) -> Result<DownloadDirectory, DbError>;
+    async fn get_directory(&self, user: &User, ordinal: i32) -> Result<Option<DownloadDirectory>, DbError>;
+    async fn get_directories(&self, user: &User) -> Result<Vec<DownloadDirectory>, DbError>;
+    async fn get_server_directories(&self, user: &User, server_id: &Uuid) -> Result<Vec<DownloadDirectory>, DbError>;
+    async fn rename_directory(&self, user: &User, ordinal: i32, alias: &str) -> Result<(), DbError>;
+    async fn set_directory_path(&self, user: &User, ordinal: i32, path: &str) -> Result<(), DbError>;
+    /// Swaps the position of two directories, the ordinals are kept
+    async fn swap_directories(&self, user: &User, first: i32, second: i32) -> Result<(), DbError>;
+    async fn set_default_directory(&self, user: &User, ordinal: i32) -> Result<(), DbError>;
+    async fn delete_directory(&self, user: &User, ordinal: i32) -> Result<(), DbError>;
+    async fn delete_directories(&self, user: &User) -> Result<(), DbError>;
+
+    // SERVERS
+    /// Saves a server of the user, the first one becomes the default
+    async fn add_server(&self, user: &User, server: &NewServer) -> Result<Server, DbError>;
+    /// Own servers of the user followed by the servers shared with the user
+    async fn get_available_servers(&self, user: &User) -> Result<Vec<Server>, DbError>;
+    /// The own server marked as default or the first registered one
+    async fn get_default_server(&self, user: &User) -> Result<Option<Server>, DbError>;
+    /// Any server regardless of its owner, for the background jobs
+    async fn find_server_by_id(&self, id: &Uuid) -> Result<Option<Server>, DbError>;
+    async fn get_servers_by_user_id(&self, user: &User) -> Result<Vec<Server>, DbError>;
+    /// Servers of other users shared with the user
+    async fn get_shared_servers(&self, user: &User) -> Result<Vec<Server>, DbError>;
+    /// Own servers with a password which can't be decrypted anymore, returned without the password
+    async fn get_unreadable_servers(&self, user: &User) -> Result<Vec<Server>, DbError>;
+    async fn get_server_by_short_id(&self, user: &User, short_id: i32) -> Result<Option<Server>, DbError>;
+    /// The own server without the password, so it can be edited even when the password is unreadable
+    async fn get_server_details_by_short_id(&self, user: &User, short_id: i32) -> Result<Option<Server>, DbError>;
+    async fn set_default_server(&self, user: &User, id: &Uuid) -> Result<(), DbError>;
+    async fn update_server(
+        &self,
+        user: &User,
+        id: &Uuid,
+        url: &str,
+        alias: &str,
+        endpoint: &ServerEndpoint,
+        auth: Option<Authentication>,
+    ) -> Result<Option<Server>, DbError>;
+    async fn delete_servers(&self, user: &User) -> Result<(), DbError>;
+
+    async fn get_available_server_by_id(&self, user: &User, id: &Uuid) -> Result<Option<Server>, DbError> {
+        Ok(self.get_available_servers(user).await?
+            .into_iter()
+            .find(|server| server.id == *id))
+    }
+
+    async fn get_available_server_by_short_id(&self, user: &User, short_id: i32) -> Result<Option<Server>, DbError> {
+        Ok(self.get_available_servers(user).await?
+            .into_iter()
+            .find(|server| server.short_id == short_id))
+    }
+
+    // TASKS
+    async fn add_task(&self, user: &User, server_id: &Uuid, magnet: &Magnet) -> Result<DownloadTask, DbError>;
+    async fn get_task_by_id(&self, id: &Uuid) -> Result<Option<DownloadTask>, DbError>;
+    async fn set_task_status(&self, id: &Uuid, status: TaskStatus) -> Result<(), DbError>;
+    /// Tasks of all users which are neither finished nor removed
+    async fn get_active_tasks(&self) -> Result<Vec<DownloadTask>, DbError>;
+    /// A page of the user tasks with their magnets, the newest first
+    async fn get_user_tasks_page(&self, user: &User, offset: i64, limit: i64) -> Result<Vec<(DownloadTask, Magnet)>, DbError>;
+    async fn user_tasks_count(&self, user: &User) -> Result<i64, DbError>;
+    async fn tasks_count_by_server_id(&self, id: &Uuid) -> Result<i64, DbError>;
+
+    // MAGNETS
+    async fn register_magnet(&self, user: &User, url: &str) -> Result<Uuid, DbError>;
+    async fn register_torrent_file(&self, user: &User, url: &str, metainfo: &str) -> Result<Uuid, DbError>;
+    async fn set_magnet_server(&self, user: &User, id: &Uuid, server_id: &Uuid) -> Result<(), DbError>;
+    async fn get_magnet_by_id(&self, user: &User, id: Uuid) -> Result<Option<Magnet>, DbError>;
+
+    // FRIENDS
+    async fn get_friends(&self, user_id: &u64) -> Result<Vec<User>, DbError>;
+    async fn find_friend(&self, user_id: &i64, friend_id: &i64) -> Result<Option<User>, DbError>;
+    async fn add_friend(&self, user_id: &i64, friend_id: &i64) -> Result<Uuid, DbError>;
+    async fn delete_friend(&self, user_id: &i64, friend_id: &i64) -> Result<(), DbError>;
+
+    // SHARES
+    async fn share_server(&self, server_id: &Uuid, friend_id: &i64) -> Result<(), DbError>;
+    async fn unshare_server(&self, server_id: &Uuid, friend_id: &i64) -> Result<(), DbError>;
+    /// Shares of all the servers of the owner
+    async fn get_shares_by_owner(&self, owner: &User) -> Result<Vec<ServerShare>, DbError>;
+    /// Removes all the shares of the owner servers and returns what was removed
+    async fn delete_shares_by_owner(&self, owner: &User) -> Result<Vec<ServerShare>, DbError>;
+    /// Removes the shares between two users in both directions
+    async fn delete_shares_between(&self, user_id: &i64, friend_id: &i64) -> Result<(), DbError>;
+}
+
+#[async_trait]
+impl Repository for Pool {
+    async fn get_user(&self, id: &i64) -> Result<Option<User>, DbError> {
+        repository::get_user(self, id).await
+    }
+
+    async fn save_user(&self, user: NewUser) -> Result<User, DbError> {
+        repository::save_user(self, user).await
+    }
+
+    async fn add_directory(
+        &self,
+        user: &User,
+        alias: &str,
+        path: &str,
+        server_id: Option<Uuid>,
+    ) -> Result<DownloadDirectory, DbError> {
+        repository::add_directory(self, user, &alias.to_owned(), &path.to_owned(), server_id).await
+    }
+
+    async fn get_directory(&self, user: &User, ordinal: i32) -> Result<Option<DownloadDirectory>, DbError> {
+        repository::get_directory(self, user, ordinal).await
+    }
+
+    async fn get_directories(&self, user: &User) -> Result<Vec<DownloadDirectory>, DbError> {
+        repository::get_directories(self, user).await
+    }
+
+    async fn get_server_directories(&self, user: &User, server_id: &Uuid) -> Result<Vec<DownloadDirectory>, DbError> {
+        repository::get_server_directories(self, user, server_id).await
+    }
+
+    async fn rename_directory(&self, user: &User, ordinal: i32, alias: &str) -> Result<(), DbError> {
+        repository::rename_directory(self, user, ordinal, alias).await
+    }
+
+    async fn set_directory_path(&self, user: &User, ordinal: i32, path: &str) -> Result<(), DbError> {
+        repository::set_directory_path(self, user, ordinal, path).await
+    }
+
+    async fn swap_directories(&self, user: &User, first: i32, second: i32) -> Result<(), DbError> {
+        repository::swap_directories(self, user, first, second).await
+    }
+
+    async fn set_default_directory(&self, user: &User, ordinal: i32) -> Result<(), DbError> {
+        repository::set_default_directory(self, user, ordinal).await
+    }
+
+    async fn delete_directory(&self, user: &User, ordinal: i32) -> Result<(), DbError> {
+        repository::delete_directory(self, user.clone(), ordinal).await
+    }
+
+    async fn delete_directories(&self, user: &User) -> Result<(), DbError> {
+        repository::delete_directories(self, user.clone()).await
+    }
+
+    async fn add_server(&self, user: &User, server: &NewServer) -> Result<Server, DbError> {
+        let url = server.url().get_base_url();
+        match server.auth() {
+            Some(auth) => {
+                repository::add_server_auth(
+                    self,
+                    user,
+                    &url,
+                    &server.alias(),
+                    &server.endpoint(),
+                    &auth.username,
+                    &auth.password,
+                ).await
+            }
+            None => repository::add_server(self, user, &url, &server.alias(), &server.endpoint()).await,
+        }
+    }
+
+    async fn get_available_servers(&self, user: &User) -> Result<Vec<Server>, DbError> {
+        repository::get_available_servers(self, user).await
+    }
+
+    async fn get_default_server(&self, user: &User) -> Result<Option<Server>, DbError> {
+        repository::get_default_server(self, user).await
+    }
+
+    async fn find_server_by_id(&self, id: &Uuid) -> Result<Option<Server>, DbError> {
+        repository::find_server_by_id(self, id).await
+    }
+
+    async fn get_servers_by_user_id(&self, user: &User) -> Result<Vec<Server>, DbError> {
+        repository::get_servers_by_user_id(self, user).await
+    }
+
+    async fn get_shared_servers(&self, user: &User) -> Result<Vec<Server>, DbError> {
+        repository::get_shared_servers(self, user).await
+    }
+
+    async fn get_unreadable_servers(&self, user: &User) -> Result<Vec<Server>, DbError> {
+        repository::get_unreadable_servers(self, user).await
+    }
+
+    async fn get_server_by_short_id(&self, user: &User, short_id: i32) -> Result<Option<Server>, DbError> {
+        repository::get_server_by_short_id(self, user, short_id).await
+    }
+
+    async fn get_server_details_by_short_id(&self, user: &User, short_id: i32) -> Result<Option<Server>, DbError> {
+        repository::get_server_details_by_short_id(self, user, short_id).await
+    }
+
+    async fn set_default_server(&self, user: &User, id: &Uuid) -> Result<(), DbError> {
+        repository::set_default_server(self, user, id).await
+    }
+
+    async fn update_server(
+        &self,
+        user: &User,
+        id: &Uuid,
+        url: &str,
+        alias: &str,
+        endpoint: &ServerEndpoint,
+        auth: Option<Authentication>,
+    ) -> Result<Option<Server>, DbError> {
+        repository::update_server(self, user, id, url, alias, endpoint, auth).await
+    }
+
+    async fn delete_servers(&self, user: &User) -> Result<(), DbError> {
+        repository::delete_servers(self, user).await
+    }
+
+    async fn add_task(&self, user: &User, server_id: &Uuid, magnet: &Magnet) -> Result<DownloadTask, DbError> {
+        repository::add_task(self, user, server_id, magnet).await
+    }
+
+    async fn get_task_by_id(&self, id: &Uuid) -> Result<Option<DownloadTask>, DbError> {
+        repository::get_task_by_id(self, id).await
+    }
+
+    async fn set_task_status(&self, id: &Uuid, status: TaskStatus) -> Result<(), DbError> {
+        repository::set_task_status(self, id, status).await
+    }
+
+    async fn get_active_tasks(&self) -> Result<Vec<DownloadTask>, DbError> {
+        repository::get_active_tasks(self).await
+    }
+
+    async fn get_user_tasks_page(&self, user: &User, offset: i64, limit: i64) -> Result<Vec<(DownloadTask, Magnet)>, DbError> {
+        repository::get_user_tasks_page(self, user, offset, limit).await
+    }
+
+    async fn user_tasks_count(&self, user: &User) -> Result<i64, DbError> {
+        repository::user_tasks_count(self, user).await
+    }
+
+    async fn tasks_count_by_server_id(&self, id: &Uuid) -> Result<i64, DbError> {
+        repository::tasks_count_by_server_id(self, id).await
+    }
+
+    async fn register_magnet(&self, user: &User, url: &str) -> Result<Uuid, DbError> {
+        repository::register_magnet(self, user, &url.to_owned()).await
+    }
+
+    async fn register_torrent_file(&self, user: &User, url: &str, metainfo: &str) -> Result<Uuid, DbError> {
+        repository::register_torrent_file(self, user, url, metainfo).await
+    }
+
+    async fn set_magnet_server(&self, user: &User, id: &Uuid, server_id: &Uuid) -> Result<(), DbError> {
+        repository::set_magnet_server(self, user, id, server_id).await
+    }
+
+    async fn get_magnet_by_id(&self, user: &User, id: Uuid) -> Result<Option<Magnet>, DbError> {
+        repository::get_magnet_by_id(self, user, id).await
+    }
+
+    async fn get_friends(&self, user_id: &u64) -> Result<Vec<User>, DbError> {
+        repository::get_friends(self, user_id).await
+    }
+
+    async fn find_friend(&self, user_id: &i64, friend_id: &i64) -> Result<Option<User>, DbError> {
+        repository::find_friend(self, user_id, friend_id).await
+    }
+
+    async fn add_friend(&self, user_id: &i64, friend_id: &i64) -> Result<Uuid, DbError> {
+        repository::add_friend(self, user_id, friend_id).await
+    }
+
+    async fn delete_friend(&self, user_id: &i64, friend_id: &i64) -> Result<(), DbError> {
+        repository::delete_friend(self, user_id, friend_id).await
+    }
+
+    async fn share_server(&self, server_id: &Uuid, friend_id: &i64) -> Result<(), DbError> {
+        repository::share_server(self, server_id, friend_id).await
+    }
+
+    async fn unshare_server(&self, server_id: &Uuid, friend_id: &i64) -> Result<(), DbError> {
+        repository::unshare_server(self, server_id, friend_id).await
+    }
+
+    async fn get_shares_by_owner(&self, owner: &User) -> Result<Vec<ServerShare>, DbError> {
+        repository::get_shares_by_owner(self, owner).await
+    }
+
+    async fn delete_shares_by_owner(&self, owner: &User) -> Result<Vec<ServerShare>, DbError> {
+        repository::delete_shares_by_owner(self, owner).await
+    }
+
+    async fn delete_shares_between(&self, user_id: &i64, friend_id: &i64) -> Result<(), DbError> {
+        repository::delete_shares_between(self, user_id, friend_id).await
+    }
+}
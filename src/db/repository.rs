@@ -2,8 +2,8 @@ use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
 
 use crate::errors::DbError;
-use crate::core::crypto::{random_salt, Crypto};
-use crate::schema::{dirs, magnets, servers, tasks, users, friends};
+use crate::core::crypto::{CryptoError, Keyring};
+use crate::schema::{dialogues, dirs, magnets, server_shares, servers, tasks, users, friends};
 use diesel::prelude::*;
 use uuid::Uuid;
 
@@ -13,9 +13,11 @@ use super::models::{
     directories::{DownloadDirectory, NewDownloadDirectory},
     download_task::{DownloadTask, NewDownloadTask, TaskStatus},
     magnet::{Magnet, NewMagnet},
-    server::{Authentication, NewServer, Server},
+    server::{Authentication, NewServer, Server, ServerEndpoint},
     user::{NewUser, User},
     friends::NewFriend,
+    dialogue::NewDialogue,
+    server_share::{NewServerShare, ServerShare},
 };
 use log::*;
 
@@ -43,11 +45,17 @@ pub async fn add_directory(
     user: &User,
     alias: &String,
     path: &String,
+    server_id: Option<Uuid>,
 ) -> Result<DownloadDirectory, DbError> {
     let mut connection = pool.get()?;
     let next_ordinal = get_directory_next_ordinal(pool, user).await?;
-    let new_dir =
-        NewDownloadDirectory::new(user.id as i64, alias.to_owned(), path.to_owned(), next_ordinal);
+    let new_dir = NewDownloadDirectory::new(
+        user.id,
+        alias.to_owned(),
+        path.to_owned(),
+        next_ordinal,
+        server_id,
+    );
     let dir_id = diesel::insert_into(dirs::table)
         .values(&new_dir)
         .returning(dirs::id)
@@ -65,14 +73,13 @@ async fn get_directory_by_id(pool: &Pool, id: &Uuid) -> Result<DownloadDirectory
         .ok_or("Directory not found!".to_owned().into())
 }
 
+/// Ordinals of deleted directories are not reused, keyboards sent earlier may still refer to them
 pub(crate) async fn get_directory_next_ordinal(pool: &Pool, user: &User) -> Result<i32, DbError> {
     let mut connection = pool.get()?;
-    let last_ordinal: i32 = dirs::table
-        .filter(dirs::user_id.eq(&(user.id as i64)))
-        .select(diesel::dsl::max(dirs::ordinal))
-        .first::<Option<i32>>(&mut connection)?
-        .unwrap_or(0);
-    Ok(last_ordinal + 1)
+    Ok(diesel::update(users::table.filter(users::id.eq(user.id)))
+        .set(users::last_dir_ordinal.eq(users::last_dir_ordinal + 1))
+        .returning(users::last_dir_ordinal)
+        .get_result(&mut connection)?)
 }
 
 pub async fn get_directory(
@@ -87,10 +94,68 @@ pub async fn get_directory(
         .optional()?)
 }
 
+pub async fn rename_directory(pool: &Pool, user: &User, ordinal: i32, alias: &str) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    diesel::update(dirs::table.filter(dirs::user_id.eq(user.id).and(dirs::ordinal.eq(ordinal))))
+        .set(dirs::alias.eq(alias))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
+pub async fn set_directory_path(pool: &Pool, user: &User, ordinal: i32, path: &str) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    diesel::update(dirs::table.filter(dirs::user_id.eq(user.id).and(dirs::ordinal.eq(ordinal))))
+        .set(dirs::path.eq(path))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
+/// Swaps the position of two directories, the ordinals are kept
+/// so the directory choices which were already sent stay valid
+pub async fn swap_directories(pool: &Pool, user: &User, first: i32, second: i32) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    connection.transaction::<_, DbError, _>(|connection| {
+        let user_dirs = dirs::table.filter(dirs::user_id.eq(user.id));
+        let first_position = user_dirs.filter(dirs::ordinal.eq(first)).select(dirs::position).first::<i32>(connection)?;
+        let second_position = user_dirs.filter(dirs::ordinal.eq(second)).select(dirs::position).first::<i32>(connection)?;
+        diesel::update(user_dirs.filter(dirs::ordinal.eq(first)))
+            .set(dirs::position.eq(second_position))
+            .execute(connection)?;
+        diesel::update(user_dirs.filter(dirs::ordinal.eq(second)))
+            .set(dirs::position.eq(first_position))
+            .execute(connection)?;
+        Ok(())
+    })
+}
+
+pub async fn set_default_directory(pool: &Pool, user: &User, ordinal: i32) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    diesel::update(dirs::table.filter(dirs::user_id.eq(user.id)))
+        .set(dirs::is_default.eq(dirs::ordinal.eq(ordinal)))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
 pub async fn get_directories(pool: &Pool, user: &User) -> Result<Vec<DownloadDirectory>, DbError> {
     let mut connection = pool.get()?;
     dirs::table
         .filter(dirs::user_id.eq(&(user.id as i64)))
+        .order((dirs::position, dirs::ordinal))
+        .load::<DownloadDirectory>(&mut connection)
+        .map_err(|e| e.into())
+}
+
+/// Directories of the user which belong to the server or to no server in particular
+pub async fn get_server_directories(
+    pool: &Pool,
+    user: &User,
+    server_id: &Uuid,
+) -> Result<Vec<DownloadDirectory>, DbError> {
+    let mut connection = pool.get()?;
+    dirs::table
+        .filter(dirs::user_id.eq(user.id))
+        .filter(dirs::server_id.is_null().or(dirs::server_id.eq(server_id)))
+        .order((dirs::position, dirs::ordinal))
         .load::<DownloadDirectory>(&mut connection)
         .map_err(|e| e.into())
 }
@@ -101,7 +166,7 @@ pub async fn delete_directories(pool: &Pool, user: User) -> Result<(), DbError>
     Ok(())
 }
 
-#[allow(dead_code)]
+/// Other ordinals are left as they are, so the directory choices which were already sent stay valid
 pub async fn delete_directory(pool: &Pool, user: User, ordinal: i32) -> Result<(), DbError> {
     let mut connection = pool.get()?;
     diesel::delete(dirs::table.filter(dirs::user_id.eq(&(user.id as i64)).and(dirs::ordinal.eq(ordinal))))
@@ -154,6 +219,54 @@ pub(crate) async fn get_tasks_by_server_id(
         .load::<DownloadTask>(&mut connection)?)
 }
 
+/// Tasks which are not finished yet on any server
+pub(crate) async fn get_active_tasks(pool: &Pool) -> Result<Vec<DownloadTask>, DbError> {
+    let mut connection = pool.get()?;
+    Ok(tasks::table
+        .filter(tasks::status.eq_any(vec![
+            TaskStatus::Created.to_string(),
+            TaskStatus::Started.to_string(),
+        ]))
+        .load::<DownloadTask>(&mut connection)?)
+}
+
+pub(crate) async fn set_task_status(
+    pool: &Pool,
+    id: &Uuid,
+    status: TaskStatus,
+) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    diesel::update(tasks::table.filter(tasks::id.eq(id)))
+        .set(tasks::status.eq(status.to_string()))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
+/// A page of the user tasks with their magnets, the newest first
+pub(crate) async fn get_user_tasks_page(
+    pool: &Pool,
+    user: &User,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<(DownloadTask, Magnet)>, DbError> {
+    let mut connection = pool.get()?;
+    Ok(tasks::table
+        .inner_join(magnets::table)
+        .filter(tasks::user_id.eq(user.id))
+        .order(tasks::created_at.desc())
+        .offset(offset)
+        .limit(limit)
+        .load::<(DownloadTask, Magnet)>(&mut connection)?)
+}
+
+pub(crate) async fn user_tasks_count(pool: &Pool, user: &User) -> Result<i64, DbError> {
+    let mut connection = pool.get()?;
+    Ok(tasks::table
+        .filter(tasks::user_id.eq(user.id))
+        .count()
+        .first::<i64>(&mut connection)?)
+}
+
 pub(crate) async fn tasks_count_by_server_id(pool: &Pool, id: &Uuid) -> Result<i64, DbError> {
     let mut connection = pool.get()?;
     Ok(tasks::table
@@ -165,25 +278,38 @@ pub(crate) async fn tasks_count_by_server_id(pool: &Pool, id: &Uuid) -> Result<i
 
 // SERVERS
 
-pub(crate) fn test_db_crypto() {
+pub(crate) fn test_db_crypto() -> Result<(), CryptoError> {
     debug!("Testing db crypto");
-    let salt = random_salt();
-    init_salty_crypto(salt);
+    Keyring::from_env()?;
     debug!("Test passed!");
+    Ok(())
 }
 
-fn init_crypto(user: &User) -> Crypto {
-    init_salty_crypto(user.salt.clone())
+fn init_keyring() -> Result<Keyring, DbError> {
+    Ok(Keyring::from_env()?)
 }
 
-fn init_salty_crypto(salt: String) -> Crypto {
-    Crypto::new(std::env::var("SECRET").expect("SECRET is not set"), salt)
-        .expect("All keys should be valid since the system sets them up")
+async fn has_servers(pool: &Pool, user: &User) -> Result<bool, DbError> {
+    let mut connection = pool.get()?;
+    let count: i64 = servers::table
+        .filter(servers::user_id.eq(user.id))
+        .count()
+        .get_result(&mut connection)?;
+    Ok(count > 0)
 }
 
-pub async fn add_server(pool: &Pool, user: &User, url: &String) -> Result<Server, DbError> {
+pub async fn add_server(
+    pool: &Pool,
+    user: &User,
+    url: &str,
+    alias: &str,
+    endpoint: &ServerEndpoint,
+) -> Result<Server, DbError> {
+    let is_first = !has_servers(pool, user).await?;
     let mut connection = pool.get()?;
-    let new_server = NewServer::new(user.id as u64, url.clone(), None);
+    let new_server = NewServer::new(user.id as u64, url.to_owned(), alias.to_owned(), None)
+        .with_default(is_first)
+        .with_endpoint(endpoint);
 
     let new_id = diesel::insert_into(servers::table)
         .values(new_server)
@@ -199,17 +325,22 @@ pub async fn add_server_auth(
     pool: &Pool,
     user: &User,
     url: &String,
+    alias: &str,
+    endpoint: &ServerEndpoint,
     username: &String,
     password: &String,
 ) -> Result<Server, DbError> {
+    let is_first = !has_servers(pool, user).await?;
     let mut connection = pool.get()?;
 
-    let crypto = init_crypto(&user);
+    let keyring = init_keyring()?;
     let auth = Authentication {
         username: username.clone(),
-        password: crypto.encrypt(password),
+        password: keyring.encrypt(password)?,
     };
-    let new_server = NewServer::new(user.id as u64, url.clone(), Some(auth));
+    let new_server = NewServer::new(user.id as u64, url.clone(), alias.to_owned(), Some(auth))
+        .with_default(is_first)
+        .with_endpoint(endpoint);
 
     let new_id = diesel::insert_into(servers::table)
         .values(new_server)
@@ -220,15 +351,42 @@ pub async fn add_server_auth(
     Ok(server)
 }
 
-#[allow(dead_code)]
-pub async fn delete_server(pool: &Pool, user: &User, id: &Uuid) -> Result<(), DbError> {
+/// Changes the server in place, so the tasks stay linked to it
+pub async fn update_server(
+    pool: &Pool,
+    user: &User,
+    id: &Uuid,
+    url: &str,
+    alias: &str,
+    endpoint: &ServerEndpoint,
+    auth: Option<Authentication>,
+) -> Result<Option<Server>, DbError> {
     let mut connection = pool.get()?;
-    diesel::delete(servers::table.filter(servers::user_id.eq(user.id as i64).and(servers::id.eq(id))))
-        .execute(&mut connection)?;
-    Ok(())
+    let keyring = init_keyring()?;
+    let password = auth.as_ref().map(|auth| keyring.encrypt(&auth.password)).transpose()?;
+    let filter = servers::user_id.eq(user.id).and(servers::id.eq(id));
+    connection.transaction::<_, DbError, _>(|connection| {
+        diesel::update(servers::table.filter(filter))
+            .set((
+                servers::url.eq(url),
+                servers::alias.eq(alias),
+                servers::kind.eq(endpoint.kind.to_string()),
+                servers::endpoint.eq(endpoint.url.to_string()),
+                servers::tls.eq(endpoint.tls.to_string()),
+                servers::ca_cert.eq(endpoint.tls.ca_cert()),
+            ))
+            .execute(connection)?;
+        // Credentials left out of the edit keep the stored ones
+        if let (Some(auth), Some(password)) = (auth, password) {
+            diesel::update(servers::table.filter(filter))
+                .set((servers::username.eq(auth.username), servers::password.eq(password)))
+                .execute(connection)?;
+        }
+        Ok(())
+    })?;
+    get_server_by_id(pool, user, *id).await
 }
 
-#[allow(dead_code)]
 pub async fn delete_servers(pool: &Pool, user: &User) -> Result<(), DbError> {
     let mut connection = pool.get()?;
     diesel::delete(servers::table.filter(servers::user_id.eq(user.id as i64))).execute(&mut connection)?;
@@ -236,46 +394,160 @@ pub async fn delete_servers(pool: &Pool, user: &User) -> Result<(), DbError> {
 }
 
 impl Server {
-    pub(crate) fn decrypt(self: &Self, crypto: &Crypto) -> Server {
+    /// The salt of the owner is needed for the passwords encrypted before the keyring
+    pub(crate) fn decrypt(&self, keyring: &Keyring, salt: &str) -> Result<Server, CryptoError> {
         let clone = self.clone();
-        Server {
-            password: clone.password.map(|val| crypto.decrypt(&val)),
+        Ok(Server {
+            password: clone.password.map(|val| keyring.decrypt(&val, salt)).transpose()?,
             ..clone
-        }
+        })
     }
 }
 
-#[allow(dead_code)]
 pub(crate) async fn get_servers_by_user_id(
     pool: &Pool,
     user: &User,
 ) -> Result<Vec<Server>, DbError> {
     let mut connection = pool.get()?;
-    let crypto = init_crypto(&user);
+    let keyring = init_keyring()?;
 
     let encrypted_servers = servers::table
         .filter(servers::user_id.eq(user.id as i64))
+        .order(servers::short_id)
         .load::<Server>(&mut connection)?;
     let decrypted_servers = encrypted_servers
         .iter()
-        .map(|server| server.decrypt(&crypto))
-        .collect();
+        .map(|server| server.decrypt(&keyring, &user.salt))
+        .collect::<Result<_, _>>()?;
     Ok(decrypted_servers)
 }
 
+/// Own servers with a password which can't be decrypted anymore, returned without the password
+pub(crate) async fn get_unreadable_servers(pool: &Pool, user: &User) -> Result<Vec<Server>, DbError> {
+    let mut connection = pool.get()?;
+    let keyring = init_keyring()?;
+
+    Ok(servers::table
+        .filter(servers::user_id.eq(user.id))
+        .order(servers::short_id)
+        .load::<Server>(&mut connection)?
+        .into_iter()
+        .filter(|server| server.decrypt(&keyring, &user.salt).is_err())
+        .map(|server| Server { password: None, ..server })
+        .collect())
+}
+
 pub(crate) async fn get_server_by_id(
     pool: &Pool,
     user: &User,
     id: Uuid,
 ) -> Result<Option<Server>, DbError> {
     let mut connection = pool.get()?;
-    let crypto = init_crypto(&user);
+    let keyring = init_keyring()?;
 
     Ok(servers::table
         .filter(servers::user_id.eq(user.id as i64).and(servers::id.eq(id)))
         .first::<Server>(&mut connection)
         .optional()?
-        .map(|v| v.decrypt(&crypto)))
+        .map(|v| v.decrypt(&keyring, &user.salt))
+        .transpose()?)
+}
+
+pub(crate) async fn get_server_by_short_id(
+    pool: &Pool,
+    user: &User,
+    short_id: i32,
+) -> Result<Option<Server>, DbError> {
+    let mut connection = pool.get()?;
+    let keyring = init_keyring()?;
+
+    Ok(servers::table
+        .filter(servers::user_id.eq(user.id).and(servers::short_id.eq(short_id)))
+        .first::<Server>(&mut connection)
+        .optional()?
+        .map(|v| v.decrypt(&keyring, &user.salt))
+        .transpose()?)
+}
+
+/// Loads the server without the password, so it can be edited even when the password is unreadable
+pub(crate) async fn get_server_details_by_short_id(
+    pool: &Pool,
+    user: &User,
+    short_id: i32,
+) -> Result<Option<Server>, DbError> {
+    let mut connection = pool.get()?;
+
+    Ok(servers::table
+        .filter(servers::user_id.eq(user.id).and(servers::short_id.eq(short_id)))
+        .first::<Server>(&mut connection)
+        .optional()?
+        .map(|server| Server { password: None, ..server }))
+}
+
+/// The server marked as default or the first registered one
+pub(crate) async fn get_default_server(pool: &Pool, user: &User) -> Result<Option<Server>, DbError> {
+    let servers = get_servers_by_user_id(pool, user).await?;
+    Ok(servers
+        .iter()
+        .find(|server| server.is_default)
+        .or(servers.first())
+        .cloned())
+}
+
+pub(crate) async fn set_default_server(pool: &Pool, user: &User, id: &Uuid) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    diesel::update(servers::table.filter(servers::user_id.eq(user.id)))
+        .set(servers::is_default.eq(servers::id.eq(id)))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
+/// Re-encrypts the passwords which use a salt as the nonce or a previous secret.
+/// Unreadable passwords are skipped. Returns how many passwords were updated
+pub(crate) async fn reencrypt_passwords(pool: &Pool) -> Result<usize, DbError> {
+    let mut connection = pool.get()?;
+    let keyring = init_keyring()?;
+    let passwords = servers::table
+        .inner_join(users::table)
+        .filter(servers::password.is_not_null())
+        .select((servers::id, servers::password, users::salt))
+        .load::<(Uuid, Option<String>, String)>(&mut connection)?;
+    let mut updated = 0;
+    for (id, password, salt) in passwords {
+        let password = match password {
+            Some(password) if !keyring.is_current(&password) => password,
+            _ => continue,
+        };
+        let reencrypted = match keyring.decrypt(&password, &salt).and_then(|plain| keyring.encrypt(&plain)) {
+            Ok(reencrypted) => reencrypted,
+            Err(err) => {
+                warn!("Unable to re-encrypt the password of server {}: {}", id, err);
+                continue;
+            }
+        };
+        updated += diesel::update(servers::table.filter(servers::id.eq(id).and(servers::password.eq(password))))
+            .set(servers::password.eq(reencrypted))
+            .execute(&mut connection)?;
+    }
+    Ok(updated)
+}
+
+/// Loads a server regardless of the requesting user,
+/// the password is decrypted with the salt of the server owner
+pub(crate) async fn find_server_by_id(pool: &Pool, id: &Uuid) -> Result<Option<Server>, DbError> {
+    let mut connection = pool.get()?;
+    let server = servers::table
+        .filter(servers::id.eq(id))
+        .first::<Server>(&mut connection)
+        .optional()?;
+    match server {
+        Some(server) => {
+            let owner = get_user(pool, &server.user_id).await?
+                .ok_or(DbError::from("Server owner not found!".to_owned()))?;
+            Ok(Some(server.decrypt(&init_keyring()?, &owner.salt)?))
+        }
+        None => Ok(None),
+    }
 }
 
 // MAGNETS
@@ -286,7 +558,26 @@ pub(crate) async fn register_magnet(
     url: &String,
 ) -> Result<Uuid, DbError> {
     let mut connection = pool.get()?;
-    let new_magnet = NewMagnet::new(user.id as i64, url.clone());
+    let new_magnet = NewMagnet::new(user.id as i64, url.clone(), None);
+
+    let new_id = diesel::insert_into(magnets::table)
+        .values(new_magnet)
+        .returning(magnets::id)
+        .get_result(&mut connection)?;
+
+    Ok(new_id)
+}
+
+/// Registers a magnet derived from a .torrent file together with the
+/// base64 encoded file content, which is sent to Transmission instead of the link
+pub(crate) async fn register_torrent_file(
+    pool: &Pool,
+    user: &User,
+    url: &str,
+    metainfo: &str,
+) -> Result<Uuid, DbError> {
+    let mut connection = pool.get()?;
+    let new_magnet = NewMagnet::new(user.id, url.to_owned(), Some(metainfo.to_owned()));
 
     let new_id = diesel::insert_into(magnets::table)
         .values(new_magnet)
@@ -296,6 +587,19 @@ pub(crate) async fn register_magnet(
     Ok(new_id)
 }
 
+pub(crate) async fn set_magnet_server(
+    pool: &Pool,
+    user: &User,
+    id: &Uuid,
+    server_id: &Uuid,
+) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    diesel::update(magnets::table.filter(magnets::user_id.eq(user.id).and(magnets::id.eq(id))))
+        .set(magnets::server_id.eq(server_id))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
 pub(crate) async fn get_magnet_by_id(
     pool: &Pool,
     user: &User,
@@ -368,10 +672,137 @@ pub(crate) async fn delete_friend(
     Ok(())
 }
 
+// SHARES
+
+pub(crate) async fn share_server(pool: &Pool, server_id: &Uuid, friend_id: &i64) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    let share = NewServerShare { server_id: *server_id, friend_user_id: *friend_id };
+    diesel::insert_into(server_shares::table)
+        .values(share)
+        .on_conflict_do_nothing()
+        .execute(&mut connection)?;
+    Ok(())
+}
+
+pub(crate) async fn unshare_server(pool: &Pool, server_id: &Uuid, friend_id: &i64) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    diesel::delete(server_shares::table.filter(
+        server_shares::server_id.eq(server_id)
+            .and(server_shares::friend_user_id.eq(friend_id))
+    ))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
+/// Shares of all the servers of the owner
+pub(crate) async fn get_shares_by_owner(pool: &Pool, owner: &User) -> Result<Vec<ServerShare>, DbError> {
+    let mut connection = pool.get()?;
+    Ok(server_shares::table
+        .inner_join(servers::table)
+        .filter(servers::user_id.eq(owner.id))
+        .select(server_shares::all_columns)
+        .order(server_shares::created_at)
+        .load::<ServerShare>(&mut connection)?)
+}
+
+/// Removes all the shares of the owner servers and returns what was removed
+pub(crate) async fn delete_shares_by_owner(pool: &Pool, owner: &User) -> Result<Vec<ServerShare>, DbError> {
+    let shares = get_shares_by_owner(pool, owner).await?;
+    let mut connection = pool.get()?;
+    let ids: Vec<Uuid> = shares.iter().map(|share| share.id).collect();
+    diesel::delete(server_shares::table.filter(server_shares::id.eq_any(ids)))
+        .execute(&mut connection)?;
+    Ok(shares)
+}
+
+/// Removes the shares between two users in both directions
+pub(crate) async fn delete_shares_between(pool: &Pool, user_id: &i64, friend_id: &i64) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    let shared_to_friend = servers::table
+        .filter(servers::user_id.eq(user_id))
+        .select(servers::id);
+    diesel::delete(server_shares::table.filter(
+        server_shares::friend_user_id.eq(friend_id)
+            .and(server_shares::server_id.eq_any(shared_to_friend))
+    ))
+        .execute(&mut connection)?;
+    let shared_to_user = servers::table
+        .filter(servers::user_id.eq(friend_id))
+        .select(servers::id);
+    diesel::delete(server_shares::table.filter(
+        server_shares::friend_user_id.eq(user_id)
+            .and(server_shares::server_id.eq_any(shared_to_user))
+    ))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
+/// Servers of other users shared with the user
+pub(crate) async fn get_shared_servers(pool: &Pool, user: &User) -> Result<Vec<Server>, DbError> {
+    let server_ids = {
+        let mut connection = pool.get()?;
+        server_shares::table
+            .filter(server_shares::friend_user_id.eq(user.id))
+            .select(server_shares::server_id)
+            .order(server_shares::created_at)
+            .load::<Uuid>(&mut connection)?
+    };
+    let mut servers = vec![];
+    for id in server_ids {
+        if let Some(server) = find_server_by_id(pool, &id).await? {
+            servers.push(server);
+        }
+    }
+    Ok(servers)
+}
+
+/// Own servers of the user followed by the servers shared with the user
+pub(crate) async fn get_available_servers(pool: &Pool, user: &User) -> Result<Vec<Server>, DbError> {
+    let mut servers = get_servers_by_user_id(pool, user).await?;
+    servers.append(&mut get_shared_servers(pool, user).await?);
+    Ok(servers)
+}
+
+// DIALOGUES
+
+pub(crate) async fn get_dialogue_state(pool: &Pool, chat_id: i64) -> Result<Option<String>, DbError> {
+    let mut connection = pool.get()?;
+    Ok(dialogues::table
+        .filter(dialogues::chat_id.eq(chat_id))
+        .select(dialogues::state)
+        .first::<String>(&mut connection)
+        .optional()?)
+}
+
+pub(crate) async fn save_dialogue_state(pool: &Pool, chat_id: i64, state: String) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    let dialogue = NewDialogue { chat_id, state };
+    diesel::insert_into(dialogues::table)
+        .values(&dialogue)
+        .on_conflict(dialogues::chat_id)
+        .do_update()
+        .set((
+            dialogues::state.eq(&dialogue.state),
+            dialogues::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
+pub(crate) async fn delete_dialogue_state(pool: &Pool, chat_id: i64) -> Result<(), DbError> {
+    let mut connection = pool.get()?;
+    diesel::delete(dialogues::table.filter(dialogues::chat_id.eq(chat_id)))
+        .execute(&mut connection)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
+    use crate::core::crypto::{random_salt, Crypto};
+    use crate::db::models::server::{ServerKind, TlsOptions};
+    use crate::db::repo::Repository;
     use crate::DbConfig;
     use rand::Rng;
 
@@ -380,6 +811,10 @@ mod test {
         DbConfig::get_pool()
     }
 
+    fn endpoint(kind: ServerKind) -> ServerEndpoint {
+        ServerEndpoint { kind, url: "http://localhost:9091/transmission/rpc".parse().unwrap(), tls: TlsOptions::Verify }
+    }
+
     fn new_user() -> NewUser {
         let mut rng = rand::thread_rng();
         NewUser {
@@ -413,11 +848,193 @@ mod test {
     pub async fn test_server_get() -> Result<(), DbError> {
         let pool = pool();
         let user = new_user().save(&pool).await?;
-        let server = add_server(&pool, &user, &"Some url".to_owned()).await?;
+        let server = add_server(&pool, &user, "Some url", "Some alias", &endpoint(ServerKind::Transmission)).await?;
 
         assert_eq!(&server.user_id, &(user.id));
         assert_eq!(&server.url, &"Some url".to_owned());
+        assert_eq!(&server.alias, &"Some alias".to_owned());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_server_update() -> Result<(), DbError> {
+        let pool = pool();
+        let user = new_user().save(&pool).await?;
+        let server = add_server(&pool, &user, "Old url", "Old", &endpoint(ServerKind::Transmission)).await?;
+        let auth = Authentication { username: "user".to_owned(), password: "secret".to_owned() };
+
+        let endpoint = ServerEndpoint { tls: TlsOptions::AcceptInvalid, ..endpoint(ServerKind::Deluge) };
+        let updated = update_server(&pool, &user, &server.id, "New url", "New", &endpoint, Some(auth)).await?.unwrap();
+
+        assert_eq!(updated.id, server.id);
+        assert_eq!(updated.url, "New url");
+        assert_eq!(updated.alias, "New");
+        assert_eq!(updated.endpoint(), endpoint);
+        assert_eq!(updated.username, Some("user".to_owned()));
+        assert_eq!(updated.password, Some("secret".to_owned()));
+
+        let renamed = update_server(&pool, &user, &server.id, "New url", "Renamed", &endpoint, None).await?.unwrap();
+        assert_eq!(renamed.alias, "Renamed");
+        assert_eq!(renamed.username, Some("user".to_owned()));
+        assert_eq!(renamed.password, Some("secret".to_owned()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_password_reencryption() -> Result<(), DbError> {
+        let pool = pool();
+        let user = new_user().save(&pool).await?;
+        let server = add_server(&pool, &user, "Legacy url", "Legacy", &endpoint(ServerKind::Transmission)).await?;
+        let legacy = Crypto::new(std::env::var("SECRET").unwrap(), user.salt.clone())
+            .unwrap()
+            .encrypt("secret")
+            .unwrap();
+        diesel::update(servers::table.filter(servers::id.eq(server.id)))
+            .set((servers::username.eq("user"), servers::password.eq(legacy)))
+            .execute(&mut pool.get()?)?;
+
+        assert!(reencrypt_passwords(&pool).await? >= 1);
+
+        let stored: Option<String> = servers::table
+            .filter(servers::id.eq(server.id))
+            .select(servers::password)
+            .first(&mut pool.get()?)?;
+        assert!(init_keyring()?.is_current(&stored.unwrap()));
+        let server = get_server_by_id(&pool, &user, server.id).await?.unwrap();
+        assert_eq!(server.password, Some("secret".to_owned()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_unreadable_password() -> Result<(), DbError> {
+        let pool = pool();
+        let user = new_user().save(&pool).await?;
+        let server = add_server(&pool, &user, "Broken url", "Broken", &endpoint(ServerKind::Transmission)).await?;
+        diesel::update(servers::table.filter(servers::id.eq(server.id)))
+            .set((servers::username.eq("user"), servers::password.eq("v2:00000000:AAAA")))
+            .execute(&mut pool.get()?)?;
+
+        let error = get_servers_by_user_id(&pool, &user).await.unwrap_err();
+        assert!(error.is_unreadable_credentials());
+        assert!(get_server_by_short_id(&pool, &user, server.short_id).await.is_err());
+        let unreadable = get_unreadable_servers(&pool, &user).await?;
+        assert_eq!(unreadable.len(), 1);
+        assert_eq!(unreadable[0].password, None);
+        let details = get_server_details_by_short_id(&pool, &user, server.short_id).await?.unwrap();
+        assert_eq!(details.alias, "Broken");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_default_server() -> Result<(), DbError> {
+        let pool = pool();
+        let user = new_user().save(&pool).await?;
+        let first = add_server(&pool, &user, "First url", "First", &endpoint(ServerKind::Transmission)).await?;
+        let second = add_server(&pool, &user, "Second url", "Second", &endpoint(ServerKind::Transmission)).await?;
+        assert!(first.is_default);
+        assert!(!second.is_default);
+
+        set_default_server(&pool, &user, &second.id).await?;
+
+        let default = get_default_server(&pool, &user).await?.unwrap();
+        assert_eq!(default.id, second.id);
+        let by_short_id = get_server_by_short_id(&pool, &user, first.short_id).await?.unwrap();
+        assert!(!by_short_id.is_default);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_directory_management() -> Result<(), DbError> {
+        let pool = pool();
+        let user = new_user().save(&pool).await?;
+        let first = add_directory(&pool, &user, &"Movies".to_owned(), &"/movies".to_owned(), None).await?;
+        let second = add_directory(&pool, &user, &"Music".to_owned(), &"/music".to_owned(), None).await?;
+
+        swap_directories(&pool, &user, first.ordinal, second.ordinal).await?;
+        rename_directory(&pool, &user, second.ordinal, "Songs").await?;
+        set_directory_path(&pool, &user, second.ordinal, "/songs").await?;
+        set_default_directory(&pool, &user, first.ordinal).await?;
+
+        let dirs = get_directories(&pool, &user).await?;
+        assert_eq!(dirs[0].id, second.id);
+        assert_eq!(dirs[0].ordinal, second.ordinal);
+        assert_eq!(dirs[0].alias, "Songs");
+        assert_eq!(dirs[0].path, "/songs");
+        assert!(!dirs[0].is_default);
+        assert_eq!(dirs[1].ordinal, first.ordinal);
+        assert!(dirs[1].is_default);
+
+        delete_directory(&pool, user.clone(), first.ordinal).await?;
+        let dirs = get_directories(&pool, &user).await?;
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].ordinal, second.ordinal);
+
+        delete_directory(&pool, user.clone(), second.ordinal).await?;
+        let third = add_directory(&pool, &user, &"Books".to_owned(), &"/books".to_owned(), None).await?;
+        assert!(third.ordinal > second.ordinal);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_task_status_update() -> Result<(), DbError> {
+        let pool = pool();
+        let user = new_user().save(&pool).await?;
+        let server = add_server(&pool, &user, "Some url", "Some alias", &endpoint(ServerKind::Transmission)).await?;
+        let magnet_id = register_magnet(&pool, &user, &"magnet:?xt=urn:btih:hash".to_owned()).await?;
+        let magnet = get_magnet_by_id(&pool, &user, magnet_id).await?.unwrap();
+        let task = add_task(&pool, &user, &server.id, &magnet).await?;
+        assert!(get_active_tasks(&pool).await?.iter().any(|t| t.id == task.id));
+
+        set_task_status(&pool, &task.id, TaskStatus::Finished).await?;
+
+        let updated = get_task_by_id(&pool, &task.id).await?.unwrap();
+        assert!(matches!(updated.status(), TaskStatus::Finished));
+        assert!(!get_active_tasks(&pool).await?.iter().any(|t| t.id == task.id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_user_tasks_page() -> Result<(), DbError> {
+        let pool = pool();
+        let user = new_user().save(&pool).await?;
+        let server = add_server(&pool, &user, "Some url", "Some alias", &endpoint(ServerKind::Transmission)).await?;
+        for _ in 0..3 {
+            let magnet_id = register_magnet(&pool, &user, &"magnet:?xt=urn:btih:hash".to_owned()).await?;
+            let magnet = get_magnet_by_id(&pool, &user, magnet_id).await?.unwrap();
+            add_task(&pool, &user, &server.id, &magnet).await?;
+        }
+        assert_eq!(user_tasks_count(&pool, &user).await?, 3);
+        let page = get_user_tasks_page(&pool, &user, 2, 2).await?;
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].0.magnet_id, page[0].1.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_server_sharing() -> Result<(), DbError> {
+        let pool = pool();
+        let owner = new_user().save(&pool).await?;
+        let friend = new_user().save(&pool).await?;
+        let server = add_server(&pool, &owner, "Shared url", "Shared", &endpoint(ServerKind::Transmission)).await?;
+        assert!(get_shared_servers(&pool, &friend).await?.is_empty());
+
+        share_server(&pool, &server.id, &friend.id).await?;
+        share_server(&pool, &server.id, &friend.id).await?;
+
+        let available = get_available_servers(&pool, &friend).await?;
+        assert_eq!(available.len(), 1);
+        assert_eq!(available[0].id, server.id);
+        assert!(pool.get_available_server_by_short_id(&friend, server.short_id).await?.is_some());
+        assert_eq!(get_shares_by_owner(&pool, &owner).await?.len(), 1);
+
+        delete_shares_between(&pool, &friend.id, &owner.id).await?;
+        assert!(get_shared_servers(&pool, &friend).await?.is_empty());
 
+        share_server(&pool, &server.id, &friend.id).await?;
+        let deleted = delete_shares_by_owner(&pool, &owner).await?;
+        assert_eq!(deleted.len(), 1);
+        assert!(pool.get_available_server_by_id(&friend, &server.id).await?.is_none());
         Ok(())
     }
 }
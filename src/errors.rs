@@ -1,6 +1,7 @@
 use std::{error, fmt};
 
 use crate::{fromError, fromErrorString};
+use crate::core::crypto::CryptoError;
 
 /// ***************
 /// Bot Errors
@@ -17,6 +18,10 @@ impl BotError {
     pub(crate) fn logic(message: String) -> BotError {
         BotError(BotErrorKind::BotLogic(message))
     }
+
+    pub(crate) fn is_unreadable_credentials(&self) -> bool {
+        matches!(&self.0, BotErrorKind::DbError(error) if error.is_unreadable_credentials())
+    }
 }
 
 #[derive(Debug)]
@@ -30,11 +35,14 @@ pub(crate) enum BotErrorKind {
 fromError!(teloxide::ApiError, BotError, BotErrorKind::TelegramError);
 fromError!(teloxide::RequestError, BotError, BotErrorKind::TelegramRequestError);
 fromError!(DbError, BotError, BotErrorKind::DbError);
+fromErrorString!(MagnetMappingError, BotError, BotErrorKind::BotLogic);
 
 fromError!(r2d2::Error, BotError, BotErrorKind::DbError);
 fromErrorString!(r2d2::Error, DbError, DbErrorKind::Connection);
 fromErrorString!(diesel::result::Error, DbError, DbErrorKind::Execution);
 fromErrorString!(String, DbError, DbErrorKind::Execution);
+fromErrorString!(serde_json::Error, DbError, DbErrorKind::Serialization);
+fromErrorString!(CryptoError, DbError, DbErrorKind::Crypto);
 
 impl fmt::Display for BotError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -76,6 +84,26 @@ impl error::Error for MagnetMappingError {
     }
 }
 
+/// ***************
+/// Torrent File Parsing Error
+/// ***************
+#[derive(Debug, Clone)]
+pub struct TorrentParseError(&'static str);
+
+impl TorrentParseError {
+    pub(crate) fn new(str: &'static str) -> Self {
+        TorrentParseError(str)
+    }
+}
+
+impl fmt::Display for TorrentParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Torrent parsing error: {}", self.0)
+    }
+}
+
+impl error::Error for TorrentParseError {}
+
 /// ***************
 /// DB ERRORS
 /// ***************
@@ -83,6 +111,8 @@ impl error::Error for MagnetMappingError {
 pub(crate) enum DbErrorKind {
     Connection(String),
     Execution(String),
+    Serialization(String),
+    Crypto(String),
 }
 
 #[derive(Debug)]
@@ -92,6 +122,11 @@ impl DbError {
     pub(crate) fn new(kind: DbErrorKind) -> DbError {
         DbError(kind)
     }
+
+    /// Stored passwords can't be decrypted, e.g. after the secret was changed
+    pub(crate) fn is_unreadable_credentials(&self) -> bool {
+        matches!(self.0, DbErrorKind::Crypto(_))
+    }
 }
 
 impl fmt::Display for DbError {
@@ -99,6 +134,8 @@ impl fmt::Display for DbError {
         match &self.0 {
             DbErrorKind::Connection(error) => write!(f, "{}", error),
             DbErrorKind::Execution(error) => write!(f, "{}", error),
+            DbErrorKind::Serialization(error) => write!(f, "{}", error),
+            DbErrorKind::Crypto(error) => write!(f, "{}", error),
         }
     }
 }
@@ -154,4 +191,12 @@ mod test {
     fn string_DbError(e: String) -> DbError {
         e.into()
     }
+
+    fn serde_json_Error_DbError(e: serde_json::Error) -> DbError {
+        e.into()
+    }
+
+    fn crypto_Error_DbError(e: CryptoError) -> DbError {
+        e.into()
+    }
 }
@@ -2,16 +2,22 @@
 extern crate diesel;
 
 use std::env;
+use std::sync::Arc;
 use diesel::PgConnection;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
 use dotenvy::dotenv;
+use log::{info, warn};
 use teloxide::{Bot, dptree};
-use teloxide::dispatching::dialogue::InMemStorage;
+use teloxide::dispatching::dialogue::{ErasedStorage, InMemStorage, Storage};
+use teloxide::error_handlers::LoggingErrorHandler;
 use teloxide::prelude::Dispatcher;
+use teloxide::update_listeners::webhooks;
 
 use db::db_config::DbConfig;
-use db::repository::test_db_crypto;
+use db::dialogue_storage::PgStorage;
+use db::repo::Repository;
+use db::repository::{reencrypt_passwords, test_db_crypto, Pool};
 
 use crate::router::{schema, State};
 
@@ -23,6 +29,10 @@ mod router;
 
 mod conversation;
 mod schema;
+mod watcher;
+mod webhook;
+mod health;
+mod metrics;
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
@@ -35,19 +45,49 @@ async fn main() {
     DbConfig::test_connection(pool.clone()).unwrap();
     run_migration(&mut DbConfig::get_single_connection());
 
-    test_db_crypto();
+    test_db_crypto().expect("SECRET and PREVIOUS_SECRETS should be valid keys");
+    match reencrypt_passwords(&pool).await {
+        Ok(0) => {}
+        Ok(count) => info!("Re-encrypted {} server passwords", count),
+        Err(err) => warn!("Unable to re-encrypt server passwords: {}", err),
+    }
 
     let token = env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN not set");
     let bot = Bot::new(token);
+    let webhook = webhook::webhook_options().expect("Invalid webhook configuration");
 
-    Dispatcher::builder(bot, schema())
-        .dependencies(dptree::deps![InMemStorage::<State>::new(), pool])
+    tokio::spawn(watcher::watch_tasks(bot.clone(), pool.clone()));
+    tokio::spawn(health::serve_health(bot.clone(), pool.clone()));
+
+    let storage = dialogue_storage(pool.clone());
+    let repository: Arc<dyn Repository> = Arc::new(pool.clone());
+
+    let mut dispatcher = Dispatcher::builder(bot.clone(), schema())
+        .dependencies(dptree::deps![storage, repository])
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+    match webhook {
+        Some(options) => {
+            info!("Receiving updates via webhook on {}", options.address);
+            let listener = webhooks::axum(bot, options)
+                .await
+                .expect("Unable to set up the webhook");
+            dispatcher
+                .dispatch_with_listener(listener, LoggingErrorHandler::with_custom_text("Webhook listener error"))
+                .await;
+        }
+        None => dispatcher.dispatch().await,
+    }
 }
 
 fn run_migration(conn: &mut PgConnection) {
     conn.run_pending_migrations(MIGRATIONS).unwrap();
-}
\ No newline at end of file
+}
+
+/// DIALOGUE_STORAGE=postgres keeps dialogues between restarts, the default is memory
+fn dialogue_storage(pool: Pool) -> Arc<ErasedStorage<State>> {
+    match env::var("DIALOGUE_STORAGE").unwrap_or_default().as_str() {
+        "postgres" => PgStorage::new(pool).erase(),
+        _ => InMemStorage::<State>::new().erase(),
+    }
+}
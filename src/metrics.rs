@@ -0,0 +1,83 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use uuid::Uuid;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static MESSAGES_HANDLED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new("bot_messages_handled_total", "Messages received from users").unwrap())
+});
+
+pub static MAGNETS_PROCESSED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new("bot_magnets_processed_total", "Magnets and torrent files offered for download").unwrap())
+});
+
+pub static DOWNLOADS_STARTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new("bot_downloads_started_total", "Torrents added to the servers").unwrap())
+});
+
+static RPC_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bot_transmission_rpc_errors_total", "Failed Transmission RPC calls"),
+        &["server"],
+    ).unwrap())
+});
+
+static HANDLER_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("bot_handler_duration_seconds", "Time spent handling an update"),
+        &["handler"],
+    ).unwrap())
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(collector: T) -> T {
+    REGISTRY.register(Box::new(collector.clone())).unwrap();
+    collector
+}
+
+/// Counts a failed call to the server, the label is the server id since aliases are personal
+pub fn rpc_error(server_id: &Uuid) {
+    RPC_ERRORS.with_label_values(&[&server_id.to_string()]).inc();
+}
+
+/// Observes the handler duration when the returned timer is dropped
+pub fn handler_timer(handler: &str) -> prometheus::HistogramTimer {
+    HANDLER_LATENCY.with_label_values(&[handler]).start_timer()
+}
+
+/// All the metrics in the Prometheus text format
+pub fn render() -> String {
+    // the metrics appear only after the first use otherwise
+    LazyLock::force(&MESSAGES_HANDLED);
+    LazyLock::force(&MAGNETS_PROCESSED);
+    LazyLock::force(&DOWNLOADS_STARTED);
+    LazyLock::force(&RPC_ERRORS);
+    LazyLock::force(&HANDLER_LATENCY);
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Metrics should be encodable");
+    String::from_utf8(buffer).expect("Metrics should be valid utf8")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let server = Uuid::new_v4();
+        rpc_error(&server);
+        DOWNLOADS_STARTED.inc();
+        drop(handler_timer("test"));
+
+        let text = render();
+        assert!(text.contains(&format!("bot_transmission_rpc_errors_total{{server=\"{}\"}} 1", server)));
+        assert!(text.contains("bot_downloads_started_total"));
+        assert!(text.contains("bot_messages_handled_total"));
+        assert!(text.contains("bot_handler_duration_seconds_count{handler=\"test\"} 1"));
+    }
+}
@@ -1,6 +1,9 @@
+use std::sync::Arc;
+
 use teloxide::{Bot, dptree, RequestError};
 use teloxide::dispatching::{dialogue, UpdateFilterExt, UpdateHandler};
-use teloxide::dispatching::dialogue::InMemStorage;
+use serde::{Deserialize, Serialize};
+use teloxide::dispatching::dialogue::ErasedStorage;
 use teloxide::prelude::*;
 use teloxide::types::Update;
 
@@ -10,19 +13,27 @@ use crate::conversation::directories::*;
 use crate::conversation::friends::{confirm_unfriend_callback, manage_friend_callback, unfriend_callback};
 use crate::conversation::messages::*;
 use crate::conversation::servers::*;
+use crate::conversation::shared_server::*;
+use crate::conversation::task_files::*;
 use crate::conversation::tasks::*;
-use crate::db::repository::Pool;
+use crate::db::repo::Repository;
+use crate::metrics;
+
+const TASK_ACTIONS: [&str; 5] = ["t_pause:", "t_resume:", "t_verify:", "t_reannounce:", "t_top:"];
 
-pub type BotDialogue = Dialogue<State, InMemStorage<State>>;
+pub type BotDialogue = Dialogue<State, ErasedStorage<State>>;
 pub type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub enum State {
     #[default]
     Start,
     AddDirectory,
     RegisterServer,
     AddFriend,
+    RenameDirectory { ordinal: i32 },
+    ChangeDirectoryPath { ordinal: i32 },
+    EditServer { short_id: i32 },
 }
 
 pub(crate) fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
@@ -35,21 +46,27 @@ pub(crate) fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync
         .branch(case![Command::Cancel].endpoint(cancel_command))
         .branch(case![Command::AddFriend].endpoint(add_friend_command))
         .branch(case![Command::ListFriends].endpoint(list_friends_command))
+        .branch(case![Command::ListServers].endpoint(list_servers_command))
+        .branch(case![Command::Tasks].endpoint(list_tasks_command))
         .branch(case![Command::ServerSharing].endpoint(share_server_command))
         ;
 
     let message_handler = Update::filter_message()
+        .inspect(|| metrics::MESSAGES_HANDLED.inc())
         .branch(command_handler)
         .branch(case![State::Start].endpoint(process_message))
         .branch(case![State::AddDirectory].endpoint(add_directory_dialogue))
         .branch(case![State::RegisterServer].endpoint(register_server_dialogue))
         .branch(case![State::AddFriend].endpoint(add_friend_dialogue))
+        .branch(case![State::RenameDirectory { ordinal }].endpoint(rename_directory_dialogue))
+        .branch(case![State::ChangeDirectoryPath { ordinal }].endpoint(change_directory_path_dialogue))
+        .branch(case![State::EditServer { short_id }].endpoint(edit_server_dialogue))
         .branch(dptree::endpoint(invalid_state));
 
     let callback_query_handler = Update::filter_callback_query()
         .branch(dptree::endpoint(process_callback));
 
-    dialogue::enter::<Update, InMemStorage<State>, State, _>()
+    dialogue::enter::<Update, ErasedStorage<State>, State, _>()
         .branch(message_handler)
         .branch(callback_query_handler)
 }
@@ -58,9 +75,10 @@ pub(crate) fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync
 async fn process_callback(
     bot: Bot,
     dialogue: BotDialogue,
-    pool: Pool,
+    repo: Arc<dyn Repository>,
     callback_query: CallbackQuery,
 ) -> HandlerResult {
+    let _timer = metrics::handler_timer("callback");
     let user_id: &u64 = &callback_query.from.id.0;
     let chat_id = &dialogue.chat_id();
     let message = match callback_query.message {
@@ -72,62 +90,177 @@ async fn process_callback(
         None => return Ok(())
     };
 
+    let routed = route_callback(
+        bot.clone(), dialogue, repo.clone(), user_id, data.clone(), message.clone(),
+    ).await;
+    report_unreadable_credentials(&bot, &*repo, user_id, chat_id, routed).await?;
+    match data {
+        s if s.starts_with("t_status:") => {}
+        s if s.starts_with("t_remove:") => {}
+        s if s.starts_with("t_rm:") => {}
+        s if s.starts_with("t_files:") => {}
+        s if s.starts_with("srv_turtle:") => {}
+        s if s.starts_with("srv_limit:") => {}
+        s if s.starts_with("t_fw:") => {}
+        s if s.starts_with("t_fp:") => {}
+        s if s.starts_with("t_show:") => {}
+        s if TASK_ACTIONS.iter().any(|prefix| s.starts_with(prefix)) => {}
+        s if s.starts_with("t_page:") => {}
+        s if s.starts_with("t_lremove:") => {}
+        s if s.starts_with(BACK_TO_SETTINGS) => {}
+        _ => delete_or_hide(&bot, &message).await?
+    }
+    bot.answer_callback_query(callback_query.id).await?;
+    Ok(())
+}
+
+async fn route_callback(
+    bot: Bot,
+    dialogue: BotDialogue,
+    repo: Arc<dyn Repository>,
+    user_id: &u64,
+    data: String,
+    message: Message,
+) -> HandlerResult {
+    let chat_id = &dialogue.chat_id();
     match data.as_str() {
         // download:magnet_uuid:directory_ordinal (1-64 bytes)
         value if value.starts_with("download:") => {
-            start_download(&bot, &pool, chat_id, user_id, value).await?
+            start_download(&bot, &*repo, chat_id, user_id, value).await?
+        }
+        // m_server:magnet_uuid:server_short_id
+        value if value.starts_with("m_server:") => {
+            choose_server(&bot, &*repo, chat_id, user_id, value).await?
         }
         // t_status:task_uuid
         value if value.starts_with("t_status:") => {
-            update_task_status(&bot, &pool, user_id, value, &message).await?
+            update_task_status(&bot, &*repo, user_id, value, &message).await?
+        }
+        // t_pause|t_resume|t_verify|t_reannounce|t_top:task_uuid
+        value if TASK_ACTIONS.iter().any(|prefix| value.starts_with(prefix)) => {
+            run_task_action(&bot, &*repo, user_id, value, &message).await?
+        }
+        // t_files:task_uuid:page
+        value if value.starts_with("t_files:") => {
+            show_task_files(&bot, &*repo, user_id, value, &message).await?
+        }
+        // t_fw:task_uuid:file_index:page
+        value if value.starts_with("t_fw:") => {
+            toggle_file_wanted(&bot, &*repo, user_id, value, &message).await?
+        }
+        // t_fp:task_uuid:file_index:page
+        value if value.starts_with("t_fp:") => {
+            cycle_file_priority(&bot, &*repo, user_id, value, &message).await?
+        }
+        // t_show:task_uuid
+        value if value.starts_with("t_show:") => {
+            show_task(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // t_page:page
+        value if value.starts_with("t_page:") => {
+            change_tasks_page(&bot, &*repo, user_id, value, &message).await?
+        }
+        // t_lremove:task_uuid:page
+        value if value.starts_with("t_lremove:") => {
+            remove_listed_task(&bot, &*repo, user_id, value, &message).await?
+        }
+        // t_rm:k|d:task_uuid[:page]
+        value if value.starts_with("t_rm:") => {
+            confirm_task_removal(&bot, &*repo, user_id, value, &message).await?
         }
         // t_remove:task_uuid
         value if value.starts_with("t_remove:") => {
-            remove_task(&bot, &pool, user_id, value, &message).await?
+            remove_task(&bot, &*repo, user_id, value, &message).await?
+        }
+        // srv_default:server_short_id
+        value if value.starts_with("srv_default:") => {
+            make_server_default(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // srv_edit:server_short_id
+        value if value.starts_with("srv_edit:") => {
+            edit_server_prepare(&bot, &*repo, user_id, chat_id, value, &dialogue).await?
+        }
+        // srv_dash:server_short_id
+        value if value.starts_with("srv_dash:") => {
+            show_dashboard(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // srv_turtle:server_short_id:1|0 and srv_limit:server_short_id:d|u:kbps
+        value if value.starts_with("srv_turtle:") || value.starts_with("srv_limit:") => {
+            change_session(&bot, &*repo, user_id, value, &message).await?
+        }
+        // share_srv:server_short_id
+        value if value.starts_with("share_srv:") => {
+            choose_friend_to_share(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // share_to:server_short_id:user_id
+        value if value.starts_with("share_to:") => {
+            share_server_with_friend(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // unshare:server_short_id:user_id
+        value if value.starts_with("unshare:") => {
+            revoke_share(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // dir:ordinal
+        value if value.starts_with("dir:") => {
+            directory_menu(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // dir_alias:ordinal and dir_path:ordinal
+        value if value.starts_with("dir_alias:") || value.starts_with("dir_path:") => {
+            edit_directory_prepare(&bot, &*repo, user_id, chat_id, value, &dialogue).await?
+        }
+        // dir_up:ordinal and dir_down:ordinal
+        value if value.starts_with("dir_up:") || value.starts_with("dir_down:") => {
+            move_directory(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // dir_default:ordinal
+        value if value.starts_with("dir_default:") => {
+            make_directory_default(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // dir_del:ordinal
+        value if value.starts_with("dir_del:") => {
+            delete_directory_callback(&bot, &*repo, user_id, chat_id, value).await?
+        }
+        // confirm_dir_del:ordinal
+        value if value.starts_with("confirm_dir_del:") => {
+            confirm_delete_directory_callback(&bot, &*repo, user_id, chat_id, value).await?
         }
         // manage_friend:user_id
         value if value.starts_with("manage_friend:") => {
-            manage_friend_callback(&bot, &pool, value, &message).await?
+            manage_friend_callback(&bot, &*repo, value, &message).await?
         }
         // unfriend:user_id
         value if value.starts_with("unfriend:") => {
-            unfriend_callback(&bot, &pool, value, &message).await?
+            unfriend_callback(&bot, &*repo, value, &message).await?
         }
         // confirm_unfriend:user_id
         value if value.starts_with("confirm_unfriend:") => {
-            confirm_unfriend_callback(&bot, &pool, user_id, value, &message).await?
+            confirm_unfriend_callback(&bot, &*repo, user_id, value, &message).await?
         }
         // static commands
         directories_commands::LIST_DIRECTORIES => {
-            list_directories(&bot, &pool, user_id, chat_id).await?
+            list_directories(&bot, &*repo, user_id, chat_id).await?
         }
         directories_commands::ADD_DIRECTORY => {
             add_directory_prepare(&bot, &chat_id).await?;
             dialogue.update(State::AddDirectory).await?;
         }
         directories_commands::RESET_DIRECTORIES => {
-            reset_directories(&bot, &pool, user_id, chat_id).await?
+            reset_directories(&bot, &*repo, user_id, chat_id).await?
         }
-        servers_commands::SERVER_STATS => show_stats(&bot, &pool, user_id, chat_id).await?,
-        servers_commands::RESET_SERVERS => reset_servers(&bot, &pool, user_id, chat_id).await?,
+        servers_commands::SERVER_STATS => show_stats(&bot, &*repo, user_id, chat_id).await?,
+        servers_commands::RESET_SERVERS => reset_servers(&bot, &*repo, user_id, chat_id).await?,
         servers_commands::REGISTER_SERVER => {
-            let result = register_server_prepare(&bot, &pool, user_id, chat_id).await?;
-            if result {
-                dialogue.update(State::RegisterServer).await?;
-            };
+            register_server_prepare(&bot, chat_id).await?;
+            dialogue.update(State::RegisterServer).await?;
         }
+        shared_servers_commands::SHARE => choose_server_to_share(&bot, &*repo, user_id, chat_id).await?,
+        shared_servers_commands::UN_SHARE => choose_share_to_revoke(&bot, &*repo, user_id, chat_id).await?,
+        shared_servers_commands::RESET => reset_sharing(&bot, &*repo, user_id, chat_id).await?,
         BACK_TO_SETTINGS  => {
             back_to_settings_command(&bot,  &message).await?;
         }
         _ => {}
     };
-    match data {
-        s if s.starts_with("t_status:") => {}
-        s if s.starts_with("t_remove:") => {}
-        s if s.starts_with(BACK_TO_SETTINGS) => {}
-        _ => delete_or_hide(&bot, &message).await?
-    }
-    bot.answer_callback_query(callback_query.id).await?;
     Ok(())
 }
 
@@ -6,6 +6,9 @@ table! {
         path -> Varchar,
         ordinal -> Int4,
         created_at -> Timestamptz,
+        server_id -> Nullable<Uuid>,
+        is_default -> Bool,
+        position -> Int4,
     }
 }
 
@@ -15,6 +18,8 @@ table! {
         user_id -> Int8,
         url -> Varchar,
         created_at -> Timestamptz,
+        metainfo -> Nullable<Text>,
+        server_id -> Nullable<Uuid>,
     }
 }
 
@@ -26,6 +31,13 @@ table! {
         username -> Nullable<Varchar>,
         password -> Nullable<Varchar>,
         created_at -> Timestamptz,
+        alias -> Varchar,
+        short_id -> Int4,
+        is_default -> Bool,
+        kind -> Varchar,
+        endpoint -> Nullable<Varchar>,
+        tls -> Varchar,
+        ca_cert -> Nullable<Text>,
     }
 }
 
@@ -50,6 +62,7 @@ table! {
         username -> Nullable<Varchar>,
         salt -> Varchar,
         created_at -> Timestamptz,
+        last_dir_ordinal -> Int4,
     }
 }
 
@@ -62,6 +75,23 @@ table! {
     }
 }
 
+table! {
+    dialogues (chat_id) {
+        chat_id -> Int8,
+        state -> Text,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    server_shares (id) {
+        id -> Uuid,
+        server_id -> Uuid,
+        friend_user_id -> Int8,
+        created_at -> Timestamptz,
+    }
+}
+
 joinable!(dirs -> users (user_id));
 joinable!(magnets -> users (user_id));
 joinable!(servers -> users (user_id));
@@ -69,6 +99,11 @@ joinable!(tasks -> magnets (magnet_id));
 joinable!(tasks -> servers (server_id));
 joinable!(tasks -> users (user_id));
 joinable!(friends -> users (user_id));
+joinable!(dirs -> servers (server_id));
+joinable!(server_shares -> servers (server_id));
+joinable!(server_shares -> users (friend_user_id));
 
 allow_tables_to_appear_in_same_query!(dirs, magnets, servers, tasks, users,);
 allow_tables_to_appear_in_same_query!(users, friends);
+allow_tables_to_appear_in_same_query!(server_shares, servers);
+allow_tables_to_appear_in_same_query!(server_shares, users);
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+use log::*;
+use teloxide::Bot;
+use teloxide::prelude::*;
+use transmission_rpc::types::{ErrorType, Id, Torrent, TorrentGetField};
+use uuid::Uuid;
+
+use crate::conversation::task_files::offer_task_files;
+use crate::core::magnet::MagnetLink;
+use crate::core::units::format_bytes;
+use crate::db::models::download_task::{DownloadTask, TaskStatus};
+use crate::db::models::server::Server;
+use crate::db::models::user::User;
+use crate::db::repo::Repository;
+use crate::db::repository::Pool;
+use crate::errors::BotError;
+
+const DEFAULT_INTERVAL_SECONDS: u64 = 60;
+
+/// Periodically polls the servers of all unfinished tasks
+/// and notifies the task owners when a download finishes or fails
+pub async fn watch_tasks(bot: Bot, pool: Pool) {
+    let seconds = env::var("WATCHER_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECONDS);
+    info!("Watching tasks every {} seconds", seconds);
+    let mut interval = tokio::time::interval(Duration::from_secs(seconds));
+    loop {
+        interval.tick().await;
+        if let Err(err) = check_tasks(&bot, &pool).await {
+            warn!("Unable to check tasks: {}", err);
+        }
+    }
+}
+
+async fn check_tasks(bot: &Bot, repo: &dyn Repository) -> Result<(), BotError> {
+    let mut tasks_by_server: HashMap<Uuid, Vec<DownloadTask>> = HashMap::new();
+    for task in repo.get_active_tasks().await? {
+        tasks_by_server.entry(task.server_id).or_default().push(task);
+    }
+    for (server_id, tasks) in tasks_by_server {
+        if let Err(err) = check_server_tasks(bot, repo, &server_id, tasks).await {
+            warn!("Unable to check tasks of server {}: {}", server_id, err);
+        }
+    }
+    Ok(())
+}
+
+async fn check_server_tasks(
+    bot: &Bot,
+    repo: &dyn Repository,
+    server_id: &Uuid,
+    tasks: Vec<DownloadTask>,
+) -> Result<(), BotError> {
+    let server = match repo.find_server_by_id(server_id).await? {
+        Some(server) => server,
+        None => return Ok(()),
+    };
+    let mut users: HashMap<i64, User> = HashMap::new();
+    // the task with the hash and the name of its magnet
+    let mut watched: Vec<(DownloadTask, String, String)> = vec![];
+    for task in tasks {
+        let user = match users.get(&task.user_id) {
+            Some(user) => user.clone(),
+            None => match repo.get_user(&task.user_id).await? {
+                Some(user) => {
+                    users.insert(task.user_id, user.clone());
+                    user
+                }
+                None => continue,
+            },
+        };
+        let link = repo.get_magnet_by_id(&user, task.magnet_id)
+            .await?
+            .and_then(|magnet| MagnetLink::from(&magnet.url).ok());
+        if let Some(link) = link {
+            let hash = link.clone().hash().to_lowercase();
+            watched.push((task, hash, link.dn()));
+        }
+    }
+    if watched.is_empty() {
+        return Ok(());
+    }
+
+    let ids = watched.iter().map(|(_, hash, _)| Id::Hash(hash.clone())).collect();
+    let fields = vec![
+        TorrentGetField::HashString,
+        TorrentGetField::Name,
+        TorrentGetField::PercentDone,
+        TorrentGetField::Error,
+        TorrentGetField::ErrorString,
+        TorrentGetField::MetadataPercentComplete,
+        TorrentGetField::LeftUntilDone,
+        TorrentGetField::DownloadDir,
+    ];
+    let torrents = match server.to_backend().torrent_get(Some(fields), ids).await {
+        Ok(torrents) => torrents,
+        Err(err) => {
+            debug!("Server {} is not reachable: {}", server_id, err);
+            return Ok(());
+        }
+    };
+    let torrents: HashMap<String, Torrent> = torrents
+        .into_iter()
+        .filter_map(|torrent| torrent.hash_string.clone().map(|hash| (hash.to_lowercase(), torrent)))
+        .collect();
+
+    for watched_task in watched {
+        let user = &users[&watched_task.0.user_id];
+        let task_id = watched_task.0.id;
+        // one failed notification, e.g. to a user who blocked the bot, must not stop the others
+        if let Err(err) = check_task(bot, repo, &server, user, watched_task, &torrents).await {
+            warn!("Unable to check task {}: {}", task_id, err);
+        }
+    }
+    Ok(())
+}
+
+/// Updates the task status from the torrent state, the torrents are the successful
+/// answer for all watched hashes so a missing one was removed from the server
+async fn check_task(
+    bot: &Bot,
+    repo: &dyn Repository,
+    server: &Server,
+    user: &User,
+    (task, hash, magnet_name): (DownloadTask, String, String),
+    torrents: &HashMap<String, Torrent>,
+) -> Result<(), BotError> {
+    let torrent = match torrents.get(&hash) {
+        Some(torrent) => torrent,
+        None => {
+            // removed bypassing the bot, e.g. from the web interface
+            repo.set_task_status(&task.id, TaskStatus::Removed).await?;
+            let name = if magnet_name.is_empty() { hash } else { magnet_name };
+            bot.send_message(user.clone(), format!("Download was removed from the server ❗️\n{}", name))
+                .await?;
+            return Ok(());
+        }
+    };
+    let name = torrent.name.clone().unwrap_or(hash.clone());
+    if torrent.error == Some(ErrorType::LocalError) {
+        repo.set_task_status(&task.id, TaskStatus::Error).await?;
+        let reason = torrent.error_string.clone().unwrap_or_default();
+        bot.send_message(user.clone(), format!("Download failed ❗️\n{}\n{}", name, reason))
+            .await?;
+    } else if torrent.percent_done == Some(1.0_f32) {
+        repo.set_task_status(&task.id, TaskStatus::Finished).await?;
+        bot.send_message(user.clone(), format!("Download finished ✅\n{}", name))
+            .await?;
+    } else if matches!(task.status(), TaskStatus::Created)
+        && torrent.metadata_percent_complete == Some(1.0_f32) {
+        repo.set_task_status(&task.id, TaskStatus::Started).await?;
+        // the size of a magnet is known only now
+        if let Some(warning) = check_free_space(server, torrent).await {
+            bot.send_message(user.clone(), format!("{}\n{}", name, warning)).await?;
+        }
+        offer_task_files(bot, ChatId(user.chat), server, &task.id, &hash).await?;
+    }
+    Ok(())
+}
+
+async fn check_free_space(server: &Server, torrent: &Torrent) -> Option<String> {
+    let left = torrent.left_until_done?;
+    let free_space = server.free_space(torrent.download_dir.as_ref()?).await?;
+    if left > free_space {
+        Some(format!(
+            "Not enough space ❗️ {} more needed, {} free",
+            format_bytes(left),
+            format_bytes(free_space)
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use transmission_rpc::types::TorrentAddArgs;
+
+    use crate::conversation::test_bot::{fake_bot, user};
+    use crate::conversation::tasks::start_download;
+    use crate::core::backend::fake_transmission::{FakeTransmission, FREE_SPACE};
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::server::NewServer;
+
+    use super::*;
+
+    const KEPT: &str = "magnet:?xt=urn:btih:c9e15763f722f23e98a29decdfae341b98d53056&dn=Kept";
+    const REMOVED: &str = "magnet:?xt=urn:btih:dd8255ecdc7ca55fb0bbf81323d87062db1f6d1c&dn=Removed";
+
+    #[tokio::test]
+    async fn test_removed_torrent() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = NewServer::new(1, transmission.base_url(), "Home".to_owned(), None);
+        let server = repo.add_server(&user, &server).await.unwrap();
+        for url in [KEPT, REMOVED] {
+            let magnet_id = repo.register_magnet(&user, url).await.unwrap();
+            let magnet = repo.get_magnet_by_id(&user, magnet_id).await.unwrap().unwrap();
+            repo.add_task(&user, &server.id, &magnet).await.unwrap();
+        }
+        let args = TorrentAddArgs { filename: Some(KEPT.to_owned()), ..TorrentAddArgs::default() };
+        server.to_backend().torrent_add(args).await.unwrap();
+
+        check_tasks(&bot, &repo).await.unwrap();
+        check_tasks(&bot, &repo).await.unwrap();
+
+        let tasks = repo.tasks();
+        assert!(matches!(tasks[0].status(), TaskStatus::Created));
+        assert!(matches!(tasks[1].status(), TaskStatus::Removed));
+        assert_eq!(requests.texts(), vec!["Download was removed from the server ❗️\nRemoved"]);
+    }
+
+    #[tokio::test]
+    async fn test_files_offered_with_metadata() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = NewServer::new(1, transmission.base_url(), "Home".to_owned(), None);
+        let server = repo.add_server(&user, &server).await.unwrap();
+        let magnet_id = repo.register_magnet(&user, KEPT).await.unwrap();
+        let magnet = repo.get_magnet_by_id(&user, magnet_id).await.unwrap().unwrap();
+        let task = repo.add_task(&user, &server.id, &magnet).await.unwrap();
+        let args = TorrentAddArgs { filename: Some(KEPT.to_owned()), ..TorrentAddArgs::default() };
+        server.to_backend().torrent_add(args).await.unwrap();
+
+        check_tasks(&bot, &repo).await.unwrap();
+        assert!(requests.texts().is_empty());
+
+        let hash = MagnetLink::from(&KEPT.to_owned()).unwrap().hash();
+        let file = |name: &str| serde_json::json!({"name": name, "length": 100, "bytesCompleted": 0});
+        let stat = serde_json::json!({"bytesCompleted": 0, "wanted": true, "priority": 0});
+        transmission.update_torrent(&hash, serde_json::json!({
+            "metadataPercentComplete": 1.0,
+            "files": [file("Kept/1.mkv"), file("Kept/2.mkv")],
+            "fileStats": [stat, stat],
+        }));
+        check_tasks(&bot, &repo).await.unwrap();
+
+        assert!(matches!(repo.tasks()[0].status(), TaskStatus::Started));
+        assert!(requests.texts()[0].starts_with("Kept\nSelected 2 of 2 files"));
+        assert!(requests.last_buttons().contains(&format!("t_fw:{}:0:0", task.id)));
+    }
+
+    #[tokio::test]
+    async fn test_space_checked_with_metadata() {
+        let (bot, requests) = fake_bot().await;
+        let transmission = FakeTransmission::start(None).await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = NewServer::new(1, transmission.base_url(), "Home".to_owned(), None);
+        repo.add_server(&user, &server).await.unwrap();
+        repo.add_directory(&user, "Movies", "/movies", None).await.unwrap();
+        let magnet_id = repo.register_magnet(&user, KEPT).await.unwrap();
+
+        // the size of a magnet is unknown, so it starts without any warning
+        start_download(&bot, &repo, &ChatId(1), &1, &format!("download:{}:1", magnet_id)).await.unwrap();
+        assert_eq!(requests.texts(), vec!["Downloading Kept\nto Movies"]);
+
+        let hash = MagnetLink::from(&KEPT.to_owned()).unwrap().hash();
+        let left = FREE_SPACE * 2;
+        transmission.update_torrent(&hash, serde_json::json!({"name": "Kept", "metadataPercentComplete": 1.0, "leftUntilDone": left}));
+        check_tasks(&bot, &repo).await.unwrap();
+
+        assert!(matches!(repo.tasks()[0].status(), TaskStatus::Started));
+        assert_eq!(requests.texts().pop().unwrap(), format!(
+            "Kept\nNot enough space ❗️ {} more needed, {} free",
+            format_bytes(left),
+            format_bytes(FREE_SPACE)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server() {
+        let (bot, requests) = fake_bot().await;
+        let repo = InMemoryRepository::default();
+        let user = user(&repo, 1).await;
+        let server = NewServer::new(1, "http://127.0.0.1:1".to_owned(), "Home".to_owned(), None);
+        let server = repo.add_server(&user, &server).await.unwrap();
+        let magnet_id = repo.register_magnet(&user, KEPT).await.unwrap();
+        let magnet = repo.get_magnet_by_id(&user, magnet_id).await.unwrap().unwrap();
+        repo.add_task(&user, &server.id, &magnet).await.unwrap();
+
+        check_tasks(&bot, &repo).await.unwrap();
+
+        assert!(matches!(repo.tasks()[0].status(), TaskStatus::Created));
+        assert!(requests.texts().is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use std::env;
+use std::net::SocketAddr;
+
+use teloxide::update_listeners::webhooks::Options;
+use url::Url;
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:8080";
+
+/// Webhook mode is enabled by `WEBHOOK_URL`, the public url Telegram sends updates to.
+/// `WEBHOOK_ADDRESS` is the address to listen on and `WEBHOOK_SECRET` is expected
+/// in the `X-Telegram-Bot-Api-Secret-Token` header, a random one is used if it is not set.
+/// `None` means the bot keeps using long polling
+pub fn webhook_options() -> Result<Option<Options>, String> {
+    let var = |name: &str| env::var(name).ok().filter(|value| !value.trim().is_empty());
+    parse_options(var("WEBHOOK_URL"), var("WEBHOOK_ADDRESS"), var("WEBHOOK_SECRET"))
+}
+
+fn parse_options(
+    url: Option<String>,
+    address: Option<String>,
+    secret: Option<String>,
+) -> Result<Option<Options>, String> {
+    let url = match url {
+        Some(url) => Url::parse(url.trim()).map_err(|err| format!("Invalid WEBHOOK_URL: {}", err))?,
+        None => return Ok(None),
+    };
+    let address = address.unwrap_or(DEFAULT_ADDRESS.to_owned());
+    let address = address
+        .trim()
+        .parse::<SocketAddr>()
+        .map_err(|err| format!("Invalid WEBHOOK_ADDRESS {}: {}", address, err))?;
+    let options = Options::new(address, url);
+    match secret {
+        Some(secret) if is_valid_secret(&secret) => Ok(Some(options.secret_token(secret))),
+        Some(_) => Err("WEBHOOK_SECRET should be 1-256 characters of A-Z, a-z, 0-9, _ and -".to_owned()),
+        None => Ok(Some(options)),
+    }
+}
+
+fn is_valid_secret(secret: &str) -> bool {
+    (1..=256).contains(&secret.len())
+        && secret.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+    use teloxide::update_listeners::{webhooks, AsUpdateStream};
+
+    use super::*;
+
+    const SECRET: &str = "webhook-secret";
+    const UPDATE: &str = include_str!("fixtures/webhook_update.json");
+
+    #[test]
+    fn test_options() {
+        assert!(parse_options(None, None, None).unwrap().is_none());
+
+        let options = parse_options(Some("https://bot.example.com/webhook".to_owned()), None, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.address, DEFAULT_ADDRESS.parse().unwrap());
+        assert_eq!(options.url.path(), "/webhook");
+
+        assert!(parse_options(Some("not a url".to_owned()), None, None).is_err());
+        assert!(parse_options(Some("https://bot.example.com".to_owned()), Some("localhost".to_owned()), None).is_err());
+        assert!(parse_options(Some("https://bot.example.com".to_owned()), None, Some("not secret!".to_owned())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recorded_update() {
+        let options = Options::new(
+            ([127, 0, 0, 1], 0).into(),
+            Url::parse("https://bot.example.com/webhook").unwrap(),
+        ).secret_token(SECRET.to_owned());
+        let (mut listener, _stop, router) = webhooks::axum_no_setup(options);
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+        let url = format!("http://{}/webhook", server.local_addr());
+        tokio::spawn(server);
+        let client = reqwest::Client::new();
+
+        let unauthorized = client.post(&url).body(UPDATE).send().await.unwrap();
+        assert_eq!(unauthorized.status(), 401);
+
+        let accepted = client.post(&url)
+            .header("X-Telegram-Bot-Api-Secret-Token", SECRET)
+            .body(UPDATE)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(accepted.status(), 200);
+
+        let stream = listener.as_stream();
+        futures::pin_mut!(stream);
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(update.id, 10000);
+    }
+}